@@ -6,20 +6,26 @@ use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
 use osm2streets::{osm, DebugStreets, MapConfig, StreetNetwork, Transformation};
+use streets_reader::osm_reader::Document;
 
 #[derive(Serialize, Deserialize)]
 pub struct ImportOptions {
     debug_each_step: bool,
     dual_carriageway_experiment: bool,
     cycletrack_snapping_experiment: bool,
+    turn_pocket_experiment: bool,
     inferred_sidewalks: bool,
     osm2lanes: bool,
+    check_invariants: bool,
 }
 
 #[wasm_bindgen]
 pub struct JsStreetNetwork {
     inner: StreetNetwork,
-    ways: BTreeMap<osm::WayID, streets_reader::osm_reader::Way>,
+    // The network as it was imported, before any edits
+    original: StreetNetwork,
+    doc: Document,
+    edited_tags: BTreeMap<osm::WayID, Tags>,
 }
 
 #[wasm_bindgen]
@@ -37,6 +43,7 @@ impl JsStreetNetwork {
         let mut cfg = MapConfig::default();
         cfg.inferred_sidewalks = input.inferred_sidewalks;
         cfg.osm2lanes = input.osm2lanes;
+        cfg.check_invariants = input.check_invariants;
 
         let clip_pts = None;
         let mut timer = Timer::throwaway();
@@ -49,6 +56,14 @@ impl JsStreetNetwork {
             transformations.retain(|t| !matches!(t, Transformation::CollapseShortRoads));
             transformations.push(Transformation::MergeDualCarriageways);
         }
+        if input.turn_pocket_experiment {
+            // After degenerate intersections are collapsed, but before intersection geometry
+            let idx = transformations
+                .iter()
+                .position(|t| matches!(t, Transformation::InferIntersectionControl))
+                .unwrap();
+            transformations.insert(idx, Transformation::MergeTurnPockets);
+        }
         if input.cycletrack_snapping_experiment {
             transformations.push(Transformation::SnapCycleways);
             transformations.push(Transformation::TrimDeadendCycleways);
@@ -66,18 +81,25 @@ impl JsStreetNetwork {
             // trimmed geometry.
             let mut steps = street_network.debug_steps.borrow_mut();
             for i in 0..steps.len() - 1 {
-                steps[i].streets.apply_transformations(
-                    vec![Transformation::GenerateIntersectionGeometry],
-                    &mut timer,
-                );
+                steps[i]
+                    .streets
+                    .apply_transformations_checked(
+                        vec![Transformation::GenerateIntersectionGeometry],
+                        &mut timer,
+                    )
+                    .map_err(|err| JsValue::from_str(&err.to_string()))?;
             }
         } else {
-            street_network.apply_transformations(transformations, &mut timer);
+            street_network
+                .apply_transformations_checked(transformations, &mut timer)
+                .map_err(|err| JsValue::from_str(&err.to_string()))?;
         }
 
         Ok(Self {
+            original: street_network.clone(),
             inner: street_network,
-            ways: doc.ways,
+            doc,
+            edited_tags: BTreeMap::new(),
         })
     }
     #[wasm_bindgen(js_name = toGeojsonPlain)]
@@ -128,11 +150,16 @@ impl JsStreetNetwork {
         self.inner.debug_movements_geojson().unwrap()
     }
 
+    #[wasm_bindgen(js_name = debugLaneMovementsGeojson)]
+    pub fn debug_lane_movements_geojson(&self) -> String {
+        self.inner.debug_lane_movements_geojson().unwrap()
+    }
+
     // TODO I think https://github.com/cloudflare/serde-wasm-bindgen would let us just return a
     // HashMap
     #[wasm_bindgen(js_name = getOsmTagsForWay)]
     pub fn get_osm_tags_for_way(&self, id: i64) -> String {
-        abstutil::to_json(&self.ways[&osm::WayID(id)].tags)
+        abstutil::to_json(self.way_tags(osm::WayID(id)))
     }
 
     /// Returns a GeoJSON Polygon showing a wide buffer around the way's original geometry
@@ -151,36 +178,33 @@ impl JsStreetNetwork {
 
         // Show a wide buffer around the way
         let polygon =
-            PolyLine::unchecked_new(self.ways[&id].pts.clone()).make_polygons(1.5 * width);
+            PolyLine::unchecked_new(self.doc.ways[&id].pts.clone()).make_polygons(1.5 * width);
 
         abstutil::to_json(&polygon.to_geojson(Some(&self.inner.gps_bounds)))
     }
 
-    /// Modifies all affected roads and only reruns `Transformation::GenerateIntersectionGeometry`.
+    /// Modifies all affected roads, then recalculates only the roads and intersections that depend
+    /// on them. Returns JSON with the IDs of the changed `roads` and `intersections`. Fails without
+    /// changing anything if the tags don't describe a road.
     #[wasm_bindgen(js_name = overwriteOsmTagsForWay)]
-    pub fn overwrite_osm_tags_for_way(&mut self, id: i64, tags: String) {
+    pub fn overwrite_osm_tags_for_way(&mut self, id: i64, tags: String) -> Result<String, JsValue> {
         let id = osm::WayID(id);
-        let tags: Tags = abstutil::from_json(tags.as_bytes()).unwrap();
-
-        for road in self.inner.roads.values_mut() {
-            if road.osm_ids.iter().any(|x| x.osm_way_id == id) {
-                // TODO This could panic, for example if the user removes the highway tag
-                road.lane_specs_ltr = osm2streets::get_lane_specs_ltr(&tags, &self.inner.config);
-            }
-        }
-        self.inner.apply_transformations(
-            vec![Transformation::GenerateIntersectionGeometry],
-            &mut Timer::throwaway(),
-        );
+        let tags: Tags = abstutil::from_json(tags.as_bytes())
+            .map_err(|err| JsValue::from_str(&err.to_string()))?;
 
-        self.ways.get_mut(&id).unwrap().tags = tags;
+        let changed = self
+            .inner
+            .update_osm_tags_for_way(id, &tags)
+            .map_err(|err| JsValue::from_str(&err.to_string()))?;
+        self.edited_tags.insert(id, tags);
+        Ok(abstutil::to_json(&changed))
     }
 
     /// Returns the XML string representing a way. Any OSM tags changed via
     /// `overwrite_osm_tags_for_way` are reflected.
     #[wasm_bindgen(js_name = wayToXml)]
     pub fn way_to_xml(&mut self, id: i64) -> String {
-        let way = &self.ways[&osm::WayID(id)];
+        let way = &self.doc.ways[&osm::WayID(id)];
         let mut out = format!(r#"<way id="{id}""#);
         if let Some(version) = way.version {
             out.push_str(&format!(r#" version="{version}""#));
@@ -190,13 +214,33 @@ impl JsStreetNetwork {
             out.push_str(&format!(r#"  <nd ref="{}"/>"#, node.0));
             out.push('\n');
         }
-        for (k, v) in way.tags.inner() {
+        for (k, v) in self.way_tags(osm::WayID(id)).inner() {
             out.push_str(&format!(r#"  <tag k="{k}" v="{v}"/>"#));
             out.push('\n');
         }
         out.push_str("</way>");
         out
     }
+
+    /// Returns an osmChange document with all edits made since importing, including lanes edited
+    /// through tags or directly, split ways, and moved nodes.
+    #[wasm_bindgen(js_name = toOsmChange)]
+    pub fn to_osm_change(&self) -> String {
+        streets_reader::osm_change::write_osm_change(
+            &self.original,
+            &self.inner,
+            &self.doc,
+            &self.edited_tags,
+        )
+    }
+}
+
+impl JsStreetNetwork {
+    fn way_tags(&self, id: osm::WayID) -> &Tags {
+        self.edited_tags
+            .get(&id)
+            .unwrap_or_else(|| &self.doc.ways[&id].tags)
+    }
 }
 
 #[wasm_bindgen]
@@ -216,7 +260,14 @@ impl JsDebugStreets {
     pub fn get_network(&self) -> JsValue {
         JsValue::from(JsStreetNetwork {
             inner: self.inner.streets.clone(),
-            ways: BTreeMap::new(),
+            original: self.inner.streets.clone(),
+            doc: Document {
+                gps_bounds: self.inner.streets.gps_bounds.clone(),
+                nodes: BTreeMap::new(),
+                ways: BTreeMap::new(),
+                relations: BTreeMap::new(),
+            },
+            edited_tags: BTreeMap::new(),
         })
     }
 
@@ -0,0 +1,305 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use abstutil::{deserialize_btreemap, serialize_btreemap, Tags};
+
+use crate::restrictions::split_conditional;
+use crate::time_window::time_windows_overlap;
+use crate::{LaneSpec, LaneType, Road, RoadFeatureKind, TravelMode};
+
+/// Access keys, from the most general to the most specific. More specific keys override general
+/// ones. See <https://wiki.openstreetmap.org/wiki/Key:access#Transport_mode_restrictions>.
+const ACCESS_KEYS: [&str; 9] = [
+    "access",
+    "vehicle",
+    "motor_vehicle",
+    "motorcar",
+    "hgv",
+    "psv",
+    "bus",
+    "bicycle",
+    "foot",
+];
+
+/// How one mode may use a road or lane.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccessLevel {
+    Yes,
+    /// Intended for this mode, like `bicycle=designated` on a cycle path
+    Designated,
+    /// Only to reach somewhere along the road, not to pass through
+    Destination,
+    /// Only with permission of the owner
+    Private,
+    No,
+}
+
+impl AccessLevel {
+    /// Interprets the value of an access tag. Unusual values return nothing.
+    pub fn parse(value: &str) -> Option<AccessLevel> {
+        match value.trim() {
+            "yes" | "permissive" => Some(AccessLevel::Yes),
+            "designated" | "official" => Some(AccessLevel::Designated),
+            "destination" | "delivery" | "customers" => Some(AccessLevel::Destination),
+            "private" | "permit" | "agricultural" | "forestry" => Some(AccessLevel::Private),
+            "no" | "use_sidepath" => Some(AccessLevel::No),
+            _ => None,
+        }
+    }
+
+    /// Can the mode use the road at all? Destination traffic counts; private roads don't.
+    pub fn allows(self) -> bool {
+        !matches!(self, AccessLevel::Private | AccessLevel::No)
+    }
+}
+
+/// Access explicitly tagged per mode. Modes without an entry aren't tagged, so their access
+/// depends on the type of lane.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccessRules {
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub modes: BTreeMap<TravelMode, AccessLevel>,
+    /// Access that only applies some of the time, from keys like `motor_vehicle:conditional`. The
+    /// condition is kept as written, like `Mo-Fr 07:00-19:00`.
+    pub conditional: Vec<(TravelMode, AccessLevel, String)>,
+}
+
+impl AccessRules {
+    /// Reads `access`, the mode-specific keys like `motor_vehicle` and `bicycle`, and their
+    /// `:conditional` forms.
+    pub fn new(tags: &Tags) -> AccessRules {
+        let modes = resolve(|key| tags.get(key).map(|x| x.as_str()));
+
+        let mut conditional = Vec::new();
+        for key in ACCESS_KEYS {
+            if let Some(raw) = tags.get(&format!("{key}:conditional")) {
+                for (value, condition) in split_conditional(raw) {
+                    if let Some(level) = AccessLevel::parse(&value) {
+                        for mode in modes_for_key(key) {
+                            conditional.push((mode, level, condition.clone()));
+                        }
+                    }
+                }
+            }
+        }
+
+        AccessRules { modes, conditional }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modes.is_empty() && self.conditional.is_empty()
+    }
+
+    /// The explicitly tagged access for a mode, if any.
+    pub fn get(&self, mode: TravelMode) -> Option<AccessLevel> {
+        self.modes.get(&mode).copied()
+    }
+
+    /// Like `get`, but also considers conditional access overlapping a time window, evaluated like
+    /// turn restrictions. `None` means outside of any window.
+    pub fn get_during(&self, mode: TravelMode, time: Option<&str>) -> Option<AccessLevel> {
+        let mut result = self.get(mode);
+        if let Some(time) = time {
+            for (m, level, condition) in &self.conditional {
+                if *m == mode && time_windows_overlap(condition, time) {
+                    result = Some(*level);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Applies every access key found, from general to specific.
+fn resolve<'a, F: Fn(&str) -> Option<&'a str>>(lookup: F) -> BTreeMap<TravelMode, AccessLevel> {
+    let mut modes = BTreeMap::new();
+    for key in ACCESS_KEYS {
+        if let Some(level) = lookup(key).and_then(AccessLevel::parse) {
+            for mode in modes_for_key(key) {
+                modes.insert(mode, level);
+            }
+        }
+    }
+    modes
+}
+
+fn modes_for_key(key: &str) -> Vec<TravelMode> {
+    if key == "access" {
+        vec![
+            TravelMode::Walk,
+            TravelMode::Bike,
+            TravelMode::Car,
+            TravelMode::Bus,
+            TravelMode::Hgv,
+        ]
+    } else {
+        TravelMode::from_osm_key(key)
+    }
+}
+
+/// Fills out `LaneSpec::access` from `*:lanes`, `*:lanes:forward`, `*:lanes:backward`, and
+/// `*:lanes:both_ways` tags. See `LaneSpec::indices_per_lanes_suffix` for the lanes each one
+/// describes.
+pub(crate) fn apply_lane_access(lanes: &mut [LaneSpec], tags: &Tags) {
+    for (suffix, indices) in LaneSpec::indices_per_lanes_suffix(lanes) {
+        let mut values: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for key in ACCESS_KEYS {
+            if let Some(raw) = tags.get(&format!("{key}{suffix}")) {
+                let parts: Vec<&str> = raw.split('|').collect();
+                if parts.len() == indices.len() {
+                    values.insert(key, parts);
+                } else {
+                    warn!(
+                        "{key}{suffix}={raw} has {} values, but there are {} lanes",
+                        parts.len(),
+                        indices.len()
+                    );
+                }
+            }
+        }
+        if values.is_empty() {
+            continue;
+        }
+
+        for (pos, idx) in indices.into_iter().enumerate() {
+            let modes = resolve(|key| values.get(key).map(|parts| parts[pos]));
+            lanes[idx].access.modes.extend(modes);
+        }
+    }
+}
+
+/// The modes that may use a lane of some type, when nothing else is tagged.
+fn default_modes(lt: LaneType) -> Vec<TravelMode> {
+    match lt {
+        LaneType::Driving => vec![
+            TravelMode::Bike,
+            TravelMode::Car,
+            TravelMode::Bus,
+            TravelMode::Hgv,
+        ],
+        LaneType::Bus => vec![TravelMode::Bus],
+        LaneType::Biking => vec![TravelMode::Bike],
+        LaneType::Sidewalk | LaneType::Shoulder | LaneType::Footway => vec![TravelMode::Walk],
+        LaneType::SharedUse => vec![TravelMode::Walk, TravelMode::Bike],
+        _ => Vec::new(),
+    }
+}
+
+impl Road {
+    /// Can a mode use one lane of this road? Access tagged on the lane wins. Otherwise, the road
+    /// being closed to the mode rules out the lane, and the lane's type decides. A footway
+    /// explicitly open to bicycles can also be used by them.
+    pub fn lane_allows(&self, idx: usize, mode: TravelMode) -> bool {
+        let lane = &self.lane_specs_ltr[idx];
+        if let Some(level) = lane.access.get(mode) {
+            return level.allows();
+        }
+        match self.access.get(mode) {
+            Some(level) if !level.allows() => false,
+            Some(_) if lane.lt == LaneType::Footway && mode == TravelMode::Bike => true,
+            _ => default_modes(lane.lt).contains(&mode),
+        }
+    }
+
+    /// Can a mode use any lane of this road? Bollards along the road block motor vehicles, so
+    /// roads with a modal filter in the middle don't allow them.
+    pub fn allows(&self, mode: TravelMode) -> bool {
+        if matches!(mode, TravelMode::Car | TravelMode::Bus | TravelMode::Hgv)
+            && self
+                .features
+                .iter()
+                .any(|f| f.kind == RoadFeatureKind::Bollard)
+        {
+            return false;
+        }
+        (0..self.lane_specs_ltr.len()).any(|idx| self.lane_allows(idx, mode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(input: Vec<&str>) -> Tags {
+        let mut tags = Tags::empty();
+        for kv in input {
+            let parts = kv.split_once('=').unwrap();
+            tags.insert(parts.0, parts.1);
+        }
+        tags
+    }
+
+    #[test]
+    fn test_access_hierarchy() {
+        let rules = AccessRules::new(&tags(vec!["access=no", "bicycle=designated"]));
+        assert_eq!(rules.get(TravelMode::Walk), Some(AccessLevel::No));
+        assert_eq!(rules.get(TravelMode::Bike), Some(AccessLevel::Designated));
+        assert_eq!(rules.get(TravelMode::Car), Some(AccessLevel::No));
+
+        let rules = AccessRules::new(&tags(vec!["motor_vehicle=no", "psv=yes"]));
+        assert_eq!(rules.get(TravelMode::Walk), None);
+        assert_eq!(rules.get(TravelMode::Car), Some(AccessLevel::No));
+        assert_eq!(rules.get(TravelMode::Hgv), Some(AccessLevel::No));
+        assert_eq!(rules.get(TravelMode::Bus), Some(AccessLevel::Yes));
+
+        let rules = AccessRules::new(&tags(vec![
+            "motor_vehicle:conditional=destination @ (Mo-Fr 07:00-09:00)",
+        ]));
+        assert_eq!(rules.get(TravelMode::Car), None);
+        assert_eq!(
+            rules.get_during(TravelMode::Car, Some("Mo-Fr 07:00-09:00")),
+            Some(AccessLevel::Destination)
+        );
+        assert_eq!(
+            rules.get_during(TravelMode::Bike, Some("Mo-Fr 07:00-09:00")),
+            None
+        );
+        assert_eq!(
+            rules.get_during(TravelMode::Car, Some("Tu 08:30-10:00")),
+            Some(AccessLevel::Destination)
+        );
+        assert_eq!(rules.get_during(TravelMode::Car, Some("Sa,Su")), None);
+    }
+
+    #[test]
+    fn test_lane_access() {
+        let mut lanes = LaneSpec::create_for_test("sddd", "vv^^");
+        apply_lane_access(
+            &mut lanes,
+            &tags(vec![
+                "bus:lanes:forward=|designated",
+                "motor_vehicle:lanes:forward=|no",
+                "hgv:lanes:backward=no",
+            ]),
+        );
+        assert!(lanes[0].access.is_empty());
+        assert_eq!(lanes[1].access.get(TravelMode::Hgv), Some(AccessLevel::No));
+        assert!(lanes[2].access.is_empty());
+        assert_eq!(lanes[3].access.get(TravelMode::Car), Some(AccessLevel::No));
+        assert_eq!(
+            lanes[3].access.get(TravelMode::Bus),
+            Some(AccessLevel::Designated)
+        );
+
+        // A center lane used in both directions is only described by :lanes:both_ways
+        let mut lanes = LaneSpec::create_for_test("dCd", "v-^");
+        apply_lane_access(
+            &mut lanes,
+            &tags(vec![
+                "bus:lanes:both_ways=designated",
+                "motor_vehicle:lanes:forward=no",
+            ]),
+        );
+        assert!(lanes[0].access.is_empty());
+        assert_eq!(
+            lanes[1].access.get(TravelMode::Bus),
+            Some(AccessLevel::Designated)
+        );
+        assert_eq!(lanes[1].access.get(TravelMode::Car), None);
+        assert_eq!(lanes[2].access.get(TravelMode::Car), Some(AccessLevel::No));
+    }
+}
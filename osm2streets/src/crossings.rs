@@ -0,0 +1,199 @@
+use std::collections::BTreeSet;
+
+use aabb_quadtree::QuadTree;
+use serde::{Deserialize, Serialize};
+
+use abstutil::Tags;
+use geom::Bounds;
+
+use crate::{osm, Road, RoadID, StreetNetwork};
+
+/// How a pedestrian crossing is marked or controlled. See
+/// <https://wiki.openstreetmap.org/wiki/Key:crossing>.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum CrossingKind {
+    /// Controlled by a pedestrian signal
+    Signalized,
+    /// Zebra stripes or other paint, without a signal
+    Marked,
+    /// Nothing on the road indicates the crossing
+    Unmarked,
+}
+
+impl CrossingKind {
+    /// Only separately mapped crossing ways (`highway=footway` + `footway=crossing`) produce a
+    /// kind. When the `crossing` tag is missing or unrecognized, assume the crossing is marked,
+    /// since that's why it was mapped separately in the first place.
+    pub fn from_tags(tags: &Tags) -> Option<CrossingKind> {
+        if !tags.is(osm::HIGHWAY, "footway") || !tags.is("footway", "crossing") {
+            return None;
+        }
+        Some(CrossingKind::from_crossing_tag(tags))
+    }
+
+    /// Interprets just the `crossing` tag, on either a crossing way or a `highway=crossing` node.
+    pub fn from_crossing_tag(tags: &Tags) -> CrossingKind {
+        if tags.is("crossing", "traffic_signals") {
+            CrossingKind::Signalized
+        } else if tags.is_any("crossing", vec!["unmarked", "no"]) {
+            CrossingKind::Unmarked
+        } else {
+            CrossingKind::Marked
+        }
+    }
+}
+
+/// A separately mapped pedestrian crossing, connecting sidewalks across other roads.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Crossing {
+    /// The crossing way itself
+    pub road: RoadID,
+    pub kind: CrossingKind,
+    /// Roads for vehicles that this crossing goes over. Usually the crossing way shares a node
+    /// with them, but sometimes it just passes over.
+    pub crosses: Vec<RoadID>,
+    /// Sidewalks and footways connected to either end of the crossing
+    pub sidewalks: Vec<RoadID>,
+}
+
+impl StreetNetwork {
+    /// Finds all crossings in the network. These only exist when `MapConfig::inferred_sidewalks`
+    /// is false. The roads crossed come from the last `update_crossings`.
+    pub fn crossings(&self) -> Vec<Crossing> {
+        self.roads
+            .values()
+            .filter_map(|road| {
+                road.crossing.map(|kind| Crossing {
+                    road: road.id,
+                    kind,
+                    crosses: road
+                        .crosses
+                        .iter()
+                        .filter(|r| self.roads.contains_key(r))
+                        .cloned()
+                        .collect(),
+                    sidewalks: self.sidewalks_connected_to(road),
+                })
+            })
+            .collect()
+    }
+
+    /// Finds the driveable roads that each crossing goes over, storing them in `Road::crosses`.
+    /// Crossings usually share a node with these roads, but sometimes just pass over them. This is
+    /// done after importing and after transformations, so rendering doesn't repeat it.
+    pub fn update_crossings(&mut self) {
+        if self.roads.values().all(|r| r.crossing.is_none()) {
+            return;
+        }
+
+        let mut bounds = Bounds::new();
+        for road in self.roads.values() {
+            for pt in road.reference_line.points() {
+                bounds.update(*pt);
+            }
+        }
+        let mut quadtree = QuadTree::default(bounds.as_bbox());
+        for road in self.roads.values() {
+            if road.is_driveable() {
+                quadtree.insert_with_box(road.id, road.reference_line.get_bounds().as_bbox());
+            }
+        }
+
+        let mut results = Vec::new();
+        for crossing in self.roads.values() {
+            if crossing.crossing.is_none() {
+                continue;
+            }
+            let mut crosses = BTreeSet::new();
+            for i in crossing.endpoints() {
+                for road in self.roads_per_intersection(i) {
+                    if road.is_driveable() {
+                        crosses.insert(road.id);
+                    }
+                }
+            }
+            for (id, _, _) in quadtree.query(crossing.reference_line.get_bounds().as_bbox()) {
+                let road = &self.roads[id];
+                if road.layer == crossing.layer
+                    && crossing
+                        .reference_line
+                        .intersection(&road.reference_line)
+                        .is_some()
+                {
+                    crosses.insert(road.id);
+                }
+            }
+            results.push((crossing.id, crosses.into_iter().collect()));
+        }
+        for (id, crosses) in results {
+            self.roads.get_mut(&id).unwrap().crosses = crosses;
+        }
+    }
+
+    fn sidewalks_connected_to(&self, crossing: &Road) -> Vec<RoadID> {
+        let mut results = BTreeSet::new();
+        for i in crossing.endpoints() {
+            for road in self.roads_per_intersection(i) {
+                if road.id != crossing.id
+                    && road.crossing.is_none()
+                    && road.lane_specs_ltr.iter().all(|spec| spec.lt.is_walkable())
+                {
+                    results.insert(road.id);
+                }
+            }
+        }
+        results.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crossings() {
+        // A road going east, with a crossing passing over the middle of it and another ending at
+        // its east end. A sidewalk connects the crossings' north ends. A second road to the south
+        // isn't crossed.
+        let mut streets = StreetNetwork::blank();
+        let west_i = streets.add_test_intersection(-100.0, 0.0);
+        let east_i = streets.add_test_intersection(100.0, 0.0);
+        let north1 = streets.add_test_intersection(0.0, -20.0);
+        let south1 = streets.add_test_intersection(0.0, 20.0);
+        let north2 = streets.add_test_intersection(100.0, -20.0);
+        let far_west = streets.add_test_intersection(-100.0, 50.0);
+        let far_east = streets.add_test_intersection(100.0, 50.0);
+        let road = streets.add_test_road(west_i, east_i, "dd", "v^");
+        let crossing1 = streets.add_test_road(north1, south1, "s", "^");
+        let crossing2 = streets.add_test_road(north2, east_i, "s", "^");
+        let sidewalk = streets.add_test_road(north1, north2, "s", "^");
+        streets.add_test_road(far_west, far_east, "dd", "v^");
+        for r in [crossing1, crossing2] {
+            streets.roads.get_mut(&r).unwrap().crossing = Some(CrossingKind::Marked);
+        }
+
+        streets.update_crossings();
+        assert_eq!(
+            streets.crossings(),
+            vec![
+                Crossing {
+                    road: crossing1,
+                    kind: CrossingKind::Marked,
+                    crosses: vec![road],
+                    sidewalks: vec![sidewalk],
+                },
+                Crossing {
+                    road: crossing2,
+                    kind: CrossingKind::Marked,
+                    crosses: vec![road],
+                    sidewalks: vec![sidewalk],
+                },
+            ]
+        );
+
+        // Crossings on a different layer pass over or under
+        streets.roads.get_mut(&crossing1).unwrap().layer = 1;
+        streets.update_crossings();
+        assert!(streets.roads[&crossing1].crosses.is_empty());
+    }
+}
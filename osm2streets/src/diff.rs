@@ -0,0 +1,585 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use anyhow::Result;
+use geom::{Distance, PolyLine};
+
+use crate::render::make_props;
+use crate::{
+    IntersectionControl, IntersectionID, IntersectionKind, LaneSpec, Movement, RoadID,
+    StreetNetwork,
+};
+
+/// The semantic differences between two versions of a `StreetNetwork`, like before and after an
+/// OSM edit or a change to the import pipeline. Roads and intersections are matched up by their
+/// `osm_ids`, so their IDs don't need to agree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetworkDiff {
+    /// Roads in the old network with no match in the new one
+    pub removed_roads: Vec<RoadID>,
+    /// Roads in the new network with no match in the old one
+    pub added_roads: Vec<RoadID>,
+    pub changed_roads: Vec<RoadChange>,
+    /// Intersections in the old network with no match in the new one
+    pub removed_intersections: Vec<IntersectionID>,
+    /// Intersections in the new network with no match in the old one
+    pub added_intersections: Vec<IntersectionID>,
+    pub changed_intersections: Vec<IntersectionChange>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoadChange {
+    pub before: RoadID,
+    pub after: RoadID,
+    pub changes: Vec<RoadChangeKind>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoadChangeKind {
+    Lanes {
+        before: Vec<LaneSpec>,
+        after: Vec<LaneSpec>,
+    },
+    /// The `center_line` moved by at least the threshold. This is the furthest distance between
+    /// the two lines.
+    Moved(Distance),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntersectionChange {
+    pub before: IntersectionID,
+    pub after: IntersectionID,
+    pub changes: Vec<IntersectionChangeKind>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum IntersectionChangeKind {
+    Kind {
+        before: IntersectionKind,
+        after: IntersectionKind,
+    },
+    Control {
+        before: IntersectionControl,
+        after: IntersectionControl,
+    },
+    /// Movements are expressed with road IDs from the new network. Movements involving roads that
+    /// were added or removed aren't listed; those roads are reported separately.
+    Movements {
+        added: Vec<Movement>,
+        removed: Vec<Movement>,
+    },
+    /// The center of the polygon moved by at least the threshold
+    Moved(Distance),
+}
+
+impl NetworkDiff {
+    /// Compares two networks. Geometry that moved less than `move_threshold` is ignored.
+    pub fn new(before: &StreetNetwork, after: &StreetNetwork, move_threshold: Distance) -> Self {
+        let road_matches = match_by_osm_ids(
+            before.roads.values().map(|r| (r.id, r.osm_ids.clone())),
+            after.roads.values().map(|r| (r.id, r.osm_ids.clone())),
+        );
+        let intersection_matches = match_by_osm_ids(
+            before
+                .intersections
+                .values()
+                .map(|i| (i.id, i.osm_ids.clone())),
+            after
+                .intersections
+                .values()
+                .map(|i| (i.id, i.osm_ids.clone())),
+        );
+        let matched_after_roads: BTreeSet<RoadID> = road_matches.values().cloned().collect();
+        let matched_after_intersections: BTreeSet<IntersectionID> =
+            intersection_matches.values().cloned().collect();
+
+        let mut changed_roads = Vec::new();
+        for (before_r, after_r) in &road_matches {
+            let road1 = &before.roads[before_r];
+            let road2 = &after.roads[after_r];
+            let mut changes = Vec::new();
+            if !same_lanes(&road1.lane_specs_ltr, &road2.lane_specs_ltr) {
+                changes.push(RoadChangeKind::Lanes {
+                    before: road1.lane_specs_ltr.clone(),
+                    after: road2.lane_specs_ltr.clone(),
+                });
+            }
+            let moved = max_deviation(&road1.center_line, &road2.center_line);
+            if moved >= move_threshold {
+                changes.push(RoadChangeKind::Moved(moved));
+            }
+            if !changes.is_empty() {
+                changed_roads.push(RoadChange {
+                    before: *before_r,
+                    after: *after_r,
+                    changes,
+                });
+            }
+        }
+
+        let mut changed_intersections = Vec::new();
+        for (before_i, after_i) in &intersection_matches {
+            let i1 = &before.intersections[before_i];
+            let i2 = &after.intersections[after_i];
+            let mut changes = Vec::new();
+            if i1.kind != i2.kind {
+                changes.push(IntersectionChangeKind::Kind {
+                    before: i1.kind,
+                    after: i2.kind,
+                });
+            }
+            if i1.control != i2.control {
+                changes.push(IntersectionChangeKind::Control {
+                    before: i1.control,
+                    after: i2.control,
+                });
+            }
+
+            // Only compare movements between roads that exist in both networks
+            let old_movements: BTreeSet<Movement> = i1
+                .movements
+                .iter()
+                .filter_map(|(a, b)| Some((*road_matches.get(a)?, *road_matches.get(b)?)))
+                .collect();
+            let new_movements: BTreeSet<Movement> = i2
+                .movements
+                .iter()
+                .filter(|(a, b)| matched_after_roads.contains(a) && matched_after_roads.contains(b))
+                .cloned()
+                .collect();
+            if old_movements != new_movements {
+                changes.push(IntersectionChangeKind::Movements {
+                    added: new_movements.difference(&old_movements).cloned().collect(),
+                    removed: old_movements.difference(&new_movements).cloned().collect(),
+                });
+            }
+
+            let moved = i1.polygon.center().dist_to(i2.polygon.center());
+            if moved >= move_threshold {
+                changes.push(IntersectionChangeKind::Moved(moved));
+            }
+            if !changes.is_empty() {
+                changed_intersections.push(IntersectionChange {
+                    before: *before_i,
+                    after: *after_i,
+                    changes,
+                });
+            }
+        }
+
+        Self {
+            removed_roads: before
+                .roads
+                .keys()
+                .filter(|r| !road_matches.contains_key(r))
+                .cloned()
+                .collect(),
+            added_roads: after
+                .roads
+                .keys()
+                .filter(|r| !matched_after_roads.contains(r))
+                .cloned()
+                .collect(),
+            changed_roads,
+            removed_intersections: before
+                .intersections
+                .keys()
+                .filter(|i| !intersection_matches.contains_key(i))
+                .cloned()
+                .collect(),
+            added_intersections: after
+                .intersections
+                .keys()
+                .filter(|i| !matched_after_intersections.contains(i))
+                .cloned()
+                .collect(),
+            changed_intersections,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed_roads.is_empty()
+            && self.added_roads.is_empty()
+            && self.changed_roads.is_empty()
+            && self.removed_intersections.is_empty()
+            && self.added_intersections.is_empty()
+            && self.changed_intersections.is_empty()
+    }
+
+    /// Renders everything that changed. Removed objects are drawn from the old network, and
+    /// everything else from the new one. The `change` property describes what happened.
+    pub fn to_geojson(&self, before: &StreetNetwork, after: &StreetNetwork) -> Result<String> {
+        let mut pairs = Vec::new();
+        for r in &self.removed_roads {
+            let road = &before.roads[r];
+            pairs.push((
+                road.center_line
+                    .make_polygons(road.total_width())
+                    .to_geojson(Some(&before.gps_bounds)),
+                make_props(&[
+                    ("type", "road".into()),
+                    ("id", r.0.into()),
+                    ("change", "removed".into()),
+                ]),
+            ));
+        }
+        for r in &self.added_roads {
+            let road = &after.roads[r];
+            pairs.push((
+                road.center_line
+                    .make_polygons(road.total_width())
+                    .to_geojson(Some(&after.gps_bounds)),
+                make_props(&[
+                    ("type", "road".into()),
+                    ("id", r.0.into()),
+                    ("change", "added".into()),
+                ]),
+            ));
+        }
+        for change in &self.changed_roads {
+            let road = &after.roads[&change.after];
+            pairs.push((
+                road.center_line
+                    .make_polygons(road.total_width())
+                    .to_geojson(Some(&after.gps_bounds)),
+                make_props(&[
+                    ("type", "road".into()),
+                    ("id", change.after.0.into()),
+                    ("before_id", change.before.0.into()),
+                    ("change", "changed".into()),
+                    (
+                        "details",
+                        serde_json::Value::Array(
+                            change
+                                .changes
+                                .iter()
+                                .map(|x| describe_road_change(x).into())
+                                .collect(),
+                        ),
+                    ),
+                ]),
+            ));
+        }
+
+        for i in &self.removed_intersections {
+            pairs.push((
+                before.intersections[i]
+                    .polygon
+                    .to_geojson(Some(&before.gps_bounds)),
+                make_props(&[
+                    ("type", "intersection".into()),
+                    ("id", i.0.into()),
+                    ("change", "removed".into()),
+                ]),
+            ));
+        }
+        for i in &self.added_intersections {
+            pairs.push((
+                after.intersections[i]
+                    .polygon
+                    .to_geojson(Some(&after.gps_bounds)),
+                make_props(&[
+                    ("type", "intersection".into()),
+                    ("id", i.0.into()),
+                    ("change", "added".into()),
+                ]),
+            ));
+        }
+        for change in &self.changed_intersections {
+            pairs.push((
+                after.intersections[&change.after]
+                    .polygon
+                    .to_geojson(Some(&after.gps_bounds)),
+                make_props(&[
+                    ("type", "intersection".into()),
+                    ("id", change.after.0.into()),
+                    ("before_id", change.before.0.into()),
+                    ("change", "changed".into()),
+                    (
+                        "details",
+                        serde_json::Value::Array(
+                            change
+                                .changes
+                                .iter()
+                                .map(|x| describe_intersection_change(x).into())
+                                .collect(),
+                        ),
+                    ),
+                ]),
+            ));
+        }
+
+        let obj = geom::geometries_with_properties_to_geojson(pairs);
+        let output = serde_json::to_string_pretty(&obj)?;
+        Ok(output)
+    }
+}
+
+/// One line per difference, with old IDs for removed objects and new IDs otherwise
+impl fmt::Display for NetworkDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for r in &self.removed_roads {
+            writeln!(f, "Removed {r}")?;
+        }
+        for r in &self.added_roads {
+            writeln!(f, "Added {r}")?;
+        }
+        for change in &self.changed_roads {
+            for x in &change.changes {
+                writeln!(
+                    f,
+                    "{} (was {}): {}",
+                    change.after,
+                    change.before,
+                    describe_road_change(x)
+                )?;
+            }
+        }
+        for i in &self.removed_intersections {
+            writeln!(f, "Removed {i}")?;
+        }
+        for i in &self.added_intersections {
+            writeln!(f, "Added {i}")?;
+        }
+        for change in &self.changed_intersections {
+            for x in &change.changes {
+                writeln!(
+                    f,
+                    "{} (was {}): {}",
+                    change.after,
+                    change.before,
+                    describe_intersection_change(x)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn describe_road_change(change: &RoadChangeKind) -> String {
+    match change {
+        RoadChangeKind::Lanes { before, after } => {
+            format!(
+                "lanes changed from {} to {}",
+                describe_lanes(before),
+                describe_lanes(after)
+            )
+        }
+        RoadChangeKind::Moved(dist) => format!("moved by {dist}"),
+    }
+}
+
+fn describe_intersection_change(change: &IntersectionChangeKind) -> String {
+    match change {
+        IntersectionChangeKind::Kind { before, after } => {
+            format!("kind changed from {before:?} to {after:?}")
+        }
+        IntersectionChangeKind::Control { before, after } => {
+            format!("control changed from {before:?} to {after:?}")
+        }
+        IntersectionChangeKind::Movements { added, removed } => {
+            let list = |movements: &Vec<Movement>| {
+                movements
+                    .iter()
+                    .map(|(a, b)| format!("{a} -> {b}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            format!(
+                "movements added: [{}], removed: [{}]",
+                list(added),
+                list(removed)
+            )
+        }
+        IntersectionChangeKind::Moved(dist) => format!("moved by {dist}"),
+    }
+}
+
+/// Lanes in the same compact form the lane tests use, like `sdd^vs`
+fn describe_lanes(lanes: &[LaneSpec]) -> String {
+    let types: String = lanes.iter().map(|l| l.lt.to_char()).collect();
+    let dirs: String = lanes.iter().map(|l| l.dir.to_char()).collect();
+    format!("\"{types}\" / \"{dirs}\"")
+}
+
+/// Widths are compared with a small tolerance, so rounding noise doesn't show up as a change.
+fn same_lanes(before: &[LaneSpec], after: &[LaneSpec]) -> bool {
+    before.len() == after.len()
+        && before.iter().zip(after).all(|(a, b)| {
+            a.lt == b.lt
+                && a.dir == b.dir
+                && a.turn_restrictions == b.turn_restrictions
+                && a.access == b.access
+                && (a.width.inner_meters() - b.width.inner_meters()).abs() < 0.01
+                && a.width_profile == b.width_profile
+        })
+}
+
+/// The furthest any point on one line is from the other line
+fn max_deviation(pl1: &PolyLine, pl2: &PolyLine) -> Distance {
+    let mut result = Distance::ZERO;
+    for (from, to) in [(pl1, pl2), (pl2, pl1)] {
+        for pt in from.points() {
+            result = result.max(pt.dist_to(to.project_pt(*pt)));
+        }
+    }
+    result
+}
+
+/// Pairs up objects from two networks by their OSM IDs. Each object is matched to the one sharing
+/// the most OSM IDs with it, and only mutual best matches are kept. Ties go to the lowest ID.
+fn match_by_osm_ids<ID: Copy + Ord, OsmID: Ord>(
+    before: impl Iterator<Item = (ID, Vec<OsmID>)>,
+    after: impl Iterator<Item = (ID, Vec<OsmID>)>,
+) -> BTreeMap<ID, ID> {
+    let before: Vec<(ID, Vec<OsmID>)> = before.collect();
+    let after: Vec<(ID, Vec<OsmID>)> = after.collect();
+    let best_after = best_matches(&before, &after);
+    let best_before = best_matches(&after, &before);
+    best_after
+        .into_iter()
+        .filter(|(b, a)| best_before.get(a) == Some(b))
+        .collect()
+}
+
+fn best_matches<ID: Copy + Ord, OsmID: Ord>(
+    from: &[(ID, Vec<OsmID>)],
+    to: &[(ID, Vec<OsmID>)],
+) -> BTreeMap<ID, ID> {
+    let mut by_osm_id: BTreeMap<&OsmID, Vec<ID>> = BTreeMap::new();
+    for (id, osm_ids) in to {
+        for osm_id in osm_ids {
+            by_osm_id.entry(osm_id).or_default().push(*id);
+        }
+    }
+
+    let mut results = BTreeMap::new();
+    for (id, osm_ids) in from {
+        let mut counts: BTreeMap<ID, usize> = BTreeMap::new();
+        for osm_id in osm_ids {
+            for other in by_osm_id.get(osm_id).into_iter().flatten() {
+                *counts.entry(*other).or_insert(0) += 1;
+            }
+        }
+        // max_by_key returns the last maximum; iterate in reverse to prefer the lowest ID
+        if let Some((other, _)) = counts.into_iter().rev().max_by_key(|(_, count)| *count) {
+            results.insert(*id, other);
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{osm, RestrictionConditions, RestrictionType, TurnRestriction};
+
+    #[test]
+    fn test_match_by_osm_ids() {
+        let mut ok = true;
+        for (description, before, after, expected) in vec![
+            (
+                "identical objects",
+                vec![(1, vec![10]), (2, vec![20])],
+                vec![(5, vec![10]), (6, vec![20])],
+                vec![(1, 5), (2, 6)],
+            ),
+            (
+                "an object split in half matches the half with the lowest ID",
+                vec![(1, vec![10, 11])],
+                vec![(6, vec![11]), (5, vec![10])],
+                vec![(1, 5)],
+            ),
+            (
+                "the object sharing the most OSM IDs wins",
+                vec![(1, vec![10, 11, 12])],
+                vec![(5, vec![10]), (6, vec![11, 12])],
+                vec![(1, 6)],
+            ),
+            (
+                "only mutual best matches are kept",
+                vec![(1, vec![10, 11]), (2, vec![11])],
+                vec![(5, vec![10, 11])],
+                vec![(1, 5)],
+            ),
+            (
+                "nothing in common",
+                vec![(1, vec![10])],
+                vec![(5, vec![20])],
+                vec![],
+            ),
+        ] {
+            let actual: Vec<(usize, usize)> =
+                match_by_osm_ids(before.into_iter(), after.into_iter())
+                    .into_iter()
+                    .collect();
+            if actual != expected {
+                ok = false;
+                println!("For {description}");
+                println!("Got: {actual:?}");
+                println!("Expected: {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+
+    #[test]
+    fn test_diff_movements() {
+        // A T junction, with another road added to the north afterwards
+        let mut before = StreetNetwork::blank();
+        let center = before.add_test_intersection(0.0, 0.0);
+        let west_i = before.add_test_intersection(-100.0, 0.0);
+        let east_i = before.add_test_intersection(100.0, 0.0);
+        let south_i = before.add_test_intersection(0.0, 100.0);
+        let west = before.add_test_road(west_i, center, "dd", "v^");
+        let east = before.add_test_road(center, east_i, "dd", "v^");
+        before.add_test_road(south_i, center, "dd", "v^");
+        for i in before.intersections.values_mut() {
+            i.osm_ids = vec![osm::NodeID(i.id.0 as i64)];
+        }
+
+        let mut after = before.clone();
+        let north_i = after.add_test_intersection(0.0, -100.0);
+        after.intersections.get_mut(&north_i).unwrap().osm_ids = vec![osm::NodeID(100)];
+        let north = after.add_test_road(center, north_i, "dd", "v^");
+        // The same road with a new ID
+        let mut road = after.remove_road(west);
+        road.id = after.next_road_id();
+        let new_west = road.id;
+        after.insert_road(road);
+        // Going straight is banned now
+        after
+            .roads
+            .get_mut(&new_west)
+            .unwrap()
+            .turn_restrictions
+            .push(TurnRestriction {
+                restriction: RestrictionType::BanTurns,
+                to: east,
+                conditions: RestrictionConditions::default(),
+            });
+        after.update_movements(center);
+
+        // Movements to and from the new road aren't listed, and the renamed road isn't a change
+        let diff = NetworkDiff::new(&before, &after, Distance::meters(1.0));
+        assert_eq!(
+            diff,
+            NetworkDiff {
+                removed_roads: Vec::new(),
+                added_roads: vec![north],
+                changed_roads: Vec::new(),
+                removed_intersections: Vec::new(),
+                added_intersections: vec![north_i],
+                changed_intersections: vec![IntersectionChange {
+                    before: center,
+                    after: center,
+                    changes: vec![IntersectionChangeKind::Movements {
+                        added: Vec::new(),
+                        removed: vec![(new_west, east)],
+                    }],
+                }],
+            }
+        );
+    }
+}
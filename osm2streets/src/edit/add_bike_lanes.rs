@@ -1,4 +1,4 @@
-use crate::{BufferType, Direction, DrivingSide, LaneSpec, LaneType};
+use crate::{AccessRules, BufferType, Direction, DrivingSide, LaneSpec, LaneType};
 
 impl LaneSpec {
     pub fn maybe_add_bike_lanes(
@@ -8,10 +8,19 @@ impl LaneSpec {
     ) {
         // First decompose the existing lanes back into a fwd_side and back_side. This is not quite the
         // inverse of assemble_ltr -- lanes on the OUTERMOST side of the road are first.
+        // Lanes going both ways stay on the same side as the lane to their left.
         let mut fwd_side = Vec::new();
         let mut back_side = Vec::new();
+        let mut side = if driving_side == DrivingSide::Right {
+            Direction::Back
+        } else {
+            Direction::Fwd
+        };
         for spec in lanes_ltr.drain(..) {
-            if spec.dir == Direction::Fwd {
+            if spec.dir != Direction::Both {
+                side = spec.dir;
+            }
+            if side == Direction::Fwd {
                 fwd_side.push(spec);
             } else {
                 back_side.push(spec);
@@ -82,6 +91,8 @@ impl LaneSpec {
                     dir,
                     width: LaneSpec::typical_lane_width(LaneType::Biking),
                     turn_restrictions: Vec::new(),
+                    access: AccessRules::default(),
+                    width_profile: None,
                 };
                 if let Some(buffer) = buffer_type {
                     side.insert(
@@ -91,6 +102,8 @@ impl LaneSpec {
                             dir,
                             width: LaneSpec::typical_lane_width(LaneType::Buffer(buffer)),
                             turn_restrictions: Vec::new(),
+                            access: AccessRules::default(),
+                            width_profile: None,
                         },
                     );
                 }
@@ -195,10 +208,10 @@ mod tests {
                 "https://www.openstreetmap.org/way/368670632",
                 DrivingSide::Right,
                 "sBddCddBs",
-                "vvvv^^^^^",
+                "vvvv-^^^^",
                 with_buffers,
                 "sb|BdCdB|bs",
-                "vvvvv^^^^^^",
+                "vvvvv-^^^^^",
             ),
             (
                 "Two-way without room, on a left-handed map",
@@ -1,4 +1,4 @@
-use crate::{Direction, DrivingSide, LaneSpec, LaneType};
+use crate::{AccessRules, Direction, DrivingSide, LaneSpec, LaneType};
 
 impl LaneSpec {
     /// Returns the index where the new lane was inserted
@@ -59,9 +59,19 @@ impl LaneSpec {
                 // Look for the bike lane that's missing a buffer
                 let mut fwd_bike = None;
                 let mut back_bike = None;
+                // A two-way cycletrack is treated like a bike lane on the same side of the road
+                let first_driving = lanes_ltr
+                    .iter()
+                    .position(|spec| spec.lt == LaneType::Driving)
+                    .unwrap_or(0);
                 for (idx, spec) in lanes_ltr.iter().enumerate() {
                     if spec.lt == LaneType::Biking {
-                        if spec.dir == Direction::Fwd {
+                        let fwd_side = match spec.dir {
+                            Direction::Fwd => true,
+                            Direction::Back => false,
+                            Direction::Both => idx > first_driving,
+                        };
+                        if fwd_side {
                             fwd_bike = Some(idx);
                         } else {
                             back_bike = Some(idx);
@@ -102,6 +112,8 @@ impl LaneSpec {
                 dir,
                 width: LaneSpec::typical_lane_widths(lt, highway_type)[0].0,
                 turn_restrictions: Vec::new(),
+                access: AccessRules::default(),
+                width_profile: None,
             },
         );
         idx
@@ -207,6 +219,22 @@ mod tests {
                 "sb|dds",
                 "vvvv^^",
             ),
+            (
+                "Add buffer, two-way cycletrack on the right",
+                "sddbs",
+                "vv^-^",
+                LaneType::Buffer(BufferType::Stripes),
+                "sdd|bs",
+                "vv^^-^",
+            ),
+            (
+                "Add buffer, two-way cycletrack on the left",
+                "sbdds",
+                "v-v^^",
+                LaneType::Buffer(BufferType::Stripes),
+                "sb|dds",
+                "v-vv^^",
+            ),
             (
                 "Add second buffer",
                 "sbdd|bs",
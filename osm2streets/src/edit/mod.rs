@@ -7,11 +7,11 @@ mod one_ways;
 
 use geom::Distance;
 
-use crate::{Direction, LaneSpec, LaneType};
+use crate::{AccessRules, Direction, LaneSpec, LaneType};
 
 impl LaneSpec {
     /// Transforms a string describing lane types and directions, like "spddps" and "vv^^^^^", into
-    /// a list of LaneSpecs. Useful for unit tests.
+    /// a list of LaneSpecs. `-` is a lane used in both directions. Useful for unit tests.
     pub fn create_for_test(input_lt: &str, input_dir: &str) -> Vec<LaneSpec> {
         assert_eq!(input_lt.len(), input_dir.len());
         input_lt
@@ -19,14 +19,16 @@ impl LaneSpec {
             .zip(input_dir.chars())
             .map(|(lt, dir)| LaneSpec {
                 lt: LaneType::from_char(lt),
-                dir: if dir == '^' {
-                    Direction::Fwd
-                } else {
-                    Direction::Back
+                dir: match dir {
+                    '^' => Direction::Fwd,
+                    '-' => Direction::Both,
+                    _ => Direction::Back,
                 },
                 // Dummy
                 width: Distance::ZERO,
                 turn_restrictions: Vec::new(),
+                access: AccessRules::default(),
+                width_profile: None,
             })
             .collect()
     }
@@ -43,10 +45,7 @@ impl LaneSpec {
         ok: &mut bool,
     ) {
         let actual_lt: String = actual_lanes_ltr.iter().map(|s| s.lt.to_char()).collect();
-        let actual_dir: String = actual_lanes_ltr
-            .iter()
-            .map(|s| if s.dir == Direction::Fwd { '^' } else { 'v' })
-            .collect();
+        let actual_dir: String = actual_lanes_ltr.iter().map(|s| s.dir.to_char()).collect();
 
         if actual_lt != expected_lt || actual_dir != expected_dir {
             *ok = false;
@@ -1,51 +1,24 @@
-use geom::Distance;
-
 use crate::{Direction, DrivingSide, LaneSpec, LaneType};
 
 impl LaneSpec {
     /// Convert the driving lanes of a road between one-way forwards, one-way backwards, and
-    /// bidirectional. It should be idempotent to apply this 3 times in a row. Even when an
-    /// existing one-way street is narrow, it can be made bidirectional by splitting into two very
-    /// narrow lanes.
-    pub fn toggle_road_direction(lanes_ltr: &mut Vec<LaneSpec>, driving_side: DrivingSide) {
+    /// bidirectional. It should be idempotent to apply this 3 times in a row. A one-way street
+    /// with only one driving lane becomes bidirectional by sharing that lane in both directions,
+    /// like a narrow two-way road tagged `lanes=1`. A driving lane used in both directions becomes
+    /// one-way forwards; other lanes used in both directions, like shared turn lanes, are left
+    /// alone.
+    pub fn toggle_road_direction(lanes_ltr: &mut [LaneSpec], driving_side: DrivingSide) {
         let leftmost_dir = if driving_side == DrivingSide::Right {
             Direction::Back
         } else {
             Direction::Fwd
         };
         let oneway_dir = LaneSpec::oneway_for_driving(lanes_ltr);
-        let mut num_driving_lanes = lanes_ltr
+        let num_driving_lanes = lanes_ltr
             .iter()
             .filter(|lane| lane.lt == LaneType::Driving)
             .count();
 
-        // Pre-processing: if it's one-way backwards and there's only one driving lane,
-        // split it into two narrow pieces
-        if oneway_dir == Some(Direction::Back) && num_driving_lanes == 1 {
-            // TODO If there's parking, use that
-            let idx = lanes_ltr
-                .iter()
-                .position(|x| x.lt == LaneType::Driving)
-                .unwrap();
-            lanes_ltr[idx].width *= 0.5;
-            lanes_ltr.insert(idx, lanes_ltr[idx].clone());
-            num_driving_lanes = 2;
-        }
-        // And undo the above
-        if oneway_dir == None && num_driving_lanes == 2 {
-            let idx = lanes_ltr
-                .iter()
-                .position(|x| x.lt == LaneType::Driving)
-                .unwrap();
-            // Is it super narrow?
-            // TODO Potentially brittle. SERVICE_ROAD_LANE_THICKNESS is 1.5,
-            // NORMAL_LANE_THICKNESS is 2.5. Half of either one is less than 1.5.
-            if lanes_ltr[idx].width < Distance::meters(1.5) {
-                lanes_ltr.remove(idx);
-                lanes_ltr[idx].width *= 2.0;
-            }
-        }
-
         let mut driving_lanes_so_far = 0;
         for lane in lanes_ltr {
             if lane.lt == LaneType::Driving {
@@ -57,8 +30,11 @@ impl LaneSpec {
                     }
                     Some(Direction::Back) => {
                         // If it's one-way backwards, make it bidirectional. Split the
-                        // directions down the middle
-                        if (driving_lanes_so_far as f64) / (num_driving_lanes as f64) <= 0.5 {
+                        // directions down the middle, or share a single lane
+                        if num_driving_lanes == 1 {
+                            lane.dir = Direction::Both;
+                        } else if (driving_lanes_so_far as f64) / (num_driving_lanes as f64) <= 0.5
+                        {
                             lane.dir = leftmost_dir;
                         } else {
                             lane.dir = leftmost_dir.opposite();
@@ -74,3 +50,53 @@ impl LaneSpec {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle_road_direction() {
+        let mut ok = true;
+        for (description, input_lt, input_dir, expected_dir) in vec![
+            ("Two-way becomes one-way", "sdds", "vv^^", "v^^^"),
+            ("One-way forwards is flipped", "sdds", "v^^^", "vvv^"),
+            ("One-way backwards becomes two-way", "sdds", "vvv^", "vv^^"),
+            ("A single shared lane becomes one-way", "sds", "v-^", "v^^"),
+            ("A single one-way lane becomes shared", "sds", "vv^", "v-^"),
+            (
+                "Shared turn lanes are left alone",
+                "sdCds",
+                "vv-^^",
+                "v^-^^",
+            ),
+        ] {
+            let mut lanes = LaneSpec::create_for_test(input_lt, input_dir);
+            LaneSpec::toggle_road_direction(&mut lanes, DrivingSide::Right);
+            LaneSpec::check_lanes_ltr(
+                &lanes,
+                description.to_string(),
+                input_lt,
+                input_dir,
+                input_lt,
+                expected_dir,
+                &mut ok,
+            );
+
+            // Two more toggles get back to the start
+            for _ in 0..2 {
+                LaneSpec::toggle_road_direction(&mut lanes, DrivingSide::Right);
+            }
+            LaneSpec::check_lanes_ltr(
+                &lanes,
+                format!("{description}, toggled 3 times"),
+                input_lt,
+                input_dir,
+                input_lt,
+                input_dir,
+                &mut ok,
+            );
+        }
+        assert!(ok);
+    }
+}
@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+
+use abstutil::Tags;
+use geom::{Distance, Pt2D};
+
+use crate::{osm, CrossingKind, Direction, Road};
+
+/// Something mapped as an OSM node somewhere along a road, affecting traffic there.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoadFeature {
+    pub kind: RoadFeatureKind,
+    pub osm_node: osm::NodeID,
+    /// The original position of the node. Features are re-projected from this when the road's
+    /// geometry changes.
+    pub pt: Pt2D,
+    /// Distance along the road's `reference_line`
+    pub dist: Distance,
+    /// The direction of travel along the road this applies to, if it's tagged. None means both.
+    pub direction: Option<Direction>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoadFeatureKind {
+    /// A `highway=crossing` node
+    Crossing(CrossingKind),
+    Bollard,
+    Gate,
+    /// The value of the `traffic_calming` tag, like `bump` or `table`
+    TrafficCalming(String),
+    /// `all_way` is set by `stop=all`
+    StopSign {
+        all_way: bool,
+    },
+    GiveWay,
+}
+
+impl RoadFeatureKind {
+    /// Finds all features described by one node's tags. A raised crossing might be both a crossing
+    /// and traffic calming, for example.
+    pub fn from_tags(tags: &Tags) -> Vec<RoadFeatureKind> {
+        let mut results = Vec::new();
+        if tags.is(osm::HIGHWAY, "crossing") {
+            results.push(RoadFeatureKind::Crossing(CrossingKind::from_crossing_tag(
+                tags,
+            )));
+        }
+        if tags.is("barrier", "bollard") {
+            results.push(RoadFeatureKind::Bollard);
+        }
+        if tags.is("barrier", "gate") {
+            results.push(RoadFeatureKind::Gate);
+        }
+        if let Some(value) = tags.get("traffic_calming") {
+            if value != "no" {
+                results.push(RoadFeatureKind::TrafficCalming(value.clone()));
+            }
+        }
+        if tags.is(osm::HIGHWAY, "stop") {
+            results.push(RoadFeatureKind::StopSign {
+                all_way: tags.is("stop", "all"),
+            });
+        }
+        if tags.is(osm::HIGHWAY, "give_way") {
+            results.push(RoadFeatureKind::GiveWay);
+        }
+        results
+    }
+
+    /// Is this a sign controlling traffic at the next intersection?
+    pub fn is_traffic_sign(&self) -> bool {
+        matches!(
+            self,
+            RoadFeatureKind::StopSign { .. } | RoadFeatureKind::GiveWay
+        )
+    }
+
+    /// A short description, used for rendering
+    pub fn label(&self) -> &'static str {
+        match self {
+            RoadFeatureKind::Crossing(_) => "crossing",
+            RoadFeatureKind::Bollard => "bollard",
+            RoadFeatureKind::Gate => "gate",
+            RoadFeatureKind::TrafficCalming(_) => "traffic calming",
+            RoadFeatureKind::StopSign { .. } => "stop sign",
+            RoadFeatureKind::GiveWay => "give way",
+        }
+    }
+}
+
+/// Interprets the `direction` tag of a node on a way, relative to that way. See
+/// <https://wiki.openstreetmap.org/wiki/Key:direction#Tagging_for_stop_signs_and_give_way>.
+pub fn feature_direction(tags: &Tags) -> Option<Direction> {
+    match tags.get("direction").map(|x| x.as_str()) {
+        Some("forward") => Some(Direction::Fwd),
+        Some("backward") => Some(Direction::Back),
+        _ => None,
+    }
+}
+
+impl Road {
+    pub fn add_feature(
+        &mut self,
+        kind: RoadFeatureKind,
+        osm_node: osm::NodeID,
+        pt: Pt2D,
+        direction: Option<Direction>,
+    ) {
+        self.features.push(RoadFeature {
+            kind,
+            osm_node,
+            pt,
+            dist: Distance::ZERO,
+            direction,
+        });
+        self.update_features();
+    }
+
+    /// Recalculates the distance of each feature along the `reference_line`. Call this after
+    /// changing the road's geometry.
+    pub fn update_features(&mut self) {
+        for feature in &mut self.features {
+            feature.dist = self
+                .reference_line
+                .dist_along_of_point(self.reference_line.project_pt(feature.pt))
+                .map(|(dist, _)| dist)
+                .unwrap_or(Distance::ZERO);
+        }
+        self.features.sort_by_key(|f| f.dist);
+    }
+
+    /// Takes features from a road that's being merged into this one. If the other road points the
+    /// opposite way, their directions get flipped. `update_features` needs to be called after.
+    pub(crate) fn take_features(&mut self, mut features: Vec<RoadFeature>, reversed: bool) {
+        if reversed {
+            for feature in &mut features {
+                feature.direction = feature.direction.map(|dir| dir.opposite());
+            }
+        }
+        self.features.extend(features);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StreetNetwork;
+
+    fn tags(input: Vec<&str>) -> Tags {
+        let mut tags = Tags::empty();
+        for kv in input {
+            let parts = kv.split_once('=').unwrap();
+            tags.insert(parts.0, parts.1);
+        }
+        tags
+    }
+
+    /// The kind and distance along the road of every feature, rounded to the meter
+    fn summarize(road: &Road) -> Vec<(&'static str, f64)> {
+        road.features
+            .iter()
+            .map(|f| (f.kind.label(), f.dist.inner_meters().round()))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_tags() {
+        let mut ok = true;
+        for (input, expected) in vec![
+            (vec!["highway=traffic_signals"], vec![]),
+            (
+                vec!["highway=crossing", "crossing=traffic_signals"],
+                vec![RoadFeatureKind::Crossing(CrossingKind::Signalized)],
+            ),
+            (
+                vec!["highway=crossing", "crossing=unmarked"],
+                vec![RoadFeatureKind::Crossing(CrossingKind::Unmarked)],
+            ),
+            (
+                vec!["highway=crossing", "traffic_calming=table"],
+                vec![
+                    RoadFeatureKind::Crossing(CrossingKind::Marked),
+                    RoadFeatureKind::TrafficCalming("table".to_string()),
+                ],
+            ),
+            (vec!["barrier=bollard"], vec![RoadFeatureKind::Bollard]),
+            (vec!["barrier=gate"], vec![RoadFeatureKind::Gate]),
+            (vec!["traffic_calming=no"], vec![]),
+            (
+                vec!["highway=stop"],
+                vec![RoadFeatureKind::StopSign { all_way: false }],
+            ),
+            (
+                vec!["highway=stop", "stop=all"],
+                vec![RoadFeatureKind::StopSign { all_way: true }],
+            ),
+            (vec!["highway=give_way"], vec![RoadFeatureKind::GiveWay]),
+        ] {
+            let actual = RoadFeatureKind::from_tags(&tags(input.clone()));
+            if actual != expected {
+                ok = false;
+                println!("For {input:?}");
+                println!("Got: {actual:?}");
+                println!("Expected: {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+
+    #[test]
+    fn test_update_features() {
+        let mut streets = StreetNetwork::blank();
+        let i1 = streets.add_test_intersection(0.0, 0.0);
+        let i2 = streets.add_test_intersection(100.0, 0.0);
+        let r = streets.add_test_road(i1, i2, "dd", "v^");
+        let road = streets.roads.get_mut(&r).unwrap();
+        // Added out of order, but they're sorted by distance
+        road.add_feature(
+            RoadFeatureKind::Bollard,
+            osm::NodeID(1),
+            Pt2D::new(70.0, 0.0),
+            None,
+        );
+        road.add_feature(
+            RoadFeatureKind::GiveWay,
+            osm::NodeID(2),
+            Pt2D::new(30.0, 1.0),
+            Some(Direction::Fwd),
+        );
+        assert_eq!(summarize(road), vec![("give way", 30.0), ("bollard", 70.0)]);
+
+        // After trimming the start of the road, features are measured from the new start. The
+        // ones no longer alongside the road stick to its end.
+        let mut trimmed = road.clone();
+        trimmed.reference_line = road
+            .reference_line
+            .exact_slice(Distance::meters(40.0), road.reference_line.length());
+        trimmed.update_features();
+        assert_eq!(
+            summarize(&trimmed),
+            vec![("give way", 0.0), ("bollard", 30.0)]
+        );
+
+        // After splitting, each piece keeps the features along it
+        let (_, new_r, _) = streets.split_road(r, Distance::meters(50.0)).unwrap();
+        assert_eq!(summarize(&streets.roads[&r]), vec![("give way", 30.0)]);
+        assert_eq!(summarize(&streets.roads[&new_r]), vec![("bollard", 20.0)]);
+    }
+
+    #[test]
+    fn test_take_features() {
+        let feature = |osm_node, direction| RoadFeature {
+            kind: RoadFeatureKind::StopSign { all_way: false },
+            osm_node: osm::NodeID(osm_node),
+            pt: Pt2D::new(0.0, 0.0),
+            dist: Distance::ZERO,
+            direction,
+        };
+        let features = vec![
+            feature(1, Some(Direction::Fwd)),
+            feature(2, Some(Direction::Back)),
+            feature(3, None),
+        ];
+
+        let mut streets = StreetNetwork::blank();
+        let i1 = streets.add_test_intersection(0.0, 0.0);
+        let i2 = streets.add_test_intersection(100.0, 0.0);
+        let r = streets.add_test_road(i1, i2, "dd", "v^");
+        let mut road = streets.roads.remove(&r).unwrap();
+
+        let directions = |road: &Road| -> Vec<Option<Direction>> {
+            road.features.iter().map(|f| f.direction).collect()
+        };
+        let mut same_way = road.clone();
+        same_way.take_features(features.clone(), false);
+        assert_eq!(
+            directions(&same_way),
+            vec![Some(Direction::Fwd), Some(Direction::Back), None]
+        );
+        road.take_features(features, true);
+        assert_eq!(
+            directions(&road),
+            vec![Some(Direction::Back), Some(Direction::Fwd), None]
+        );
+    }
+}
@@ -63,17 +63,29 @@ impl InputRoad {
                 dir: crate::Direction::Fwd,
                 width: self.total_width,
                 turn_restrictions: Vec::new(),
+                access: crate::AccessRules::default(),
+                width_profile: None,
             }],
+            lane_provenance: crate::LaneProvenance::default(),
             // Mostly dummy values, except for what selfEdge::calculate needs
             osm_ids: Vec::new(),
             highway_type: String::new(),
             name: None,
             internal_junction_road: false,
             layer: 0,
+            crossing: None,
+            crosses: Vec::new(),
+            access: crate::AccessRules::default(),
+            speed_limits: crate::SpeedLimits {
+                fwd: crate::SpeedLimit::default_for("", crate::SpeedUnit::KmPerHour),
+                back: crate::SpeedLimit::default_for("", crate::SpeedUnit::KmPerHour),
+            },
             reference_line: PolyLine::dummy(),
             reference_line_placement: crate::lanes::Placement::Transition,
+            way_fractions: (0.0, 1.0),
             turn_restrictions: Vec::new(),
             complicated_turn_restrictions: Vec::new(),
+            features: Vec::new(),
         }
     }
 }
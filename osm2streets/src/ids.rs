@@ -6,7 +6,8 @@ use serde::{Deserialize, Serialize};
 use crate::osm::{NodeID, WayID};
 use crate::Road;
 
-/// Opaque and non-contiguous
+/// Opaque and non-contiguous. When `MapConfig::stable_ids` is set, IDs of roads created from OSM
+/// are derived from their `OriginalRoad`, so they're the same across imports.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct RoadID(
     #[serde(
@@ -22,7 +23,8 @@ impl fmt::Display for RoadID {
     }
 }
 
-/// Opaque and non-contiguous
+/// Opaque and non-contiguous. When `MapConfig::stable_ids` is set, IDs of intersections created
+/// from OSM are derived from their `osm::NodeID`s, so they're the same across imports.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct IntersectionID(
     #[serde(
@@ -72,6 +74,31 @@ impl OriginalRoad {
     }
 }
 
+/// Hashes OSM IDs into a value that doesn't depend on the platform, the Rust version, or the order
+/// things were imported in. This is 32-bit FNV-1a, so the result fits in a `usize` everywhere,
+/// including WebAssembly.
+///
+/// 32 bits is small for big areas. Among n objects, some pair is likely to collide once n reaches
+/// about 77,000, and a metro area has several times that many roads. IDs are still unique, because
+/// `next_stable_id` probes past collisions, but the probed IDs depend on which object claimed the
+/// hash first. So a small fraction of IDs in a large import may change when the input changes.
+pub(crate) fn stable_hash(values: &[i64]) -> usize {
+    let mut hash: u32 = 0x811c9dc5;
+    for value in values {
+        for byte in value.to_le_bytes() {
+            hash ^= byte as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+    }
+    hash as usize
+}
+
+/// The next candidate when a stable ID is already taken. Collisions are rare, but they need to be
+/// resolved the same way every time.
+pub(crate) fn next_stable_id(id: usize) -> usize {
+    (id as u32).wrapping_add(1) as usize
+}
+
 /// It's sometimes useful to track both a road's ID and endpoints together. Use this sparingly.
 #[derive(Clone)]
 pub struct RoadWithEndpoints {
@@ -1,9 +1,14 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
+use abstutil::{deserialize_btreemap, serialize_btreemap};
 use geom::{Polygon, Pt2D};
 use serde::{Deserialize, Serialize};
 
-use crate::{osm, DrivingSide, IntersectionID, RoadID, StreetNetwork};
+use crate::ids::{next_stable_id, stable_hash};
+use crate::{
+    osm, DrivingSide, IntersectionID, LaneMovement, OriginalRoad, RoadID, StreetNetwork,
+    TrafficInterruption, TravelMode,
+};
 use TrafficConflict::*;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -28,10 +33,27 @@ pub struct Intersection {
     /// intersection. They're ordered clockwise around the intersection.
     pub roads: Vec<RoadID>,
     pub movements: Vec<Movement>,
+    /// The lane-level refinement of `movements`
+    pub lane_movements: Vec<LaneMovement>,
+
+    /// Roads with a traffic signal controlling traffic entering this intersection, when the
+    /// signals were mapped on the approaching ways. Empty when the signal is mapped on the
+    /// intersection itself, which means every approach is signalized.
+    pub signalized_approaches: BTreeSet<RoadID>,
+    /// How traffic approaching from each road is controlled. Only filled out by
+    /// `Transformation::InferIntersectionControl`.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub approach_controls: BTreeMap<RoadID, TrafficInterruption>,
 
     // true if src_i matches this intersection (or the deleted/consolidated one, whatever)
     // TODO Store start/end trim distance on _every_ road
     pub trim_roads_for_merging: BTreeMap<(RoadID, bool), Pt2D>,
+
+    /// The original OSM way segments of short roads collapsed into this intersection
+    pub collapsed_osm_roads: Vec<OriginalRoad>,
 }
 
 /// How two lanes of travel conflict with each other.
@@ -91,13 +113,65 @@ impl Intersection {
     pub fn is_map_edge(&self) -> bool {
         self.kind == IntersectionKind::MapEdge
     }
+
+    /// Returns the roads whose traffic entering this intersection is controlled by a signal,
+    /// interpreting an empty `signalized_approaches` as every approach.
+    pub fn signalized_roads(&self, streets: &StreetNetwork) -> Vec<RoadID> {
+        if self.control != IntersectionControl::Signalled {
+            return Vec::new();
+        }
+        if !self.signalized_approaches.is_empty() {
+            return self.signalized_approaches.iter().cloned().collect();
+        }
+        self.roads
+            .iter()
+            .filter(|r| streets.roads[r].can_drive_out_of_end(self.id))
+            .cloned()
+            .collect()
+    }
 }
 
 impl StreetNetwork {
     pub fn next_intersection_id(&mut self) -> IntersectionID {
-        let id = IntersectionID(self.intersection_id_counter);
-        self.intersection_id_counter += 1;
-        id
+        loop {
+            let id = IntersectionID(self.intersection_id_counter);
+            self.intersection_id_counter += 1;
+            // With stable IDs, the counter might run into a hashed ID
+            if !self.intersections.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Picks the ID for a new intersection based on some OSM nodes. When
+    /// `MapConfig::stable_ids` is set, this is a hash of the nodes. Otherwise, IDs are assigned in
+    /// order.
+    pub fn new_intersection_id(&mut self, osm_ids: &[osm::NodeID]) -> IntersectionID {
+        self.new_intersection_id_for_road(osm_ids, &[])
+    }
+
+    /// Like `new_intersection_id`, but for one copy of an intersection that's split up per road,
+    /// like at the edge of the map. Every copy keeps the same OSM nodes, so with stable IDs, the
+    /// road's OSM segments are hashed too. That way each copy gets the same ID every time, no
+    /// matter what order the copies are made in.
+    pub fn new_intersection_id_for_road(
+        &mut self,
+        osm_ids: &[osm::NodeID],
+        road: &[OriginalRoad],
+    ) -> IntersectionID {
+        if !self.config.stable_ids || osm_ids.is_empty() {
+            return self.next_intersection_id();
+        }
+        let mut values: Vec<i64> = osm_ids.iter().map(|x| x.0).collect();
+        values.sort();
+        for id in road {
+            values.extend([id.osm_way_id.0, id.i1.0, id.i2.0]);
+        }
+        let mut id = stable_hash(&values);
+        while self.intersections.contains_key(&IntersectionID(id)) {
+            id = next_stable_id(id);
+        }
+        IntersectionID(id)
     }
 
     /// This creates a new intersection based on one or more real OSM nodes, assigning an ID and
@@ -109,7 +183,7 @@ impl StreetNetwork {
         t: IntersectionKind,
         control: IntersectionControl,
     ) -> IntersectionID {
-        let id = self.next_intersection_id();
+        let id = self.new_intersection_id(&osm_ids);
         self.intersections.insert(
             id,
             Intersection {
@@ -122,7 +196,11 @@ impl StreetNetwork {
                 // Filled out later
                 roads: Vec::new(),
                 movements: Vec::new(),
+                lane_movements: Vec::new(),
+                signalized_approaches: BTreeSet::new(),
+                approach_controls: BTreeMap::new(),
                 trim_roads_for_merging: BTreeMap::new(),
+                collapsed_osm_roads: Vec::new(),
             },
         );
         id
@@ -132,9 +210,15 @@ impl StreetNetwork {
     //
     // TODO This doesn't handle trim_roads_for_merging
     pub fn sort_roads(&mut self, i: IntersectionID) {
-        let intersection = self.intersections.get_mut(&i).unwrap();
+        let sorted = self.clockwise_roads(i);
+        self.intersections.get_mut(&i).unwrap().roads = sorted;
+    }
+
+    /// Calculates the clockwise order of an intersection's roads, without changing anything.
+    pub(crate) fn clockwise_roads(&self, i: IntersectionID) -> Vec<RoadID> {
+        let intersection = &self.intersections[&i];
         if intersection.roads.len() < 2 {
-            return; // Already sorted.
+            return intersection.roads.clone(); // Already sorted.
         }
 
         // (ID, polyline pointing to the intersection, sorting point that's filled out later)
@@ -182,7 +266,7 @@ impl StreetNetwork {
                 .normalized_degrees() as i64
         });
 
-        intersection.roads = road_centers.into_iter().map(|(r, _, _)| r).collect();
+        road_centers.into_iter().map(|(r, _, _)| r).collect()
     }
 
     /// Updates the derived properties of an intersection.
@@ -194,19 +278,37 @@ impl StreetNetwork {
         }
 
         let (movements, kind) = self.calculate_movements_and_kind(i);
+        let lane_movements = self.calculate_lane_movements(i, &movements);
         let intersection = self.intersections.get_mut(&i).unwrap();
         intersection.movements = movements;
+        intersection.lane_movements = lane_movements;
         intersection.kind = kind;
     }
 
     pub fn calculate_movements_and_kind(
         &self,
         i: IntersectionID,
+    ) -> (Vec<Movement>, IntersectionKind) {
+        self.calculate_movements_and_kind_for(i, TravelMode::Car, None)
+    }
+
+    /// Like `calculate_movements_and_kind`, but only considers turn restrictions that apply to one
+    /// mode during one time window. Movements are still only calculated between driveable roads,
+    /// and if `MapConfig::movements_respect_access` is set, only roads the mode may use.
+    /// A time window of `None` means outside of any time-conditional restriction; see
+    /// `restriction_time_windows` for the windows that matter at an intersection.
+    pub fn calculate_movements_and_kind_for(
+        &self,
+        i: IntersectionID,
+        mode: TravelMode,
+        time: Option<&str>,
     ) -> (Vec<Movement>, IntersectionKind) {
         let roads: Vec<_> = self
             .roads_per_intersection(i)
             .into_iter()
-            .filter(|road| road.is_driveable())
+            .filter(|road| {
+                road.is_driveable() && (!self.config.movements_respect_access || road.allows(mode))
+            })
             .collect();
 
         // A terminus is characterised by a single connected road.
@@ -244,7 +346,7 @@ impl StreetNetwork {
                 // the angle between them is small enough.
 
                 // Check for any turn restrictions.
-                if src_road.allowed_to_turn_to(dst_road.id) {
+                if src_road.allowed_to_turn_to_for(dst_road.id, mode, time) {
                     connections.push((s, d));
                 }
             }
@@ -282,6 +384,21 @@ impl StreetNetwork {
             },
         )
     }
+
+    /// Returns all the distinct time windows of simple and complicated turn restrictions from roads
+    /// at an intersection.
+    pub fn restriction_time_windows(&self, i: IntersectionID) -> BTreeSet<String> {
+        let mut windows = BTreeSet::new();
+        for road in self.roads_per_intersection(i) {
+            for tr in &road.turn_restrictions {
+                windows.extend(tr.conditions.time.clone());
+            }
+            for ctr in &road.complicated_turn_restrictions {
+                windows.extend(ctr.conditions.time.clone());
+            }
+        }
+        windows
+    }
 }
 
 /// Calculate how two turns through an intersection conflict. Turns are identified by the clockwise
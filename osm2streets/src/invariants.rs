@@ -0,0 +1,361 @@
+use std::fmt;
+
+use crate::{DanglingTurnRestriction, IntersectionID, RoadID, StreetNetwork};
+
+/// Something wrong with a `StreetNetwork` that transformations assume can't happen.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvariantError {
+    /// A road's `src_i` or `dst_i` doesn't exist
+    MissingIntersection {
+        road: RoadID,
+        intersection: IntersectionID,
+    },
+    /// A road starts and ends at the same intersection
+    SelfLoop(RoadID),
+    /// A road's endpoint doesn't list the road
+    RoadNotListed {
+        road: RoadID,
+        intersection: IntersectionID,
+    },
+    /// An intersection lists a road that doesn't exist or doesn't end there
+    UnknownRoad {
+        intersection: IntersectionID,
+        road: RoadID,
+    },
+    /// An intersection lists the same road more than once
+    DuplicateRoad {
+        intersection: IntersectionID,
+        road: RoadID,
+    },
+    /// An intersection's roads aren't ordered clockwise
+    NotClockwise {
+        intersection: IntersectionID,
+        actual: Vec<RoadID>,
+        expected: Vec<RoadID>,
+    },
+    /// A `trim_roads_for_merging` key refers to a missing road, or the road's endpoint on that side
+    /// isn't this intersection
+    InvalidTrimKey {
+        intersection: IntersectionID,
+        road: RoadID,
+        src_i: bool,
+    },
+    DanglingTurnRestriction(DanglingTurnRestriction),
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantError::MissingIntersection { road, intersection } => {
+                write!(f, "{road} points to {intersection}, which doesn't exist")
+            }
+            InvariantError::SelfLoop(road) => write!(f, "{road} is a self-loop"),
+            InvariantError::RoadNotListed { road, intersection } => {
+                write!(f, "{road} ends at {intersection}, but isn't listed there")
+            }
+            InvariantError::UnknownRoad { intersection, road } => write!(
+                f,
+                "{intersection} lists {road}, which doesn't exist or doesn't end there"
+            ),
+            InvariantError::DuplicateRoad { intersection, road } => {
+                write!(f, "{intersection} lists {road} more than once")
+            }
+            InvariantError::NotClockwise {
+                intersection,
+                actual,
+                expected,
+            } => write!(
+                f,
+                "{intersection} has roads {:?}, but the clockwise order is {:?}",
+                actual, expected
+            ),
+            InvariantError::InvalidTrimKey {
+                intersection,
+                road,
+                src_i,
+            } => write!(
+                f,
+                "{intersection} has a trim_roads_for_merging key for {road} (src_i = {src_i}), \
+                 but that endpoint isn't the intersection"
+            ),
+            InvariantError::DanglingTurnRestriction(dangling) => write!(
+                f,
+                "{} has a turn restriction through {:?}, which is broken: {:?}",
+                dangling.from, dangling.sequence, dangling.problem
+            ),
+        }
+    }
+}
+
+impl StreetNetwork {
+    /// Checks everything that transformations assume about the structure of the network, returning
+    /// every problem found. An empty list means the network is consistent.
+    pub fn check_invariants(&self) -> Vec<InvariantError> {
+        let mut errors = Vec::new();
+
+        for road in self.roads.values() {
+            if road.src_i == road.dst_i {
+                errors.push(InvariantError::SelfLoop(road.id));
+            }
+            for i in road.endpoints() {
+                match self.intersections.get(&i) {
+                    Some(intersection) => {
+                        if !intersection.roads.contains(&road.id) {
+                            errors.push(InvariantError::RoadNotListed {
+                                road: road.id,
+                                intersection: i,
+                            });
+                        }
+                    }
+                    None => {
+                        errors.push(InvariantError::MissingIntersection {
+                            road: road.id,
+                            intersection: i,
+                        });
+                    }
+                }
+            }
+        }
+
+        for intersection in self.intersections.values() {
+            let mut roads_ok = true;
+            for (idx, r) in intersection.roads.iter().enumerate() {
+                if intersection.roads[..idx].contains(r) {
+                    roads_ok = false;
+                    errors.push(InvariantError::DuplicateRoad {
+                        intersection: intersection.id,
+                        road: *r,
+                    });
+                }
+                if !self
+                    .roads
+                    .get(r)
+                    .map(|road| road.src_i == intersection.id || road.dst_i == intersection.id)
+                    .unwrap_or(false)
+                {
+                    roads_ok = false;
+                    errors.push(InvariantError::UnknownRoad {
+                        intersection: intersection.id,
+                        road: *r,
+                    });
+                }
+            }
+
+            // Sorting only makes sense once the list of roads is sane
+            if roads_ok {
+                let expected = self.clockwise_roads(intersection.id);
+                if !is_rotation(&intersection.roads, &expected) {
+                    errors.push(InvariantError::NotClockwise {
+                        intersection: intersection.id,
+                        actual: intersection.roads.clone(),
+                        expected,
+                    });
+                }
+            }
+
+            for (r, src_i) in intersection.trim_roads_for_merging.keys() {
+                let valid = self
+                    .roads
+                    .get(r)
+                    .map(|road| {
+                        if *src_i {
+                            road.src_i == intersection.id
+                        } else {
+                            road.dst_i == intersection.id
+                        }
+                    })
+                    .unwrap_or(false);
+                if !valid {
+                    errors.push(InvariantError::InvalidTrimKey {
+                        intersection: intersection.id,
+                        road: *r,
+                        src_i: *src_i,
+                    });
+                }
+            }
+        }
+
+        errors.extend(
+            self.find_dangling_turn_restrictions()
+                .into_iter()
+                .map(InvariantError::DanglingTurnRestriction),
+        );
+
+        errors
+    }
+}
+
+/// The clockwise order has no particular starting road, so any rotation of it is fine.
+fn is_rotation(actual: &[RoadID], expected: &[RoadID]) -> bool {
+    if actual.len() != expected.len() {
+        return false;
+    }
+    if actual.is_empty() {
+        return true;
+    }
+    (0..expected.len()).any(|offset| {
+        actual
+            .iter()
+            .enumerate()
+            .all(|(idx, r)| *r == expected[(idx + offset) % expected.len()])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use abstutil::Timer;
+    use geom::Pt2D;
+
+    use super::*;
+    use crate::{DanglingProblem, RestrictionConditions, RestrictionType, TurnRestriction};
+
+    #[test]
+    fn test_check_invariants() {
+        // Three roads leaving one intersection
+        let mut streets = StreetNetwork::blank();
+        let i0 = streets.add_test_intersection(0.0, 0.0);
+        let i1 = streets.add_test_intersection(100.0, 0.0);
+        let i2 = streets.add_test_intersection(0.0, 100.0);
+        let i3 = streets.add_test_intersection(-100.0, 0.0);
+        let a = streets.add_test_road(i0, i1, "dd", "v^");
+        streets.add_test_road(i0, i2, "dd", "v^");
+        streets.add_test_road(i0, i3, "dd", "v^");
+        assert_eq!(streets.check_invariants(), Vec::new());
+
+        let missing_i = IntersectionID(99);
+        let missing_r = RoadID(99);
+        let clockwise = streets.intersections[&i0].roads.clone();
+        let mut reversed = clockwise.clone();
+        reversed.reverse();
+
+        let mut ok = true;
+        let cases: Vec<(&str, Box<dyn Fn(&mut StreetNetwork) + '_>, InvariantError)> =
+            vec![
+                (
+                    "road points to a missing intersection",
+                    Box::new(|streets: &mut StreetNetwork| {
+                        streets.roads.get_mut(&a).unwrap().dst_i = missing_i;
+                    }),
+                    InvariantError::MissingIntersection {
+                        road: a,
+                        intersection: missing_i,
+                    },
+                ),
+                (
+                    "self-loop",
+                    Box::new(|streets: &mut StreetNetwork| {
+                        streets.roads.get_mut(&a).unwrap().dst_i = i0;
+                    }),
+                    InvariantError::SelfLoop(a),
+                ),
+                (
+                    "road missing from its endpoint",
+                    Box::new(|streets: &mut StreetNetwork| {
+                        streets
+                            .intersections
+                            .get_mut(&i1)
+                            .unwrap()
+                            .roads
+                            .retain(|r| *r != a);
+                    }),
+                    InvariantError::RoadNotListed {
+                        road: a,
+                        intersection: i1,
+                    },
+                ),
+                (
+                    "intersection lists a missing road",
+                    Box::new(|streets: &mut StreetNetwork| {
+                        streets
+                            .intersections
+                            .get_mut(&i1)
+                            .unwrap()
+                            .roads
+                            .push(missing_r);
+                    }),
+                    InvariantError::UnknownRoad {
+                        intersection: i1,
+                        road: missing_r,
+                    },
+                ),
+                (
+                    "intersection lists a road twice",
+                    Box::new(|streets: &mut StreetNetwork| {
+                        streets.intersections.get_mut(&i0).unwrap().roads.push(a);
+                    }),
+                    InvariantError::DuplicateRoad {
+                        intersection: i0,
+                        road: a,
+                    },
+                ),
+                (
+                    "roads in counter-clockwise order",
+                    Box::new(|streets: &mut StreetNetwork| {
+                        streets.intersections.get_mut(&i0).unwrap().roads = reversed.clone();
+                    }),
+                    InvariantError::NotClockwise {
+                        intersection: i0,
+                        actual: reversed.clone(),
+                        expected: clockwise.clone(),
+                    },
+                ),
+                (
+                    "trimming the wrong end of a road",
+                    Box::new(|streets: &mut StreetNetwork| {
+                        streets
+                            .intersections
+                            .get_mut(&i1)
+                            .unwrap()
+                            .trim_roads_for_merging
+                            .insert((a, true), Pt2D::new(50.0, 0.0));
+                    }),
+                    InvariantError::InvalidTrimKey {
+                        intersection: i1,
+                        road: a,
+                        src_i: true,
+                    },
+                ),
+                (
+                    "turn restriction to a missing road",
+                    Box::new(|streets: &mut StreetNetwork| {
+                        streets.roads.get_mut(&a).unwrap().turn_restrictions.push(
+                            TurnRestriction {
+                                restriction: RestrictionType::BanTurns,
+                                to: missing_r,
+                                conditions: RestrictionConditions::default(),
+                            },
+                        );
+                    }),
+                    InvariantError::DanglingTurnRestriction(DanglingTurnRestriction {
+                        from: a,
+                        sequence: vec![a, missing_r],
+                        problem: DanglingProblem::MissingRoad(missing_r),
+                    }),
+                ),
+            ];
+        for (description, break_network, expected) in cases {
+            let mut copy = streets.clone();
+            break_network(&mut copy);
+            let errors = copy.check_invariants();
+            if !errors.contains(&expected) {
+                ok = false;
+                println!("For {description}, expected {expected}");
+                println!("Got: {errors:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+
+        // apply_transformations_checked only notices with the setting enabled
+        streets.roads.get_mut(&a).unwrap().dst_i = i0;
+        let mut timer = Timer::throwaway();
+        assert!(streets
+            .apply_transformations_checked(Vec::new(), &mut timer)
+            .is_ok());
+        streets.apply_transformations(Vec::new(), &mut timer);
+        streets.config.check_invariants = true;
+        assert!(streets
+            .apply_transformations_checked(Vec::new(), &mut timer)
+            .is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use serde::{Deserialize, Serialize};
+
+use geom::Angle;
+
+use crate::{
+    Direction, DrivingSide, IntersectionID, LaneType, Movement, Road, RoadID, StreetNetwork,
+};
+
+/// A connection from one lane to another through an intersection, refining a road-level
+/// `Movement`. Lanes are identified by their road and index into `lane_specs_ltr`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct LaneMovement {
+    pub from: (RoadID, usize),
+    pub to: (RoadID, usize),
+}
+
+/// A rough classification of a movement, used to match `turn:lanes` arrows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum TurnType {
+    Straight,
+    Left,
+    Right,
+    UTurn,
+}
+
+impl TurnType {
+    fn from_angles(from: Angle, to: Angle) -> TurnType {
+        let rotation = from.simple_shortest_rotation_towards(to);
+        if rotation.abs() < 30.0 {
+            TurnType::Straight
+        } else if rotation.abs() > 135.0 {
+            TurnType::UTurn
+        } else if rotation < 0.0 {
+            TurnType::Right
+        } else {
+            TurnType::Left
+        }
+    }
+
+    /// Does one value of a `turn:lanes` arrow permit this turn? See
+    /// <https://wiki.openstreetmap.org/wiki/Key:turn>.
+    fn matches_arrow(self, arrow: &str) -> bool {
+        match arrow {
+            "left" | "slight_left" | "sharp_left" => self == TurnType::Left,
+            "right" | "slight_right" | "sharp_right" => self == TurnType::Right,
+            "reverse" => self == TurnType::UTurn,
+            // Lanes without arrows are assumed to continue straight
+            "through" | "none" | "" | "merge_to_left" | "merge_to_right" => {
+                self == TurnType::Straight
+            }
+            _ => false,
+        }
+    }
+}
+
+impl StreetNetwork {
+    /// Refines road-level movements through an intersection into lane-level movements.
+    /// `turn:lanes` arrows pick the incoming lanes for each movement. When a road has no arrows (or
+    /// none of them match a movement), turns start from the lane closest to the side being turned
+    /// towards, and all lanes may continue straight. Incoming lanes are then paired with outgoing
+    /// lanes, starting from the side being turned towards (or the center of the road for straight
+    /// movements).
+    pub fn calculate_lane_movements(
+        &self,
+        i: IntersectionID,
+        movements: &[Movement],
+    ) -> Vec<LaneMovement> {
+        let mut results = Vec::new();
+        for (from, to) in movements {
+            if from == to {
+                continue;
+            }
+            let from_road = &self.roads[from];
+            let to_road = &self.roads[to];
+
+            let incoming_dir = if from_road.dst_i == i {
+                Direction::Fwd
+            } else {
+                Direction::Back
+            };
+            let outgoing_dir = if to_road.src_i == i {
+                Direction::Fwd
+            } else {
+                Direction::Back
+            };
+            let incoming = vehicle_lanes(from_road, incoming_dir);
+            let outgoing = vehicle_lanes(to_road, outgoing_dir);
+            if incoming.is_empty() || outgoing.is_empty() {
+                continue;
+            }
+
+            let turn_type =
+                TurnType::from_angles(approach_angle(from_road, i), departure_angle(to_road, i));
+            let center_on_left = self.config.driving_side == DrivingSide::Right;
+            let from_left = match turn_type {
+                TurnType::Left => true,
+                TurnType::Right => false,
+                TurnType::Straight | TurnType::UTurn => center_on_left,
+            };
+
+            let mut sources: Vec<usize> = incoming
+                .iter()
+                .cloned()
+                .filter(|idx| {
+                    let arrows = &from_road.lane_specs_ltr[*idx].turn_restrictions;
+                    if arrows.is_empty() {
+                        turn_type == TurnType::Straight
+                    } else {
+                        arrows.iter().any(|arrow| turn_type.matches_arrow(arrow))
+                    }
+                })
+                .collect();
+            let has_arrows = incoming
+                .iter()
+                .any(|idx| !from_road.lane_specs_ltr[*idx].turn_restrictions.is_empty());
+            if !has_arrows || sources.is_empty() {
+                sources = match turn_type {
+                    TurnType::Straight => incoming.clone(),
+                    _ => {
+                        if from_left {
+                            vec![incoming[0]]
+                        } else {
+                            vec![*incoming.last().unwrap()]
+                        }
+                    }
+                };
+            }
+
+            for (src, dst) in pair_lanes(sources, outgoing, from_left) {
+                results.push(LaneMovement {
+                    from: (*from, src),
+                    to: (*to, dst),
+                });
+            }
+        }
+        results.sort();
+        results.dedup();
+        results
+    }
+}
+
+/// Returns the indices of vehicle lanes travelling in one direction, ordered from left to right
+/// from the perspective of somebody travelling that way.
+fn vehicle_lanes(road: &Road, dir: Direction) -> Vec<usize> {
+    let mut lanes: Vec<usize> = road
+        .lane_specs_ltr
+        .iter()
+        .enumerate()
+        .filter(|(_, lane)| {
+            lane.dir.includes(dir) && matches!(lane.lt, LaneType::Driving | LaneType::Bus)
+        })
+        .map(|(idx, _)| idx)
+        .collect();
+    if dir == Direction::Back {
+        lanes.reverse();
+    }
+    lanes
+}
+
+/// The angle of a road as it enters an intersection
+fn approach_angle(road: &Road, i: IntersectionID) -> Angle {
+    if road.dst_i == i {
+        road.center_line.last_line().angle()
+    } else {
+        road.center_line.first_line().reversed().angle()
+    }
+}
+
+/// The angle of a road as it leaves an intersection
+fn departure_angle(road: &Road, i: IntersectionID) -> Angle {
+    if road.src_i == i {
+        road.center_line.first_line().angle()
+    } else {
+        road.center_line.last_line().reversed().angle()
+    }
+}
+
+/// Pairs up lanes one-to-one, starting from the left or right. When the counts don't match, the
+/// extra lanes on one side all connect to the last lane on the other side.
+fn pair_lanes(
+    mut incoming: Vec<usize>,
+    mut outgoing: Vec<usize>,
+    from_left: bool,
+) -> Vec<(usize, usize)> {
+    if !from_left {
+        incoming.reverse();
+        outgoing.reverse();
+    }
+    (0..incoming.len().max(outgoing.len()))
+        .map(|idx| {
+            (
+                incoming[idx.min(incoming.len() - 1)],
+                outgoing[idx.min(outgoing.len() - 1)],
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turn_type_from_angles() {
+        for (from, to, expected) in vec![
+            (270.0, 270.0, TurnType::Straight),
+            (270.0, 0.0, TurnType::Right),
+            (270.0, 180.0, TurnType::Left),
+            (270.0, 90.0, TurnType::UTurn),
+            (0.0, 20.0, TurnType::Straight),
+            (350.0, 10.0, TurnType::Straight),
+            (90.0, 180.0, TurnType::Right),
+        ] {
+            let actual = TurnType::from_angles(Angle::degrees(from), Angle::degrees(to));
+            assert_eq!(actual, expected, "turning from {from} to {to}");
+        }
+    }
+
+    #[test]
+    fn test_four_way_lane_movements() {
+        // A three-lane one-way heads north into a junction. The other roads leave it and have one
+        // lane in each direction, except for the road north, with two.
+        let mut streets = StreetNetwork::blank();
+        let center = streets.add_test_intersection(0.0, 0.0);
+        let south_i = streets.add_test_intersection(0.0, 100.0);
+        let north_i = streets.add_test_intersection(0.0, -100.0);
+        let east_i = streets.add_test_intersection(100.0, 0.0);
+        let west_i = streets.add_test_intersection(-100.0, 0.0);
+        let south = streets.add_test_road(south_i, center, "ddd", "^^^");
+        let north = streets.add_test_road(center, north_i, "dddd", "vv^^");
+        let east = streets.add_test_road(center, east_i, "dd", "v^");
+        let west = streets.add_test_road(center, west_i, "dd", "v^");
+
+        let mut ok = true;
+        for (description, turn_lanes, expected) in vec![
+            (
+                "without turn:lanes",
+                vec![vec![], vec![], vec![]],
+                vec![
+                    ((south, 0), (north, 2)),
+                    ((south, 1), (north, 3)),
+                    ((south, 2), (north, 3)),
+                    ((south, 2), (east, 1)),
+                    ((south, 0), (west, 1)),
+                ],
+            ),
+            (
+                "with turn:lanes=left|left;through|right",
+                vec![vec!["left"], vec!["left", "through"], vec!["right"]],
+                vec![
+                    ((south, 1), (north, 2)),
+                    ((south, 1), (north, 3)),
+                    ((south, 2), (east, 1)),
+                    ((south, 0), (west, 1)),
+                    ((south, 1), (west, 1)),
+                ],
+            ),
+        ] {
+            let mut copy = streets.clone();
+            let road = copy.roads.get_mut(&south).unwrap();
+            for (lane, arrows) in road.lane_specs_ltr.iter_mut().zip(turn_lanes) {
+                lane.turn_restrictions = arrows.into_iter().map(|x| x.to_string()).collect();
+            }
+
+            let movements = vec![(south, north), (south, east), (south, west)];
+            let actual = copy.calculate_lane_movements(center, &movements);
+            let mut expected: Vec<LaneMovement> = expected
+                .into_iter()
+                .map(|(from, to)| LaneMovement { from, to })
+                .collect();
+            expected.sort();
+            if actual != expected {
+                ok = false;
+                println!("For {description}");
+                println!("Got:      {actual:?}");
+                println!("Expected: {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+}
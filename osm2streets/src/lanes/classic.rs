@@ -3,15 +3,11 @@ use std::iter;
 use abstutil::Tags;
 use geom::Distance;
 
-use crate::{osm, BufferType, Direction, DrivingSide, LaneSpec, LaneType, MapConfig};
+use crate::{osm, AccessRules, BufferType, Direction, DrivingSide, LaneSpec, LaneType, MapConfig};
 
 /// Purely from OSM tags, determine the lanes that a road segment has. This is the "classic"
 /// implementation -- the default, but on its way out.
-pub fn get_lane_specs_ltr(tags: &Tags, cfg: &MapConfig) -> Vec<LaneSpec> {
-    if cfg.osm2lanes {
-        return super::osm2lanes::get_lane_specs_ltr_experimental(tags, cfg);
-    }
-
+pub(crate) fn get_lane_specs_ltr_classic(tags: &Tags, cfg: &MapConfig) -> Vec<LaneSpec> {
     // TODO This hides a potentially expensive (on a hot-path) clone
     let mut tags = tags.clone();
     // This'll do weird things for the special cases of railways and cycleways/footways, but the
@@ -78,6 +74,8 @@ fn fwd(lt: LaneType) -> LaneSpec {
         // Fill out later
         width: Distance::ZERO,
         turn_restrictions: Vec::new(),
+        access: AccessRules::default(),
+        width_profile: None,
     }
 }
 fn back(lt: LaneType) -> LaneSpec {
@@ -86,6 +84,19 @@ fn back(lt: LaneType) -> LaneSpec {
         dir: Direction::Back,
         width: Distance::ZERO,
         turn_restrictions: Vec::new(),
+        access: AccessRules::default(),
+        width_profile: None,
+    }
+}
+
+fn both(lt: LaneType) -> LaneSpec {
+    LaneSpec {
+        lt,
+        dir: Direction::Both,
+        width: Distance::ZERO,
+        turn_restrictions: Vec::new(),
+        access: AccessRules::default(),
+        width_profile: None,
     }
 }
 
@@ -96,6 +107,10 @@ fn apply_width(mut lanes: Vec<LaneSpec>, tags: &Tags) -> Vec<LaneSpec> {
         .unwrap();
     for spec in &mut lanes {
         spec.width = LaneSpec::typical_lane_widths(spec.lt, highway_type)[0].0;
+        // A two-way cycletrack is as wide as two one-way bike lanes
+        if spec.lt == LaneType::Biking && spec.dir == Direction::Both {
+            spec.width *= 2.0;
+        }
     }
     lanes
 }
@@ -128,11 +143,13 @@ fn non_motorized_road(tags: &Tags, cfg: &MapConfig) -> Option<Vec<LaneSpec>> {
     }
 
     // These roads will only exist if cfg.inferred_sidewalks is false
-    if tags.is(osm::HIGHWAY, "footway") && tags.is_any("footway", vec!["crossing", "sidewalk"]) {
-        // Treating a crossing as a sidewalk for now. Eventually crossings need to be dealt with
-        // completely differently.
+    if tags.is(osm::HIGHWAY, "footway") && tags.is("footway", "sidewalk") {
         return Some(vec![fwd(LaneType::Sidewalk)]);
     }
+    // Crossings go over a road, rather than running alongside one. Road::crossing describes them.
+    if tags.is(osm::HIGHWAY, "footway") && tags.is("footway", "crossing") {
+        return Some(vec![fwd(LaneType::Footway)]);
+    }
 
     // Handle pedestrian-oriented spaces
     if tags.is_any(
@@ -245,7 +262,8 @@ fn create_driving_lanes(
     };
 
     // These are ordered from the road center, going outwards. Most of the members of fwd_side will
-    // have Direction::Fwd, but there can be exceptions with two-way cycletracks.
+    // have Direction::Fwd, but there can be exceptions with shared turn lanes and two-way
+    // cycletracks, which have Direction::Both.
     let mut fwd_side: Vec<LaneSpec> = iter::repeat_with(|| fwd(driving_lane))
         .take(num_driving_fwd)
         .collect();
@@ -253,7 +271,7 @@ fn create_driving_lanes(
         .take(num_driving_back)
         .collect();
     if tags.is("lanes:both_ways", "1") || tags.is("centre_turn_lane", "yes") {
-        fwd_side.insert(0, fwd(LaneType::SharedLeftTurn));
+        fwd_side.insert(0, both(LaneType::SharedLeftTurn));
     }
 
     (fwd_side, back_side, oneway, driving_lane)
@@ -388,13 +406,13 @@ fn add_bike_lanes(
         if tags.is_any("cycleway:right", vec!["lane", "track"]) {
             if cfg.driving_side == DrivingSide::Right {
                 if tags.is("cycleway:right:oneway", "no") || tags.is("oneway:bicycle", "no") {
-                    fwd_side.push(back(LaneType::Biking));
+                    fwd_side.push(both(LaneType::Biking));
+                } else {
+                    fwd_side.push(fwd(LaneType::Biking));
                 }
-                fwd_side.push(fwd(LaneType::Biking));
+            } else if tags.is("cycleway:right:oneway", "no") || tags.is("oneway:bicycle", "no") {
+                back_side.push(both(LaneType::Biking));
             } else {
-                if tags.is("cycleway:right:oneway", "no") || tags.is("oneway:bicycle", "no") {
-                    back_side.push(fwd(LaneType::Biking));
-                }
                 back_side.push(back(LaneType::Biking));
             }
         }
@@ -408,8 +426,7 @@ fn add_bike_lanes(
         if tags.is_any("cycleway:left", vec!["lane", "opposite_track", "track"]) {
             if cfg.driving_side == DrivingSide::Right {
                 if tags.is("cycleway:left:oneway", "no") || tags.is("oneway:bicycle", "no") {
-                    back_side.push(fwd(LaneType::Biking));
-                    back_side.push(back(LaneType::Biking));
+                    back_side.push(both(LaneType::Biking));
                 } else if oneway {
                     fwd_side.insert(0, fwd(LaneType::Biking));
                 } else {
@@ -419,9 +436,10 @@ fn add_bike_lanes(
                 // TODO This should mimic the logic for right-handed driving, but I need test cases
                 // first to do this sanely
                 if tags.is("cycleway:left:oneway", "no") || tags.is("oneway:bicycle", "no") {
-                    fwd_side.push(back(LaneType::Biking));
+                    fwd_side.push(both(LaneType::Biking));
+                } else {
+                    fwd_side.push(fwd(LaneType::Biking));
                 }
-                fwd_side.push(fwd(LaneType::Biking));
             }
         }
     }
@@ -1,8 +1,11 @@
 mod classic;
 mod osm2lanes;
+mod parse;
 mod placement;
 #[cfg(test)]
 mod tests;
+mod to_osm;
+mod width;
 
 use std::fmt;
 
@@ -10,8 +13,12 @@ use serde::{Deserialize, Serialize};
 
 use geom::Distance;
 
-use crate::DrivingSide;
-pub use classic::get_lane_specs_ltr;
+use crate::{AccessRules, DrivingSide};
+pub use parse::{
+    compare_lane_parsers, get_lane_specs_ltr, get_lane_specs_ltr_with, LaneParser, LaneProvenance,
+};
+pub use to_osm::lane_specs_to_osm_tags;
+pub(crate) use width::apply_width_lanes;
 
 pub const NORMAL_LANE_THICKNESS: Distance = Distance::const_meters(2.5);
 const SERVICE_ROAD_LANE_THICKNESS: Distance = Distance::const_meters(1.5);
@@ -247,6 +254,73 @@ pub struct LaneSpec {
     /// <https://wiki.openstreetmap.org/wiki/Key:turn>. Turns for specific vehicle types
     /// (`turn:bus:lanes` and such) are not yet captured.
     pub turn_restrictions: Vec<String>,
+    /// Access tagged for this lane specifically, from `*:lanes` tags like `bus:lanes`. Use
+    /// `Road::lane_allows` to also account for the lane type and the whole road's access.
+    pub access: AccessRules,
+    /// How the width changes along the road, if it isn't constant. `width` is then the widest
+    /// point. Intersection geometry only uses `width`.
+    #[serde(default)]
+    pub width_profile: Option<WidthProfile>,
+}
+
+/// How the width of a lane changes along a road, for turn pockets, tapers, and
+/// `width:lanes:start`/`width:lanes:end`. Each point is a fraction of the way along the road (0 at
+/// the start, 1 at the end) and the width there, sorted by fraction. The width changes linearly
+/// between points and stays constant before the first and after the last.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WidthProfile(pub Vec<(f64, Distance)>);
+
+impl WidthProfile {
+    /// A width changing linearly along the whole road.
+    pub fn linear(start: Distance, end: Distance) -> Self {
+        Self(vec![(0.0, start), (1.0, end)])
+    }
+
+    fn width_at(&self, fraction: f64) -> Option<Distance> {
+        let (first_fraction, first_width) = *self.0.first()?;
+        if fraction <= first_fraction {
+            return Some(first_width);
+        }
+        for pair in self.0.windows(2) {
+            let ((f1, w1), (f2, w2)) = (pair[0], pair[1]);
+            if fraction <= f2 {
+                if f2 <= f1 {
+                    return Some(w2);
+                }
+                return Some(w1 + (w2 - w1) * ((fraction - f1) / (f2 - f1)));
+            }
+        }
+        self.0.last().map(|(_, width)| *width)
+    }
+
+    /// The part of this profile between two fractions of the way along the road, rescaled to
+    /// cover a whole road. Used when a road is split.
+    fn slice(&self, from: f64, to: f64) -> Option<Self> {
+        let mut points = vec![(0.0, self.width_at(from)?)];
+        for (fraction, width) in &self.0 {
+            if *fraction > from && *fraction < to {
+                points.push(((fraction - from) / (to - from), *width));
+            }
+        }
+        points.push((1.0, self.width_at(to)?));
+        Some(Self(points))
+    }
+
+    fn max_width(&self) -> Option<Distance> {
+        let mut result = self.0.first()?.1;
+        for (_, width) in &self.0 {
+            if *width > result {
+                result = *width;
+            }
+        }
+        Some(result)
+    }
+
+    /// The fractions where the rate of change may change. Lines following the edge of the lane
+    /// need a point at each of these.
+    pub(crate) fn breakpoints(&self) -> Vec<f64> {
+        self.0.iter().map(|(fraction, _)| *fraction).collect()
+    }
 }
 
 impl LaneSpec {
@@ -326,6 +400,47 @@ impl LaneSpec {
         Self::typical_lane_widths(lt, "road")[0].0
     }
 
+    /// The width of this lane some fraction of the way along the road, from 0 at the start to 1
+    /// at the end.
+    pub fn width_at(&self, fraction: f64) -> Distance {
+        self.width_profile
+            .as_ref()
+            .and_then(|profile| profile.width_at(fraction))
+            .unwrap_or(self.width)
+    }
+
+    /// Makes the width of this lane vary along the road. `width` becomes the widest point.
+    pub fn set_width_profile(&mut self, profile: WidthProfile) {
+        if let Some(width) = profile.max_width() {
+            self.width = width;
+            self.width_profile = Some(profile);
+        }
+    }
+
+    /// Keeps only the part of the width profile between two fractions of the way along the road,
+    /// for one piece of a split road. `width` becomes the widest point of that part.
+    pub fn slice_width_profile(&mut self, from: f64, to: f64) {
+        let profile = match self.width_profile.take().and_then(|p| p.slice(from, to)) {
+            Some(profile) => profile,
+            None => return,
+        };
+        if profile.0.iter().all(|(_, width)| *width == profile.0[0].1) {
+            self.width = profile.0[0].1;
+        } else {
+            self.set_width_profile(profile);
+        }
+    }
+
+    /// Scales the width of this lane everywhere along the road.
+    pub fn scale_width(&mut self, factor: f64) {
+        self.width *= factor;
+        if let Some(ref mut profile) = self.width_profile {
+            for (_, width) in &mut profile.0 {
+                *width *= factor;
+            }
+        }
+    }
+
     /// Put a list of forward and backward lanes into left-to-right order, depending on the driving
     /// side. Both input lists should be ordered from the center of the road going outwards.
     pub(crate) fn assemble_ltr(
@@ -354,11 +469,8 @@ impl LaneSpec {
         let mut back = false;
         for x in lanes {
             if x.lt == LaneType::Driving {
-                if x.dir == Direction::Fwd {
-                    fwd = true;
-                } else {
-                    back = true;
-                }
+                fwd |= x.dir.includes(Direction::Fwd);
+                back |= x.dir.includes(Direction::Back);
             }
         }
         if fwd && back {
@@ -373,11 +485,41 @@ impl LaneSpec {
             None
         }
     }
+
+    /// Finds the lanes described by each form of `:lanes` tags, like `width:lanes:forward`. Each
+    /// list holds indices into `lanes`, in the order values are listed: left to right, from the
+    /// perspective of somebody travelling in that direction. `:lanes:both_ways` describes the
+    /// lanes used in both directions, like a center turn lane. Only lanes counted by
+    /// `LaneType::is_tagged_by_lanes_suffix` are included.
+    pub(crate) fn indices_per_lanes_suffix(lanes: &[LaneSpec]) -> Vec<(&'static str, Vec<usize>)> {
+        let tagged = |dir: Option<Direction>| -> Vec<usize> {
+            lanes
+                .iter()
+                .enumerate()
+                .filter(|(_, spec)| {
+                    spec.lt.is_tagged_by_lanes_suffix()
+                        && dir.map(|d| spec.dir == d).unwrap_or(true)
+                })
+                .map(|(idx, _)| idx)
+                .collect()
+        };
+        let mut backward = tagged(Some(Direction::Back));
+        backward.reverse();
+        vec![
+            (":lanes", tagged(None)),
+            (":lanes:forward", tagged(Some(Direction::Fwd))),
+            (":lanes:backward", backward),
+            (":lanes:both_ways", tagged(Some(Direction::Both))),
+        ]
+    }
 }
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Direction {
     Fwd,
     Back,
+    /// Only for lanes used in both directions, like a shared center turn lane or a two-way cycle
+    /// track. Roads are always travelled `Fwd` or `Back`.
+    Both,
 }
 
 impl Direction {
@@ -385,8 +527,23 @@ impl Direction {
         match self {
             Direction::Fwd => Direction::Back,
             Direction::Back => Direction::Fwd,
+            Direction::Both => Direction::Both,
+        }
+    }
+
+    /// A character for describing lanes compactly: `^` forwards, `v` backwards, or `-` both ways.
+    pub fn to_char(self) -> char {
+        match self {
+            Direction::Fwd => '^',
+            Direction::Back => 'v',
+            Direction::Both => '-',
         }
     }
+
+    /// Can a lane in this direction be used travelling in `dir`?
+    pub fn includes(self, dir: Direction) -> bool {
+        self == dir || self == Direction::Both
+    }
 }
 
 impl fmt::Display for Direction {
@@ -394,6 +551,7 @@ impl fmt::Display for Direction {
         match self {
             Direction::Fwd => write!(f, "forwards"),
             Direction::Back => write!(f, "backwards"),
+            Direction::Both => write!(f, "both ways"),
         }
     }
 }
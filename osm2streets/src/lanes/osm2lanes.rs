@@ -4,42 +4,29 @@ use osm2lanes::road::Designated;
 use abstutil::Tags;
 use geom::Distance;
 
-use crate::{osm, Direction, DrivingSide, LaneSpec, LaneType, MapConfig};
+use crate::{osm, AccessRules, Direction, DrivingSide, LaneSpec, LaneType, MapConfig};
 
 /// Purely from OSM tags, determine the lanes that a road segment has. This is an experimental
 /// implementation that uses https://github.com/a-b-street/osm2lanes for the heavy lifting. Most of
 /// the work in this method is to patch around current problems in osm2lanes and to translate into
-/// osm2streets types.
-pub fn get_lane_specs_ltr_experimental(orig_tags: &Tags, cfg: &MapConfig) -> Vec<LaneSpec> {
+/// osm2streets types. Also returns any warnings osm2lanes produced.
+pub fn get_lane_specs_ltr_experimental(
+    orig_tags: &Tags,
+    cfg: &MapConfig,
+) -> Result<(Vec<LaneSpec>, Vec<String>)> {
     // Special cases first
     if orig_tags.is_any("railway", vec!["light_rail", "rail"]) {
-        return vec![LaneSpec {
+        let lanes = vec![LaneSpec {
             lt: LaneType::LightRail,
             dir: Direction::Fwd,
             width: LaneSpec::typical_lane_width(LaneType::LightRail),
             turn_restrictions: Vec::new(),
+            access: AccessRules::default(),
+            width_profile: None,
         }];
+        return Ok((lanes, Vec::new()));
     }
 
-    match inner_get_lane_specs_ltr(orig_tags, cfg) {
-        Ok(lanes) => lanes,
-        Err(err) => {
-            error!(
-                "osm2lanes broke on something with tags {:?}: {}",
-                orig_tags, err
-            );
-            // Stick something obviously wrong in the output
-            vec![LaneSpec {
-                lt: LaneType::Driving,
-                dir: Direction::Fwd,
-                width: Distance::meters(1.0),
-                turn_restrictions: Vec::new(),
-            }]
-        }
-    }
-}
-
-fn inner_get_lane_specs_ltr(orig_tags: &Tags, cfg: &MapConfig) -> Result<Vec<LaneSpec>> {
     let tags = transform_tags(orig_tags, cfg);
     let locale = osm2lanes::locale::Config::new()
         .driving_side(match cfg.driving_side {
@@ -52,29 +39,26 @@ fn inner_get_lane_specs_ltr(orig_tags: &Tags, cfg: &MapConfig) -> Result<Vec<Lan
     config.include_separators = true;
 
     let output = osm2lanes::transform::tags_to_lanes(&tags, &locale, &config)?;
+    let warnings: Vec<String> = output
+        .warnings
+        .into_inner()
+        .into_iter()
+        .map(|warning| warning.to_string())
+        .collect();
     let highway_type = output.road.highway.r#type();
     let highway_type_str = tags.get(osm::HIGHWAY).unwrap();
 
     let mut result = Vec::new();
     for lane in output.road.lanes {
-        let mut new_lanes = transform_lane(lane, &locale, highway_type, cfg, result.is_empty())?;
-        if new_lanes.is_empty() {
-            continue;
-        }
+        let mut new_lane = match transform_lane(lane, &locale, highway_type, result.is_empty())? {
+            Some(lane) => lane,
+            None => continue,
+        };
 
         // Don't use widths from osm2lanes yet
-        for lane in &mut new_lanes {
-            lane.width = LaneSpec::typical_lane_widths(lane.lt, highway_type_str)[0].0;
-        }
-
-        // If we split a bidirectional lane into two pieces, halve the width of each piece
-        if new_lanes.len() == 2 {
-            for lane in &mut new_lanes {
-                lane.width *= 0.5;
-            }
-        }
+        new_lane.width = LaneSpec::typical_lane_widths(new_lane.lt, highway_type_str)[0].0;
 
-        result.extend(new_lanes);
+        result.push(new_lane);
     }
 
     // The two projects have a different concept of shoulder. In osm2streets, no shoulders on
@@ -140,7 +124,7 @@ fn inner_get_lane_specs_ltr(orig_tags: &Tags, cfg: &MapConfig) -> Result<Vec<Lan
         }
     }
 
-    Ok(result)
+    Ok((result, warnings))
 }
 
 fn transform_tags(tags: &Tags, cfg: &MapConfig) -> osm_tags::Tags {
@@ -186,27 +170,25 @@ fn transform_tags(tags: &Tags, cfg: &MapConfig) -> osm_tags::Tags {
     result
 }
 
-// This produces:
-// - 0 lanes if we're ignoring this lane entirely (a separator)
-// - 1 lane in most cases
-// - 2 lanes if we're splitting a bidirectional lane
+// This produces nothing if we're ignoring this lane entirely (a separator)
 fn transform_lane(
     lane: osm2lanes::road::Lane,
     locale: &osm2lanes::locale::Locale,
     highway_type: osm_tag_schemes::HighwayType,
-    cfg: &MapConfig,
     is_first_lane: bool,
-) -> Result<Vec<LaneSpec>> {
+) -> Result<Option<LaneSpec>> {
     use osm2lanes::road::Lane;
 
     let single_lane = |lt, dir| {
         let width = Distance::meters(lane.width(locale, highway_type).val());
-        Ok(vec![LaneSpec {
+        Ok(Some(LaneSpec {
             lt,
             dir,
             width,
             turn_restrictions: Vec::new(),
-        }])
+            access: AccessRules::default(),
+            width_profile: None,
+        }))
     };
 
     match lane {
@@ -237,15 +219,14 @@ fn transform_lane(
             // turn lane. We may change the osm2lanes representation to clarify this
             // (https://github.com/a-b-street/osm2lanes/issues/184).
             if lt == LaneType::Driving && !is_first_lane {
-                return single_lane(LaneType::SharedLeftTurn, Direction::Fwd);
+                return single_lane(LaneType::SharedLeftTurn, Direction::Both);
             }
             if lt == LaneType::Sidewalk {
                 bail!("Unexpected direction=both and designated=foot");
             }
 
-            // Otherwise, represent the bidirection car/bike/bus lane as two half-width lanes
-            let total_width = Distance::meters(lane.width(locale, highway_type).val());
-            Ok(bidirectional_lane(lt, total_width, cfg))
+            // Otherwise, it's a bidirectional car/bike/bus lane
+            single_lane(lt, Direction::Both)
         }
         Lane::Shoulder { .. } => {
             // We'll fix direction of outermost sidewalks/shoulders later
@@ -253,7 +234,7 @@ fn transform_lane(
         }
         Lane::Separator { .. } => {
             // TODO Barriers?
-            Ok(Vec::new())
+            Ok(None)
         }
         Lane::Parking {
             direction,
@@ -270,26 +251,3 @@ fn transform_lane(
         _ => bail!("handle {:?}", lane),
     }
 }
-
-// Transform one lane into two, since A/B Street can't properly model narrow lanes
-fn bidirectional_lane(lt: LaneType, total_width: Distance, cfg: &MapConfig) -> Vec<LaneSpec> {
-    let (dir1, dir2) = if cfg.driving_side == DrivingSide::Right {
-        (Direction::Back, Direction::Fwd)
-    } else {
-        (Direction::Fwd, Direction::Back)
-    };
-    vec![
-        LaneSpec {
-            lt,
-            dir: dir1,
-            width: total_width / 2.0,
-            turn_restrictions: Vec::new(),
-        },
-        LaneSpec {
-            lt,
-            dir: dir2,
-            width: total_width / 2.0,
-            turn_restrictions: Vec::new(),
-        },
-    ]
-}
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+use abstutil::Tags;
+
+use super::classic::get_lane_specs_ltr_classic;
+use super::osm2lanes::get_lane_specs_ltr_experimental;
+use crate::{LaneSpec, MapConfig};
+
+/// Which implementation parsed a road's lanes from OSM tags
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LaneParser {
+    /// `lanes/classic.rs`, the default
+    Classic,
+    /// https://github.com/a-b-street/osm2lanes, still experimental
+    Osm2lanes,
+}
+
+/// How a road's lanes were parsed from OSM tags
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LaneProvenance {
+    /// The parser that produced the lanes. If osm2lanes was requested but failed, this is
+    /// `Classic`, and the error is in `warnings`.
+    pub parser: LaneParser,
+    /// Problems the parser noticed with the tags
+    pub warnings: Vec<String>,
+    /// With `MapConfig::compare_lane_parsers`, how the lanes from the other parser differ
+    pub disagreement: Option<String>,
+}
+
+impl Default for LaneProvenance {
+    fn default() -> Self {
+        Self {
+            parser: LaneParser::Classic,
+            warnings: Vec::new(),
+            disagreement: None,
+        }
+    }
+}
+
+/// Purely from OSM tags, determine the lanes that a road segment has. `MapConfig::osm2lanes`
+/// picks the parser.
+pub fn get_lane_specs_ltr(tags: &Tags, cfg: &MapConfig) -> Vec<LaneSpec> {
+    let parser = if cfg.osm2lanes {
+        LaneParser::Osm2lanes
+    } else {
+        LaneParser::Classic
+    };
+    get_lane_specs_ltr_with(tags, cfg, parser).0
+}
+
+/// Determine lanes from OSM tags with a particular parser, also describing how they were parsed.
+/// If osm2lanes fails, the classic parser is used instead.
+pub fn get_lane_specs_ltr_with(
+    tags: &Tags,
+    cfg: &MapConfig,
+    parser: LaneParser,
+) -> (Vec<LaneSpec>, LaneProvenance) {
+    if parser == LaneParser::Osm2lanes {
+        match get_lane_specs_ltr_experimental(tags, cfg) {
+            Ok((lanes, warnings)) => {
+                return (
+                    lanes,
+                    LaneProvenance {
+                        parser: LaneParser::Osm2lanes,
+                        warnings,
+                        disagreement: None,
+                    },
+                );
+            }
+            Err(err) => {
+                warn!("osm2lanes broke on something with tags {tags:?}, using classic: {err}");
+                let provenance = LaneProvenance {
+                    parser: LaneParser::Classic,
+                    warnings: vec![format!("osm2lanes failed: {err}")],
+                    disagreement: None,
+                };
+                return (get_lane_specs_ltr_classic(tags, cfg), provenance);
+            }
+        }
+    }
+    (
+        get_lane_specs_ltr_classic(tags, cfg),
+        LaneProvenance::default(),
+    )
+}
+
+/// Parses lanes with both the classic parser and osm2lanes. If the types or directions of the
+/// lanes differ, describes how. Widths aren't compared, since widths from osm2lanes aren't used
+/// yet.
+pub fn compare_lane_parsers(tags: &Tags, cfg: &MapConfig) -> Option<String> {
+    let (classic, _) = get_lane_specs_ltr_with(tags, cfg, LaneParser::Classic);
+    let (experimental, provenance) = get_lane_specs_ltr_with(tags, cfg, LaneParser::Osm2lanes);
+    if provenance.parser != LaneParser::Osm2lanes {
+        return Some(provenance.warnings.join(", "));
+    }
+    let same = classic.len() == experimental.len()
+        && classic
+            .iter()
+            .zip(&experimental)
+            .all(|(a, b)| a.lt == b.lt && a.dir == b.dir);
+    if same {
+        return None;
+    }
+    Some(format!(
+        "classic has {}, but osm2lanes has {}",
+        describe(&classic),
+        describe(&experimental)
+    ))
+}
+
+/// Lane types and directions, like `sdds (vv^^)`
+fn describe(lanes: &[LaneSpec]) -> String {
+    let types: String = lanes.iter().map(|l| l.lt.to_char()).collect();
+    let dirs: String = lanes.iter().map(|l| l.dir.to_char()).collect();
+    format!("{types} ({dirs})")
+}
@@ -1,6 +1,12 @@
 use abstutil::Tags;
 
-use crate::{get_lane_specs_ltr, Direction, DrivingSide, MapConfig};
+use geom::Distance;
+
+use super::apply_width_lanes;
+use crate::{
+    compare_lane_parsers, get_lane_specs_ltr, get_lane_specs_ltr_with, lane_specs_to_osm_tags,
+    DrivingSide, LaneParser, LaneSpec, LaneType, MapConfig,
+};
 
 // osm2lanes has a more extensive unit test suite, so why does this one exist? This also checks the
 // translation from osm2lanes output into osm2streets. This is particularly useful during migration
@@ -10,7 +16,89 @@ fn test_osm_to_specs() {
     abstutil::logger::setup();
 
     let mut ok = true;
-    for (url, mut input, driving_side, expected_lt, expected_dir) in vec![
+    for (url, mut input, driving_side, expected_lt, expected_dir) in osm_to_specs_fixtures() {
+        let mut cfg = MapConfig::default();
+        cfg.driving_side = driving_side;
+        input.push("highway=residential");
+        let actual = get_lane_specs_ltr(&tags(input.clone()), &cfg);
+        let actual_lt: String = actual.iter().map(|s| s.lt.to_char()).collect();
+        let actual_dir: String = actual.iter().map(|s| s.dir.to_char()).collect();
+        if actual_lt != expected_lt || actual_dir != expected_dir {
+            ok = false;
+            println!("For input (example from {}):", url);
+            for kv in input {
+                println!("    {}", kv);
+            }
+            println!("Got:");
+            println!("    {}", actual_lt);
+            println!("    {}", actual_dir);
+            println!("Expected:");
+            println!("    {}", expected_lt);
+            println!("    {}", expected_dir);
+            println!();
+        }
+    }
+    assert!(ok);
+}
+
+/// How many of the fixtures osm2lanes and the classic algorithm must agree on. Raise this when
+/// osm2lanes improves, so parity can't quietly go back down.
+const MIN_PARSER_AGREEMENT: usize = 5;
+
+// Tracks how close osm2lanes is to the classic algorithm, using the same cases. They don't fully
+// agree yet, so individual differences are only reported, but the number of cases they agree on
+// can't drop below MIN_PARSER_AGREEMENT.
+#[test]
+fn test_compare_lane_parsers() {
+    abstutil::logger::setup();
+
+    let fixtures = osm_to_specs_fixtures();
+    let total = fixtures.len();
+    let mut agree = 0;
+    for (url, mut input, driving_side, expected_lt, expected_dir) in fixtures {
+        let mut cfg = MapConfig::default();
+        cfg.driving_side = driving_side;
+        input.push("highway=residential");
+        let input = tags(input);
+        match compare_lane_parsers(&input, &cfg) {
+            Some(disagreement) => {
+                println!("For {url}, {disagreement}");
+            }
+            None => {
+                agree += 1;
+                // Then osm2lanes gets the expected lanes too
+                let (actual, provenance) =
+                    get_lane_specs_ltr_with(&input, &cfg, LaneParser::Osm2lanes);
+                assert_eq!(provenance.parser, LaneParser::Osm2lanes);
+                let actual_lt: String = actual.iter().map(|s| s.lt.to_char()).collect();
+                let actual_dir: String = actual.iter().map(|s| s.dir.to_char()).collect();
+                assert_eq!(actual_lt, expected_lt, "for {url}");
+                assert_eq!(actual_dir, expected_dir, "for {url}");
+            }
+        }
+    }
+    println!("osm2lanes agrees with the classic algorithm for {agree} of {total} cases");
+    assert!(
+        agree >= MIN_PARSER_AGREEMENT,
+        "osm2lanes only agrees with the classic algorithm for {agree} cases, but used to agree \
+         for {MIN_PARSER_AGREEMENT}"
+    );
+    if agree > MIN_PARSER_AGREEMENT {
+        println!("Raise MIN_PARSER_AGREEMENT to {agree}");
+    }
+}
+
+/// An example URL, the input tags, driving side, and expected lane types and directions
+type Fixture = (
+    &'static str,
+    Vec<&'static str>,
+    DrivingSide,
+    &'static str,
+    &'static str,
+);
+
+fn osm_to_specs_fixtures() -> Vec<Fixture> {
+    vec![
         (
             "https://www.openstreetmap.org/way/428294122",
             vec![
@@ -33,8 +121,8 @@ fn test_osm_to_specs() {
                 "oneway:bicycle=no",
             ],
             DrivingSide::Right,
-            "sbbds",
-            "vv^^^",
+            "sbds",
+            "v-^^",
         ),
         (
             // A slight variation of the above, using cycleway:left:oneway=no, which should be
@@ -48,8 +136,8 @@ fn test_osm_to_specs() {
                 "cycleway:left:oneway=no",
             ],
             DrivingSide::Right,
-            "sbbds",
-            "vv^^^",
+            "sbds",
+            "v-^^",
         ),
         (
             "https://www.openstreetmap.org/way/353690151",
@@ -61,8 +149,8 @@ fn test_osm_to_specs() {
                 "cycleway:right:oneway=no",
             ],
             DrivingSide::Right,
-            "spddddbbps",
-            "vvvv^^v^^^",
+            "spddddbps",
+            "vvvv^^-^^",
         ),
         (
             "https://www.openstreetmap.org/way/389654080",
@@ -76,8 +164,8 @@ fn test_osm_to_specs() {
                 "cycleway:right:oneway=no",
             ],
             DrivingSide::Right,
-            "spdCdbbs",
-            "vvv^^v^^",
+            "spdCdbs",
+            "vvv-^-^",
         ),
         (
             "https://www.openstreetmap.org/way/369623526",
@@ -90,8 +178,8 @@ fn test_osm_to_specs() {
                 "oneway:bicycle=no",
             ],
             DrivingSide::Right,
-            "sbbdps",
-            "vv^^^^",
+            "sbdps",
+            "v-^^^",
         ),
         (
             "https://www.openstreetmap.org/way/534549104",
@@ -104,8 +192,8 @@ fn test_osm_to_specs() {
                 "oneway:bicycle=no",
             ],
             DrivingSide::Right,
-            "sddbbs",
-            "v^^v^^",
+            "sddbs",
+            "v^^-^",
         ),
         (
             "https://www.openstreetmap.org/way/777565028",
@@ -198,34 +286,200 @@ fn test_osm_to_specs() {
             "sBddBs",
             "^^^vvv",
         ),
+    ]
+}
+
+#[test]
+fn test_specs_to_osm() {
+    abstutil::logger::setup();
+
+    let mut ok = true;
+    for (description, input, edited_lt, edited_dir, expected_tags) in vec![
+        (
+            "unedited lanes keep the original tags",
+            vec!["lanes=2", "sidewalk=both", "cycleway=no"],
+            "sdds",
+            "vv^^",
+            vec!["lanes=2", "sidewalk=both", "cycleway=no"],
+        ),
+        (
+            "add a bike lane",
+            vec!["lanes=2", "sidewalk=both"],
+            "sddbs",
+            "vv^^^",
+            vec![
+                "lanes=2",
+                "lanes:forward=1",
+                "lanes:backward=1",
+                "cycleway:right=lane",
+                "sidewalk=both",
+            ],
+        ),
+        (
+            "make a road one-way",
+            vec!["lanes=2", "sidewalk=both"],
+            "sdds",
+            "v^^^",
+            vec!["lanes=2", "oneway=yes", "sidewalk=both"],
+        ),
+        (
+            "add a bus lane",
+            vec!["lanes=2", "oneway=yes", "sidewalk=both"],
+            "sdBs",
+            "v^^^",
+            vec![
+                "lanes=2",
+                "oneway=yes",
+                "bus:lanes=|designated",
+                "sidewalk=both",
+            ],
+        ),
+        (
+            "add a center turn lane",
+            vec!["lanes=2", "sidewalk=both"],
+            "sdCds",
+            "vv-^^",
+            vec![
+                "lanes=3",
+                "lanes:forward=1",
+                "lanes:backward=1",
+                "lanes:both_ways=1",
+                "turn:lanes:both_ways=left",
+                "sidewalk=both",
+            ],
+        ),
+        (
+            "replace parking with a protected bike lane",
+            vec!["lanes=2", "sidewalk=both", "parking:lane:both=parallel"],
+            "spdd|bs",
+            "vvv^^^^",
+            vec![
+                "lanes=2",
+                "lanes:forward=1",
+                "lanes:backward=1",
+                "cycleway:right=lane",
+                "cycleway:right:separation:left=vertical_panel",
+                "parking:lane:left=parallel",
+                "parking:lane:right=no",
+                "sidewalk=both",
+            ],
+        ),
     ] {
-        let mut cfg = MapConfig::default();
-        cfg.driving_side = driving_side;
-        input.push("highway=residential");
-        let actual = get_lane_specs_ltr(&tags(input.clone()), &cfg);
-        let actual_lt: String = actual.iter().map(|s| s.lt.to_char()).collect();
-        let actual_dir: String = actual
-            .iter()
-            .map(|s| if s.dir == Direction::Fwd { '^' } else { 'v' })
-            .collect();
-        if actual_lt != expected_lt || actual_dir != expected_dir {
+        let cfg = MapConfig::default();
+        let mut input = tags(input);
+        input.insert("highway", "residential");
+        let mut expected = tags(expected_tags);
+        expected.insert("highway", "residential");
+
+        let lanes = LaneSpec::create_for_test(edited_lt, edited_dir);
+        let actual = lane_specs_to_osm_tags(&lanes, &input, &cfg);
+        if actual != expected {
             ok = false;
-            println!("For input (example from {}):", url);
-            for kv in input {
-                println!("    {}", kv);
-            }
-            println!("Got:");
-            println!("    {}", actual_lt);
-            println!("    {}", actual_dir);
-            println!("Expected:");
-            println!("    {}", expected_lt);
-            println!("    {}", expected_dir);
-            println!();
+            println!("For {description}, got tags {actual:?}, but expected {expected:?}");
+        }
+
+        // The tags should describe the edited lanes
+        LaneSpec::check_lanes_ltr(
+            &get_lane_specs_ltr(&actual, &cfg),
+            format!("Parsing the tags written for {description}"),
+            edited_lt,
+            edited_dir,
+            edited_lt,
+            edited_dir,
+            &mut ok,
+        );
+    }
+    assert!(ok);
+}
+
+#[test]
+fn test_both_ways_lanes_to_osm() {
+    // The classic parser doesn't produce travel lanes used in both directions, so unlike
+    // test_specs_to_osm, these tags aren't parsed again
+    let mut ok = true;
+    for (description, edited_lt, edited_dir, expected_tags) in vec![
+        (
+            "a single lane shared by both directions",
+            "sds",
+            "v-^",
+            vec!["lanes=1", "sidewalk=both"],
+        ),
+        (
+            "a bus lane used in both directions",
+            "sdBds",
+            "vv-^^",
+            vec![
+                "lanes=3",
+                "lanes:forward=1",
+                "lanes:backward=1",
+                "lanes:both_ways=1",
+                "bus:lanes:both_ways=designated",
+                "sidewalk=both",
+            ],
+        ),
+    ] {
+        let cfg = MapConfig::default();
+        let input = tags(vec!["highway=residential", "lanes=2", "sidewalk=both"]);
+        let mut expected = tags(expected_tags);
+        expected.insert("highway", "residential");
+
+        let lanes = LaneSpec::create_for_test(edited_lt, edited_dir);
+        let actual = lane_specs_to_osm_tags(&lanes, &input, &cfg);
+        if actual != expected {
+            ok = false;
+            println!("For {description}, got tags {actual:?}, but expected {expected:?}");
         }
     }
     assert!(ok);
 }
 
+#[test]
+fn test_width_lanes() {
+    let input = tags(vec![
+        "highway=primary",
+        "oneway=yes",
+        "lanes=2",
+        "width:lanes=3.5|3",
+        "width:lanes:start=3.5|0",
+    ]);
+    let mut lanes = get_lane_specs_ltr(&input, &MapConfig::default());
+    apply_width_lanes(&mut lanes, &input);
+    let driving: Vec<&LaneSpec> = lanes
+        .iter()
+        .filter(|lane| lane.lt == LaneType::Driving)
+        .collect();
+    assert_eq!(driving.len(), 2);
+
+    // The first lane has the same width everywhere
+    assert!(driving[0].width_profile.is_none());
+    assert_eq!(driving[0].width_at(0.5), Distance::meters(3.5));
+    // The second one widens from nothing at the start, like a turn pocket
+    assert_eq!(driving[1].width, Distance::meters(3.0));
+    assert_eq!(driving[1].width_at(0.0), Distance::ZERO);
+    assert_eq!(driving[1].width_at(0.5), Distance::meters(1.5));
+    assert_eq!(driving[1].width_at(1.0), Distance::meters(3.0));
+
+    // A center turn lane is described by :lanes:both_ways, and backward lanes are listed from
+    // the perspective of somebody travelling backwards
+    let input = tags(vec![
+        "width:lanes:backward=3.5|3",
+        "width:lanes:both_ways=4",
+        "width:lanes:forward=2.5",
+    ]);
+    let mut lanes = LaneSpec::create_for_test("ddCd", "vv-^");
+    apply_width_lanes(&mut lanes, &input);
+    let widths: Vec<Distance> = lanes.iter().map(|lane| lane.width).collect();
+    assert_eq!(
+        widths,
+        vec![
+            Distance::meters(3.0),
+            Distance::meters(3.5),
+            Distance::meters(4.0),
+            Distance::meters(2.5),
+        ]
+    );
+}
+
 fn tags(kv: Vec<&str>) -> Tags {
     let mut tags = Tags::empty();
     for pair in kv {
@@ -0,0 +1,355 @@
+use abstutil::Tags;
+
+use crate::{osm, BufferType, Direction, DrivingSide, LaneSpec, LaneType, MapConfig};
+
+/// Tags describing lanes. These're all replaced when lanes are written back to tags.
+const LANE_KEYS: [&str; 33] = [
+    "lanes",
+    "lanes:forward",
+    "lanes:backward",
+    "lanes:both_ways",
+    "centre_turn_lane",
+    "turn:lanes",
+    "turn:lanes:forward",
+    "turn:lanes:backward",
+    "turn:lanes:both_ways",
+    "bus:lanes",
+    "bus:lanes:forward",
+    "bus:lanes:backward",
+    "bus:lanes:both_ways",
+    "psv:lanes",
+    "psv:lanes:forward",
+    "psv:lanes:backward",
+    "busway",
+    "busway:both",
+    "busway:left",
+    "busway:right",
+    "cycleway",
+    "cycleway:both",
+    "cycleway:left",
+    "cycleway:right",
+    "cycleway:left:oneway",
+    "cycleway:right:oneway",
+    "cycleway:left:separation:left",
+    "cycleway:left:separation:right",
+    "cycleway:right:separation:left",
+    "cycleway:right:separation:right",
+    "parking:lane:both",
+    "parking:lane:left",
+    "parking:lane:right",
+];
+
+/// Describes lanes with OSM tags, roughly the reverse of `get_lane_specs_ltr`. Tags from the
+/// original way that describe lanes (`lanes`, `turn:lanes`, `cycleway:*`, `parking:lane:*`,
+/// `sidewalk`, and bus lanes) are replaced, and everything else is kept. If the lanes still match
+/// what the original tags describe, those tags are returned unchanged. Paths, railways, and roads
+/// under construction don't describe lanes this way, so they're never changed. Lane widths aren't
+/// written.
+pub fn lane_specs_to_osm_tags(lanes_ltr: &[LaneSpec], original: &Tags, cfg: &MapConfig) -> Tags {
+    if original.contains_key("railway")
+        || original.is_any(
+            osm::HIGHWAY,
+            vec![
+                "construction",
+                "cycleway",
+                "footway",
+                "path",
+                "pedestrian",
+                "steps",
+                "track",
+            ],
+        )
+    {
+        return original.clone();
+    }
+    let before = super::get_lane_specs_ltr(original, cfg);
+    if same_lanes(&before, lanes_ltr) {
+        return original.clone();
+    }
+    // Without any lanes for vehicles, there's no way to tell the sides of the road apart
+    let (left, middle, right) = match split_sides(lanes_ltr) {
+        Some(sides) => sides,
+        None => return original.clone(),
+    };
+    if middle.iter().any(|lane| lane.lt == LaneType::Construction) {
+        return original.clone();
+    }
+
+    let mut tags = original.clone();
+    let had_cycleway = original.inner().keys().any(|k| k.starts_with("cycleway"));
+    let had_parking = original
+        .inner()
+        .keys()
+        .any(|k| k.starts_with("parking:lane"));
+    for key in LANE_KEYS {
+        tags.remove(key);
+    }
+
+    // Lanes in each direction, left-to-right when facing that direction
+    let is_travel_lane = |lane: &&LaneSpec| matches!(lane.lt, LaneType::Driving | LaneType::Bus);
+    let fwd_lanes: Vec<&LaneSpec> = middle
+        .iter()
+        .filter(is_travel_lane)
+        .filter(|lane| lane.dir == Direction::Fwd)
+        .collect();
+    let back_lanes: Vec<&LaneSpec> = middle
+        .iter()
+        .rev()
+        .filter(is_travel_lane)
+        .filter(|lane| lane.dir == Direction::Back)
+        .collect();
+    // Lanes used in both directions: shared center turn lanes, and travel lanes like the single
+    // lane of a narrow two-way road
+    let both_ways: Vec<LaneSpec> = middle
+        .iter()
+        .filter(|lane| {
+            lane.lt == LaneType::SharedLeftTurn
+                || (is_travel_lane(lane) && lane.dir == Direction::Both)
+        })
+        .map(|lane| {
+            let mut lane = lane.clone();
+            if lane.lt == LaneType::SharedLeftTurn {
+                lane.turn_restrictions = vec!["left".to_string()];
+            }
+            lane
+        })
+        .collect();
+    let both_ways_lanes: Vec<&LaneSpec> = both_ways.iter().collect();
+    let travels = |dir: Direction| {
+        middle
+            .iter()
+            .filter(is_travel_lane)
+            .any(|lane| lane.dir.includes(dir))
+    };
+    let oneway = match (travels(Direction::Fwd), travels(Direction::Back)) {
+        (true, false) => Some(Direction::Fwd),
+        (false, true) => Some(Direction::Back),
+        _ => None,
+    };
+
+    let total = fwd_lanes.len() + back_lanes.len() + both_ways_lanes.len();
+    tags.insert("lanes", total.to_string());
+    match oneway {
+        Some(Direction::Fwd) => {
+            tags.insert("oneway", "yes");
+            write_per_lane(&mut tags, "", &fwd_lanes);
+        }
+        Some(Direction::Back) => {
+            tags.insert("oneway", "-1");
+            write_per_lane(&mut tags, "", &back_lanes);
+        }
+        None if fwd_lanes.is_empty() && back_lanes.is_empty() => {
+            // Every lane is shared by both directions, like lanes=1 on a narrow two-way road.
+            // lanes:both_ways would mean a center turn lane here.
+            tags.remove("oneway");
+            write_per_lane(&mut tags, "", &both_ways_lanes);
+        }
+        None => {
+            tags.remove("oneway");
+            tags.insert("lanes:forward", fwd_lanes.len().to_string());
+            tags.insert("lanes:backward", back_lanes.len().to_string());
+            write_per_lane(&mut tags, ":forward", &fwd_lanes);
+            write_per_lane(&mut tags, ":backward", &back_lanes);
+        }
+    }
+    if !both_ways_lanes.is_empty() && !(fwd_lanes.is_empty() && back_lanes.is_empty()) {
+        tags.insert("lanes:both_ways", both_ways_lanes.len().to_string());
+        write_per_lane(&mut tags, ":both_ways", &both_ways_lanes);
+    }
+
+    // Bike lanes on each side normally go the same way as the traffic next to them
+    let (left_dir, right_dir) = match (oneway, cfg.driving_side) {
+        (Some(dir), _) => (dir, dir),
+        (None, DrivingSide::Right) => (Direction::Back, Direction::Fwd),
+        (None, DrivingSide::Left) => (Direction::Fwd, Direction::Back),
+    };
+    // Order each side from the road outwards
+    let left_outwards: Vec<&LaneSpec> = left.iter().rev().collect();
+    let right_outwards: Vec<&LaneSpec> = right.iter().collect();
+    let mut parking = Vec::new();
+    for (side, lanes, default_dir) in [
+        ("left", left_outwards, left_dir),
+        ("right", right_outwards, right_dir),
+    ] {
+        write_cycleway(&mut tags, original, side, &lanes, default_dir, had_cycleway);
+        parking.push(if lanes.iter().any(|lane| lane.lt == LaneType::Parking) {
+            Some(parking_value(original, side))
+        } else {
+            None
+        });
+    }
+    match (&parking[0], &parking[1]) {
+        (Some(left), Some(right)) if left == right => {
+            tags.insert("parking:lane:both", left.clone());
+        }
+        (left, right) => {
+            for (side, value) in [("left", left), ("right", right)] {
+                if let Some(value) = value {
+                    tags.insert(format!("parking:lane:{side}"), value.clone());
+                } else if had_parking {
+                    tags.insert(format!("parking:lane:{side}"), "no");
+                }
+            }
+        }
+    }
+
+    // Don't write sidewalks that were only inferred, unless they've changed. A separately mapped
+    // sidewalk stays that way.
+    let sidewalks = (has_sidewalk(left), has_sidewalk(right));
+    let inferred = split_sides(&before)
+        .map(|(left, _, right)| (has_sidewalk(left), has_sidewalk(right)))
+        .unwrap_or((false, false));
+    if !original.is("sidewalk", "separate")
+        && (original.contains_key("sidewalk") || sidewalks != inferred)
+    {
+        let value = match sidewalks {
+            (true, true) => "both",
+            (true, false) => "left",
+            (false, true) => "right",
+            (false, false) => "no",
+        };
+        tags.insert("sidewalk", value);
+    }
+
+    tags
+}
+
+fn same_lanes(before: &[LaneSpec], after: &[LaneSpec]) -> bool {
+    before.len() == after.len()
+        && before.iter().zip(after).all(|(a, b)| {
+            a.lt == b.lt && a.dir == b.dir && a.turn_restrictions == b.turn_restrictions
+        })
+}
+
+/// Splits lanes into those left of the roadway, the roadway itself, and those right of it
+fn split_sides(lanes_ltr: &[LaneSpec]) -> Option<(&[LaneSpec], &[LaneSpec], &[LaneSpec])> {
+    let is_roadway = |lane: &LaneSpec| {
+        matches!(
+            lane.lt,
+            LaneType::Driving | LaneType::Bus | LaneType::SharedLeftTurn | LaneType::Construction
+        )
+    };
+    let first = lanes_ltr.iter().position(is_roadway)?;
+    let last = lanes_ltr.iter().rposition(is_roadway)?;
+    Some((
+        &lanes_ltr[..first],
+        &lanes_ltr[first..=last],
+        &lanes_ltr[last + 1..],
+    ))
+}
+
+/// Writes `bus:lanes` and `turn:lanes`, with some suffix like `:forward`
+fn write_per_lane(tags: &mut Tags, suffix: &str, lanes: &[&LaneSpec]) {
+    if lanes.iter().any(|lane| lane.lt == LaneType::Bus) {
+        let values: Vec<&str> = lanes
+            .iter()
+            .map(|lane| {
+                if lane.lt == LaneType::Bus {
+                    "designated"
+                } else {
+                    ""
+                }
+            })
+            .collect();
+        tags.insert(format!("bus:lanes{suffix}"), values.join("|"));
+    }
+    if lanes.iter().any(|lane| !lane.turn_restrictions.is_empty()) {
+        let values: Vec<String> = lanes
+            .iter()
+            .map(|lane| lane.turn_restrictions.join(";"))
+            .collect();
+        tags.insert(format!("turn:lanes{suffix}"), values.join("|"));
+    }
+}
+
+/// Writes `cycleway:left` or `cycleway:right`, along with the direction and separation of the
+/// bike lanes. `lanes` are ordered from the road outwards.
+fn write_cycleway(
+    tags: &mut Tags,
+    original: &Tags,
+    side: &str,
+    lanes: &[&LaneSpec],
+    default_dir: Direction,
+    had_cycleway: bool,
+) {
+    let bike_lanes: Vec<usize> = lanes
+        .iter()
+        .enumerate()
+        .filter(|(_, lane)| lane.lt == LaneType::Biking)
+        .map(|(idx, _)| idx)
+        .collect();
+    if bike_lanes.is_empty() {
+        if had_cycleway {
+            tags.insert(format!("cycleway:{side}"), "no");
+        }
+        return;
+    }
+
+    // A buffer between the road and the bike lanes separates them
+    let buffer = lanes[..bike_lanes[0]]
+        .iter()
+        .find_map(|lane| match lane.lt {
+            LaneType::Buffer(buffer) => Some(buffer),
+            _ => None,
+        });
+    let was_track = original.is(&format!("cycleway:{side}"), "track")
+        || original.is("cycleway", "track")
+        || original.is("cycleway:both", "track");
+    let value = if was_track
+        || matches!(
+            buffer,
+            Some(BufferType::Curb | BufferType::JerseyBarrier | BufferType::Planters)
+        ) {
+        "track"
+    } else {
+        "lane"
+    };
+    tags.insert(format!("cycleway:{side}"), value);
+    if let Some(buffer) = buffer {
+        let inner_side = if side == "left" { "right" } else { "left" };
+        tags.insert(
+            format!("cycleway:{side}:separation:{inner_side}"),
+            osm_separation_value(buffer),
+        );
+    }
+
+    let fwd = bike_lanes
+        .iter()
+        .any(|idx| lanes[*idx].dir.includes(Direction::Fwd));
+    let back = bike_lanes
+        .iter()
+        .any(|idx| lanes[*idx].dir.includes(Direction::Back));
+    if fwd && back {
+        tags.insert(format!("cycleway:{side}:oneway"), "no");
+    } else if (fwd && default_dir == Direction::Back) || (back && default_dir == Direction::Fwd) {
+        tags.insert(format!("cycleway:{side}:oneway"), "-1");
+    }
+}
+
+/// Keeps the original kind of parking on this side, if there was any
+fn parking_value(original: &Tags, side: &str) -> String {
+    for key in [
+        format!("parking:lane:{side}"),
+        "parking:lane:both".to_string(),
+    ] {
+        if original.is_any(&key, vec!["parallel", "diagonal", "perpendicular"]) {
+            return original.get(&key).unwrap().clone();
+        }
+    }
+    "parallel".to_string()
+}
+
+fn has_sidewalk(lanes: &[LaneSpec]) -> bool {
+    lanes.iter().any(|lane| lane.lt == LaneType::Sidewalk)
+}
+
+/// The inverse of `osm_separation_type`
+fn osm_separation_value(buffer: BufferType) -> &'static str {
+    match buffer {
+        BufferType::Stripes => "solid_line",
+        BufferType::FlexPosts => "vertical_panel",
+        BufferType::Planters => "planter",
+        BufferType::JerseyBarrier => "jersey_barrier",
+        BufferType::Curb => "kerb",
+    }
+}
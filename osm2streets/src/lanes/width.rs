@@ -0,0 +1,63 @@
+use abstutil::Tags;
+use geom::Distance;
+
+use super::WidthProfile;
+use crate::LaneSpec;
+
+/// Applies `width:lanes`, and `width:lanes:start` and `width:lanes:end` for lanes whose width
+/// changes along the road, along with their `:forward`, `:backward`, and `:both_ways` forms. See
+/// `LaneSpec::indices_per_lanes_suffix` for the lanes each one describes.
+pub(crate) fn apply_width_lanes(lanes: &mut [LaneSpec], tags: &Tags) {
+    for (suffix, indices) in LaneSpec::indices_per_lanes_suffix(lanes) {
+        let width = parse_widths(tags, &format!("width{suffix}"), indices.len());
+        let start = parse_widths(tags, &format!("width{suffix}:start"), indices.len());
+        let end = parse_widths(tags, &format!("width{suffix}:end"), indices.len());
+        if width.is_none() && start.is_none() && end.is_none() {
+            continue;
+        }
+
+        for (pos, idx) in indices.into_iter().enumerate() {
+            let lane = &mut lanes[idx];
+            if let Some(width) = width.as_ref().and_then(|values| values[pos]) {
+                lane.width = width;
+            }
+            let start = start.as_ref().and_then(|values| values[pos]);
+            let end = end.as_ref().and_then(|values| values[pos]);
+            if start.is_some() || end.is_some() {
+                let start = start.unwrap_or(lane.width);
+                let end = end.unwrap_or(lane.width);
+                if start != end {
+                    lane.set_width_profile(WidthProfile::linear(start, end));
+                } else {
+                    lane.width = start;
+                }
+            }
+        }
+    }
+}
+
+/// Parses one width per lane, in meters. Blank or unparseable values are `None`.
+fn parse_widths(tags: &Tags, key: &str, num_lanes: usize) -> Option<Vec<Option<Distance>>> {
+    let raw = tags.get(key)?;
+    let values: Vec<Option<Distance>> = raw
+        .split('|')
+        .map(|value| {
+            value
+                .trim()
+                .trim_end_matches('m')
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|x| *x >= 0.0)
+                .map(Distance::meters)
+        })
+        .collect();
+    if values.len() != num_lanes {
+        warn!(
+            "{key}={raw} has {} values, but there are {num_lanes} lanes",
+            values.len()
+        );
+        return None;
+    }
+    Some(values)
+}
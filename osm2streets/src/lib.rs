@@ -12,30 +12,58 @@ use serde::{Deserialize, Serialize};
 use abstutil::{deserialize_btreemap, serialize_btreemap};
 use geom::{GPSBounds, PolyLine, Polygon, Pt2D};
 
+pub use self::access::{AccessLevel, AccessRules};
+pub use self::crossings::{Crossing, CrossingKind};
+pub use self::diff::{
+    IntersectionChange, IntersectionChangeKind, NetworkDiff, RoadChange, RoadChangeKind,
+};
+pub use self::features::{feature_direction, RoadFeature, RoadFeatureKind};
 pub use self::geometry::{intersection_polygon, InputRoad};
 pub(crate) use self::ids::RoadWithEndpoints;
 pub use self::ids::{CommonEndpoint, IntersectionID, OriginalRoad, RoadID};
 pub use self::intersection::{
     Intersection, IntersectionControl, IntersectionKind, Movement, TrafficConflict,
 };
+pub use self::invariants::InvariantError;
+pub use self::lane_movements::{LaneMovement, TurnType};
 pub use self::lanes::{
-    get_lane_specs_ltr, BufferType, Direction, LaneSpec, LaneType, NORMAL_LANE_THICKNESS,
-    SIDEWALK_THICKNESS,
+    compare_lane_parsers, get_lane_specs_ltr, get_lane_specs_ltr_with, lane_specs_to_osm_tags,
+    BufferType, Direction, LaneParser, LaneProvenance, LaneSpec, LaneType, WidthProfile,
+    NORMAL_LANE_THICKNESS, SIDEWALK_THICKNESS,
+};
+pub use self::operations::{ChangedObjects, UndoEdit};
+pub use self::pathfinding::{Route, RoutingGraph};
+pub use self::restrictions::{
+    parse_restrictions, ComplicatedTurnRestriction, DanglingProblem, DanglingTurnRestriction,
+    RestrictionConditions, RestrictionType, TurnRestriction,
 };
 pub use self::road::Road;
+pub use self::speed::{SpeedLimit, SpeedLimitSource, SpeedLimits, SpeedUnit};
+pub use self::time_window::TimeWindow;
 pub use self::transform::Transformation;
-pub use self::types::{DrivingSide, MapConfig, NamePerLanguage};
+pub use self::types::{DrivingSide, MapConfig, NamePerLanguage, TrafficInterruption, TravelMode};
 
+mod access;
+mod crossings;
+mod diff;
 mod edit;
+mod features;
 mod geometry;
 mod ids;
 mod intersection;
+mod invariants;
+mod lane_movements;
 mod lanes;
 mod operations;
 pub mod osm;
 mod pathfinding;
 mod render;
+mod restrictions;
 mod road;
+mod speed;
+#[cfg(any(test, feature = "testing"))]
+mod test_utils;
+mod time_window;
 mod transform;
 mod types;
 
@@ -106,11 +134,10 @@ impl StreetNetwork {
 
     pub fn remove_road(&mut self, id: RoadID) -> Road {
         for i in self.roads[&id].endpoints() {
-            self.intersections
-                .get_mut(&i)
-                .unwrap()
-                .roads
-                .retain(|r| *r != id);
+            let intersection = self.intersections.get_mut(&i).unwrap();
+            intersection.roads.retain(|r| *r != id);
+            intersection.signalized_approaches.remove(&id);
+            intersection.approach_controls.remove(&id);
             // Since the roads are already sorted, removing doesn't break the sort.
             self.update_movements(i);
         }
@@ -238,30 +265,3 @@ impl StreetNetwork {
         }
     }
 }
-
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum RestrictionType {
-    BanTurns,
-    OnlyAllowTurns,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct TurnRestriction(pub RoadID, pub RestrictionType, pub RoadID);
-
-impl RestrictionType {
-    pub fn new(restriction: &str) -> Option<RestrictionType> {
-        // TODO There's a huge space of things not represented yet: time conditions, bus-only, no
-        // right turn on red...
-
-        // There are so many possibilities:
-        // https://taginfo.openstreetmap.org/keys/restriction#values
-        // Just attempt to bucket into allow / deny.
-        if restriction.contains("no_") || restriction == "psv" {
-            Some(RestrictionType::BanTurns)
-        } else if restriction.contains("only_") {
-            Some(RestrictionType::OnlyAllowTurns)
-        } else {
-            None
-        }
-    }
-}
@@ -27,14 +27,59 @@ impl StreetNetwork {
             }
         }
 
+        // Removing the roads forgets which signalized intersections they approach
+        let signalized: Vec<IntersectionID> = self
+            .intersections
+            .values()
+            .filter(|x| {
+                x.id != i
+                    && (x.signalized_approaches.contains(&keep_r)
+                        || x.signalized_approaches.contains(&destroy_r))
+            })
+            .map(|x| x.id)
+            .collect();
+
         // We could be more careful merging highway_type, layer, name, and other attributes, but in
         // practice, it doesn't matter for the short segments we're merging.
         let mut keep_road = self.remove_road(keep_r);
         let destroy_road = self.remove_road(destroy_r);
-        self.intersections.remove(&i).unwrap();
+        let removed_i = self.intersections.remove(&i).unwrap();
+
+        // Rejoining two pieces of the same OSM way, like after `split_road`
+        let same_way = keep_road.osm_ids.first().map(|x| x.osm_way_id)
+            == destroy_road.osm_ids.first().map(|x| x.osm_way_id);
+        if same_way
+            && keep_road.dst_i == destroy_road.src_i
+            && keep_road.way_fractions.1 == destroy_road.way_fractions.0
+        {
+            keep_road.way_fractions.1 = destroy_road.way_fractions.1;
+        } else if same_way
+            && keep_road.src_i == destroy_road.dst_i
+            && destroy_road.way_fractions.1 == keep_road.way_fractions.0
+        {
+            keep_road.way_fractions.0 = destroy_road.way_fractions.0;
+        }
 
         // Remember the merge
         keep_road.osm_ids.extend(destroy_road.osm_ids);
+        // Short roads collapsed into the removed intersection are now part of the merged road
+        keep_road.osm_ids.extend(removed_i.collapsed_osm_roads);
+        // Restrictions from the far end of destroy_r now start from the merged road. Any between
+        // the two roads become meaningless and get cleaned up below.
+        keep_road
+            .turn_restrictions
+            .extend(destroy_road.turn_restrictions);
+        keep_road
+            .complicated_turn_restrictions
+            .extend(destroy_road.complicated_turn_restrictions);
+
+        // destroy_r points the opposite way of keep_r when they share both src_i or both dst_i
+        let reversed =
+            keep_road.dst_i == destroy_road.dst_i || keep_road.src_i == destroy_road.src_i;
+        keep_road.take_features(destroy_road.features, reversed);
+        keep_road
+            .speed_limits
+            .merge(destroy_road.speed_limits, reversed);
 
         // There are 4 cases, easy to understand on paper. Preserve the original direction of
         // keep_r. Work with points, not PolyLine::extend. We want to RDP simplify before
@@ -67,11 +112,19 @@ impl StreetNetwork {
         let epsilon = 1.0;
         keep_road.reference_line = PolyLine::must_new(Pt2D::simplify_rdp(new_pts, epsilon));
         keep_road.update_center_line(self.config.driving_side);
+        keep_road.update_features();
 
         // Keep the same ID, but fix the endpoints
         keep_road.src_i = new_src_i;
         keep_road.dst_i = new_dst_i;
         self.insert_road(keep_road);
+        for x in signalized {
+            self.intersections
+                .get_mut(&x)
+                .unwrap()
+                .signalized_approaches
+                .insert(keep_r);
+        }
 
         // We may need to fix up turn restrictions. destroy_r becomes keep_r.
         let rewrite = |x: &mut RoadID| {
@@ -80,14 +133,17 @@ impl StreetNetwork {
             }
         };
         for road in self.roads.values_mut() {
-            for (_, id) in &mut road.turn_restrictions {
-                rewrite(id);
+            for tr in &mut road.turn_restrictions {
+                rewrite(&mut tr.to);
             }
 
-            for (id1, id2) in &mut road.complicated_turn_restrictions {
-                rewrite(id1);
-                rewrite(id2);
+            for ctr in &mut road.complicated_turn_restrictions {
+                for id in &mut ctr.via {
+                    rewrite(id);
+                }
+                rewrite(&mut ctr.to);
             }
         }
+        self.normalize_turn_restrictions();
     }
 }
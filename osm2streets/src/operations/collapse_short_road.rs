@@ -1,9 +1,11 @@
 use std::collections::BTreeMap;
 
 use anyhow::Result;
+use geom::Distance;
 
 use crate::{
-    IntersectionControl, IntersectionID, IntersectionKind, RestrictionType, RoadID, StreetNetwork,
+    ComplicatedTurnRestriction, IntersectionControl, IntersectionID, IntersectionKind,
+    RestrictionType, Road, RoadID, StreetNetwork, TurnRestriction,
 };
 
 // TODO After collapsing a road, trying to drag the surviving intersection in map_editor crashes. I
@@ -36,7 +38,15 @@ impl StreetNetwork {
         // A previous call to this method on nearby roads could produce loop roads. If we later try
         // to collapse this, all we need to do is remove it.
         if keep_i == destroy_i {
-            self.remove_road(short_r);
+            let short_road = self.remove_road(short_r);
+            let neighbors = self.intersections[&keep_i].roads.clone();
+            self.move_features_to_neighbors(&short_road, &neighbors, &[]);
+            self.intersections
+                .get_mut(&keep_i)
+                .unwrap()
+                .collapsed_osm_roads
+                .extend(short_road.osm_ids);
+            self.remove_turn_restrictions_involving(short_r);
             return Ok((keep_i, keep_i));
         }
 
@@ -88,21 +98,38 @@ impl StreetNetwork {
                 .extend(trim_roads_for_merging);
         }
 
-        self.remove_road(short_r);
+        // Keep features along the short road (like a crossing in the middle of a complex junction)
+        // on the nearest road, before endpoints change below.
+        let short_road = self.remove_road(short_r);
+        self.move_features_to_neighbors(&short_road, &connected_to_keep_i, &connected_to_destroy_i);
 
         let destroy_i = self.intersections.remove(&destroy_i).unwrap();
 
-        // If the intersection types differ, upgrade the surviving interesting.
+        // If the intersection types differ, upgrade the surviving interesting. No signalized
+        // approaches means all of them are, so only combine specific lists.
         if destroy_i.control == IntersectionControl::Signalled {
-            self.intersections.get_mut(&keep_i).unwrap().control = IntersectionControl::Signalled;
+            let keep = self.intersections.get_mut(&keep_i).unwrap();
+            if keep.control != IntersectionControl::Signalled {
+                keep.signalized_approaches = destroy_i.signalized_approaches;
+            } else if keep.signalized_approaches.is_empty()
+                || destroy_i.signalized_approaches.is_empty()
+            {
+                keep.signalized_approaches.clear();
+            } else {
+                keep.signalized_approaches
+                    .extend(destroy_i.signalized_approaches);
+            }
+            keep.control = IntersectionControl::Signalled;
         }
 
         // Remember the merge
-        self.intersections
-            .get_mut(&keep_i)
-            .unwrap()
-            .osm_ids
-            .extend(destroy_i.osm_ids);
+        {
+            let keep = self.intersections.get_mut(&keep_i).unwrap();
+            keep.osm_ids.extend(destroy_i.osm_ids);
+            keep.collapsed_osm_roads
+                .extend(destroy_i.collapsed_osm_roads);
+            keep.collapsed_osm_roads.extend(short_road.osm_ids);
+        }
 
         // Fix the endpoint of all roads connected to destroy_i.
         for r in destroy_i.roads {
@@ -124,54 +151,297 @@ impl StreetNetwork {
         self.sort_roads(keep_i);
         self.update_movements(keep_i);
 
-        // TODO Fix up turn restrictions. Many cases:
-        // [ ] road we're deleting has simple restrictions
-        // [ ] road we're deleting has complicated restrictions
-        // [X] road we're deleting is the target of a simple BanTurns restriction
-        // [ ] road we're deleting is the target of a simple OnlyAllowTurns restriction
-        // [ ] road we're deleting is the target of a complicated restriction
-        // [X] road we're deleting is the 'via' of a complicated restriction
-        // [ ] road we're deleting has turn lanes that wind up orphaning something
+        // Fix up turn restrictions. Roads on the other side of the deleted road from some road
+        // are its successors (when heading into the deleted road) or predecessors (when leaving
+        // it). Depending if the intersection we kept is the one connecting them, these differ.
+        let other_side = |r: RoadID| -> Vec<RoadID> {
+            if connected_to_keep_i.contains(&r) {
+                connected_to_destroy_i.clone()
+            } else {
+                connected_to_keep_i.clone()
+            }
+        };
+
+        // Restrictions starting from the deleted road now start from each of its predecessors.
+        // Copying an OnlyAllowTurns restriction would stop the predecessor from turning anywhere
+        // else, even to roads it never reached through the deleted road. Instead, ban turns to
+        // the other successors.
+        for tr in short_road.turn_restrictions {
+            for pred in other_side(tr.to) {
+                if pred == tr.to {
+                    continue;
+                }
+                let mut new_trs = Vec::new();
+                match tr.restriction {
+                    RestrictionType::BanTurns => {
+                        new_trs.push(tr.clone());
+                    }
+                    RestrictionType::OnlyAllowTurns => {
+                        for alt in other_side(pred) {
+                            if alt != tr.to && alt != pred {
+                                new_trs.push(TurnRestriction {
+                                    restriction: RestrictionType::BanTurns,
+                                    to: alt,
+                                    conditions: tr.conditions.clone(),
+                                });
+                            }
+                        }
+                    }
+                }
+                self.roads
+                    .get_mut(&pred)
+                    .unwrap()
+                    .turn_restrictions
+                    .extend(new_trs);
+            }
+        }
+        for ctr in short_road.complicated_turn_restrictions {
+            for pred in other_side(ctr.via[0]) {
+                self.roads
+                    .get_mut(&pred)
+                    .unwrap()
+                    .complicated_turn_restrictions
+                    .push(ctr.clone());
+            }
+        }
 
-        // If we're deleting the target of a simple restriction somewhere, update it.
         for road in self.roads.values_mut() {
+            // If we're deleting the target of a simple restriction somewhere, replace it with a
+            // new one to each of the successors of the deleted road.
             let mut fix_trs = Vec::new();
-            for (rt, to) in road.turn_restrictions.drain(..) {
-                if to == short_r && rt == RestrictionType::BanTurns {
-                    // Remove this restriction, and replace it with a new one to each of the
-                    // successors of the deleted road. Depending if the intersection we kept is the
-                    // one connecting these two roads, the successors differ.
-                    if connected_to_keep_i.contains(&road.id) {
-                        for x in &connected_to_destroy_i {
-                            fix_trs.push((rt, *x));
-                        }
-                    } else {
-                        for x in &connected_to_keep_i {
-                            fix_trs.push((rt, *x));
-                        }
+            for tr in road.turn_restrictions.drain(..) {
+                if tr.to == short_r {
+                    for x in other_side(road.id) {
+                        fix_trs.push(TurnRestriction {
+                            to: x,
+                            ..tr.clone()
+                        });
                     }
                 } else {
-                    fix_trs.push((rt, to));
+                    fix_trs.push(tr);
                 }
             }
             road.turn_restrictions = fix_trs;
-        }
 
-        // If we're deleting the 'via' of a complicated restriction somewhere, change it to a
-        // simple restriction.
-        for road in self.roads.values_mut() {
-            let mut add = Vec::new();
-            road.complicated_turn_restrictions.retain(|(via, to)| {
-                if *via == short_r {
-                    add.push((RestrictionType::BanTurns, *to));
-                    false
+            // Do the same for complicated restrictions, and also remove the deleted road when it's
+            // one of the via roads.
+            let mut fix_ctrs = Vec::new();
+            for mut ctr in road.complicated_turn_restrictions.drain(..) {
+                ctr.via.retain(|r| *r != short_r);
+                if ctr.to == short_r {
+                    let prev = ctr.via.last().cloned().unwrap_or(road.id);
+                    for x in other_side(prev) {
+                        fix_ctrs.push(ComplicatedTurnRestriction {
+                            to: x,
+                            ..ctr.clone()
+                        });
+                    }
                 } else {
-                    true
+                    fix_ctrs.push(ctr);
                 }
-            });
-            road.turn_restrictions.extend(add);
+            }
+            road.complicated_turn_restrictions = fix_ctrs;
         }
+        // This turns complicated restrictions without any via roads left into simple ones.
+        self.normalize_turn_restrictions();
 
         Ok((keep_i, destroy_i.id))
     }
+
+    /// Moves the features along a road being collapsed onto the nearest road that was connected
+    /// to it. `before` are the roads connected to its `src_i` and `after` to its `dst_i`; their
+    /// endpoints must not have changed yet.
+    fn move_features_to_neighbors(
+        &mut self,
+        short_road: &Road,
+        before: &[RoadID],
+        after: &[RoadID],
+    ) {
+        for feature in &short_road.features {
+            let mut best: Option<(RoadID, Distance)> = None;
+            for r in before.iter().chain(after) {
+                let pl = &self.roads[r].reference_line;
+                let dist = pl.project_pt(feature.pt).dist_to(feature.pt);
+                if best.map(|(_, best_dist)| dist < best_dist).unwrap_or(true) {
+                    best = Some((*r, dist));
+                }
+            }
+            let r = match best {
+                Some((r, _)) => r,
+                None => {
+                    warn!(
+                        "Dropping {:?} on {}, since nothing's connected to it",
+                        feature.kind, short_road.id
+                    );
+                    continue;
+                }
+            };
+
+            let road = self.roads.get_mut(&r).unwrap();
+            // Roads before the short one point the same way if they lead into it, and roads after
+            // if they lead away from it.
+            let reversed = if before.contains(&r) {
+                road.src_i == short_road.src_i
+            } else {
+                road.dst_i == short_road.dst_i
+            };
+            road.take_features(vec![feature.clone()], reversed);
+            road.update_features();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use abstutil::Timer;
+    use geom::Pt2D;
+
+    use super::*;
+    use crate::{
+        osm, CrossingKind, Direction, OriginalRoad, RestrictionConditions, RoadFeatureKind,
+        Transformation,
+    };
+
+    #[test]
+    fn test_collapse_only_allow_restriction() {
+        // A short road from A to B. Roads from the west and south reach A; roads to the east and
+        // north leave B.
+        let mut streets = StreetNetwork::blank();
+        let a = streets.add_test_intersection(0.0, 0.0);
+        let b = streets.add_test_intersection(20.0, 0.0);
+        let west_i = streets.add_test_intersection(-100.0, 0.0);
+        let south_i = streets.add_test_intersection(0.0, 100.0);
+        let east_i = streets.add_test_intersection(120.0, 0.0);
+        let north_i = streets.add_test_intersection(20.0, -100.0);
+        let short = streets.add_test_road(a, b, "dd", "v^");
+        let west = streets.add_test_road(west_i, a, "dd", "v^");
+        let south = streets.add_test_road(south_i, a, "dd", "v^");
+        let east = streets.add_test_road(b, east_i, "dd", "v^");
+        let north = streets.add_test_road(b, north_i, "dd", "v^");
+
+        // From the short road, only going east is allowed
+        streets
+            .roads
+            .get_mut(&short)
+            .unwrap()
+            .turn_restrictions
+            .push(TurnRestriction {
+                restriction: RestrictionType::OnlyAllowTurns,
+                to: east,
+                conditions: RestrictionConditions::default(),
+            });
+
+        streets.collapse_short_road(short).unwrap();
+
+        // Roads that reached the short road can't go north anymore, but can still turn between
+        // each other
+        let ban_north = vec![TurnRestriction {
+            restriction: RestrictionType::BanTurns,
+            to: north,
+            conditions: RestrictionConditions::default(),
+        }];
+        assert_eq!(streets.roads[&west].turn_restrictions, ban_north);
+        assert_eq!(streets.roads[&south].turn_restrictions, ban_north);
+        assert!(streets.roads[&west].allowed_to_turn_to(east));
+        assert!(streets.roads[&west].allowed_to_turn_to(south));
+        assert!(streets.roads[&east].turn_restrictions.is_empty());
+        assert!(streets.roads[&north].turn_restrictions.is_empty());
+    }
+
+    #[test]
+    fn test_collapse_keeps_features() {
+        // A junction road from A to B, with a crossing near B on its north side
+        let mut streets = StreetNetwork::blank();
+        let a = streets.add_test_intersection(0.0, 0.0);
+        let b = streets.add_test_intersection(20.0, 0.0);
+        let west_i = streets.add_test_intersection(-100.0, 0.0);
+        let east_i = streets.add_test_intersection(120.0, 0.0);
+        let north_i = streets.add_test_intersection(20.0, -100.0);
+        let short = streets.add_test_road(a, b, "dd", "v^");
+        streets.add_test_road(west_i, a, "dd", "v^");
+        streets.add_test_road(b, east_i, "dd", "v^");
+        let north = streets.add_test_road(north_i, b, "dd", "v^");
+        {
+            let road = streets.roads.get_mut(&short).unwrap();
+            road.internal_junction_road = true;
+            road.add_feature(
+                RoadFeatureKind::Crossing(CrossingKind::Marked),
+                osm::NodeID(1),
+                Pt2D::new(15.0, -4.0),
+                Some(Direction::Fwd),
+            );
+        }
+
+        streets.apply_transformations(
+            vec![Transformation::CollapseShortRoads],
+            &mut Timer::throwaway(),
+        );
+        assert!(!streets.roads.contains_key(&short));
+
+        // The crossing moves to the closest road. That road points towards the short one, so the
+        // direction flips.
+        for road in streets.roads.values() {
+            if road.id == north {
+                assert_eq!(road.features.len(), 1);
+                let feature = &road.features[0];
+                assert_eq!(
+                    feature.kind,
+                    RoadFeatureKind::Crossing(CrossingKind::Marked)
+                );
+                assert_eq!(feature.pt, Pt2D::new(15.0, -4.0));
+                assert_eq!(feature.direction, Some(Direction::Back));
+            } else {
+                assert!(road.features.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_road_by_original_after_collapse() {
+        // Way 100 goes from the west through A to B. The short road from A to B is collapsed.
+        let mut streets = StreetNetwork::blank();
+        let a = streets.add_test_intersection(0.0, 0.0);
+        let b = streets.add_test_intersection(20.0, 0.0);
+        let west_i = streets.add_test_intersection(-100.0, 0.0);
+        let east_i = streets.add_test_intersection(120.0, 0.0);
+        let north_i = streets.add_test_intersection(20.0, -100.0);
+        let short = streets.add_test_road(a, b, "dd", "v^");
+        let west = streets.add_test_road(west_i, a, "dd", "v^");
+        let east = streets.add_test_road(b, east_i, "dd", "v^");
+        let north = streets.add_test_road(b, north_i, "dd", "v^");
+        let mut originals = BTreeMap::new();
+        for (r, way, i1, i2) in [
+            (short, 100, 2, 3),
+            (west, 100, 1, 2),
+            (east, 300, 3, 4),
+            (north, 200, 3, 5),
+        ] {
+            let orig = OriginalRoad::new(way, (i1, i2));
+            streets.roads.get_mut(&r).unwrap().osm_ids = vec![orig];
+            originals.insert(r, orig);
+        }
+
+        streets.collapse_short_road(short).unwrap();
+
+        // The short road's segment is now part of the intersection, and the road continuing the
+        // same way stands in for it
+        assert_eq!(
+            streets.find_intersection_by_collapsed_road(originals[&short]),
+            Some(a)
+        );
+        assert_eq!(streets.find_road_by_original(originals[&short]), Some(west));
+        for r in [west, east, north] {
+            assert_eq!(streets.find_road_by_original(originals[&r]), Some(r));
+            assert_eq!(
+                streets.find_intersection_by_collapsed_road(originals[&r]),
+                None
+            );
+        }
+
+        let mut expected = BTreeMap::new();
+        for (r, orig) in originals {
+            expected.insert(orig, if r == short { west } else { r });
+        }
+        assert_eq!(streets.original_road_lookup(), expected);
+    }
 }
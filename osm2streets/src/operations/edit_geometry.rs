@@ -0,0 +1,555 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use geom::{Distance, PolyLine, Pt2D};
+
+use crate::{
+    Intersection, IntersectionControl, IntersectionID, IntersectionKind, Road, RoadID,
+    StreetNetwork,
+};
+
+/// The state of every road and intersection before an edit touched them, so the edit can be
+/// reverted. `None` means the object didn't exist yet.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct UndoEdit {
+    pub roads: BTreeMap<RoadID, Option<Road>>,
+    pub intersections: BTreeMap<IntersectionID, Option<Intersection>>,
+}
+
+impl StreetNetwork {
+    /// Moves an intersection, dragging the ends of every connected road along with it.
+    pub fn move_intersection(&mut self, i: IntersectionID, pt: Pt2D) -> Result<UndoEdit> {
+        let mut new_lines = Vec::new();
+        for r in &self.intersections[&i].roads {
+            let road = &self.roads[r];
+            let mut pts = road.reference_line.clone().into_points();
+            if road.src_i == i {
+                pts[0] = pt;
+            }
+            if road.dst_i == i {
+                *pts.last_mut().unwrap() = pt;
+            }
+            match PolyLine::new(pts) {
+                Ok(pl) => new_lines.push((*r, pl)),
+                Err(err) => bail!("Moving {i} breaks {r}: {err}"),
+            }
+        }
+
+        let mut affected = BTreeSet::from([i]);
+        for (r, _) in &new_lines {
+            affected.extend(self.roads[r].endpoints());
+        }
+        let extra_roads = self.roads_at(&affected);
+        let undo = self.snapshot(extra_roads, affected.clone());
+
+        self.intersections.get_mut(&i).unwrap().point = pt;
+        for (r, pl) in new_lines {
+            self.forget_trim_for_merging(r);
+            let road = self.roads.get_mut(&r).unwrap();
+            road.reference_line = pl;
+            road.update_features();
+        }
+        self.finish_geometry_edit(&affected);
+        Ok(undo)
+    }
+
+    /// Replaces a road's `reference_line`. The first and last points always stay at the road's
+    /// intersections; use `move_intersection` to change those.
+    pub fn set_reference_line(&mut self, r: RoadID, pl: PolyLine) -> Result<UndoEdit> {
+        let road = &self.roads[&r];
+        let mut pts = pl.into_points();
+        pts[0] = self.intersections[&road.src_i].point;
+        *pts.last_mut().unwrap() = self.intersections[&road.dst_i].point;
+        let pl = PolyLine::new(pts)?;
+
+        let affected: BTreeSet<IntersectionID> = road.endpoints().into_iter().collect();
+        let extra_roads = self.roads_at(&affected);
+        let undo = self.snapshot(extra_roads, affected.clone());
+
+        self.forget_trim_for_merging(r);
+        let road = self.roads.get_mut(&r).unwrap();
+        road.reference_line = pl;
+        road.update_features();
+        self.finish_geometry_edit(&affected);
+        Ok(undo)
+    }
+
+    /// Splits a road in two at some distance along its `reference_line`, with a new intersection
+    /// between the pieces. The original road keeps its ID and the first piece; the new road has
+    /// the same tags and lanes. Lane widths and placement varying along the road are split too.
+    /// Returns the new intersection and road.
+    pub fn split_road(
+        &mut self,
+        r: RoadID,
+        dist: Distance,
+    ) -> Result<(IntersectionID, RoadID, UndoEdit)> {
+        let road = &self.roads[&r];
+        if dist <= Distance::ZERO || dist >= road.reference_line.length() {
+            bail!(
+                "Can't split {r} at {dist}; it's {} long",
+                road.reference_line.length()
+            );
+        }
+        // Width profiles and varying placement are split at the same point
+        let fraction = dist / road.reference_line.length();
+        let first_line = road
+            .reference_line
+            .maybe_exact_slice(Distance::ZERO, dist)?;
+        let second_line = road
+            .reference_line
+            .maybe_exact_slice(dist, road.reference_line.length())?;
+        let (src_i, dst_i) = (road.src_i, road.dst_i);
+        if src_i == dst_i {
+            bail!("Can't split {r}, because it's a loop");
+        }
+
+        let mut extra_roads = self.roads_at(&BTreeSet::from([src_i, dst_i]));
+        extra_roads.extend(self.roads_referencing(r));
+        let mut undo = self.snapshot(extra_roads, BTreeSet::from([src_i, dst_i]));
+
+        let new_i = self.insert_intersection(
+            Vec::new(),
+            first_line.last_pt(),
+            IntersectionKind::Connection,
+            IntersectionControl::Uncontrolled,
+        );
+        undo.intersections.insert(new_i, None);
+
+        // Pick the ID before removing the old road, so it can't be reused
+        let osm_ids = self.roads[&r].osm_ids.clone();
+        let new_r = self.new_road_id(&osm_ids);
+        undo.roads.insert(new_r, None);
+        let mut old_road = self.remove_road(r);
+        let mut new_road = old_road.clone();
+        new_road.id = new_r;
+
+        old_road.dst_i = new_i;
+        old_road.reference_line = first_line;
+        new_road.src_i = new_i;
+        new_road.reference_line = second_line;
+        let (from, to) = old_road.way_fractions;
+        let middle = from + (to - from) * fraction;
+        old_road.way_fractions = (from, middle);
+        new_road.way_fractions = (middle, to);
+        for lane in &mut old_road.lane_specs_ltr {
+            lane.slice_width_profile(0.0, fraction);
+        }
+        for lane in &mut new_road.lane_specs_ltr {
+            lane.slice_width_profile(fraction, 1.0);
+        }
+        // Features and restrictions at the far end move to the new road
+        old_road.features.retain(|f| f.dist < dist);
+        new_road.features.retain(|f| f.dist >= dist);
+        let at_src: BTreeSet<RoadID> = self.intersections[&src_i].roads.iter().cloned().collect();
+        let (restrictions_at_src, restrictions_at_dst) = old_road
+            .turn_restrictions
+            .drain(..)
+            .partition(|tr| at_src.contains(&tr.to));
+        old_road.turn_restrictions = restrictions_at_src;
+        new_road.turn_restrictions = restrictions_at_dst;
+        let (complicated_at_src, complicated_at_dst) = old_road
+            .complicated_turn_restrictions
+            .drain(..)
+            .partition(|ctr| at_src.contains(&ctr.via[0]));
+        old_road.complicated_turn_restrictions = complicated_at_src;
+        new_road.complicated_turn_restrictions = complicated_at_dst;
+        for road in [&mut old_road, &mut new_road] {
+            road.update_center_line(self.config.driving_side);
+            road.update_features();
+        }
+
+        let intersection = self.intersections.get_mut(&dst_i).unwrap();
+        if intersection.signalized_approaches.remove(&r) {
+            intersection.signalized_approaches.insert(new_r);
+        }
+        if let Some(control) = intersection.approach_controls.remove(&r) {
+            intersection.approach_controls.insert(new_r, control);
+        }
+        if let Some(pt) = intersection.trim_roads_for_merging.remove(&(r, false)) {
+            intersection
+                .trim_roads_for_merging
+                .insert((new_r, false), pt);
+        }
+
+        // Restrictions from roads at dst_i into the old road now lead into the new one. Sequences
+        // passing through the old road now pass through both pieces, in the direction travelled.
+        for road in self.roads.values_mut() {
+            for tr in &mut road.turn_restrictions {
+                if tr.to == r && !at_src.contains(&road.id) {
+                    tr.to = new_r;
+                }
+            }
+            for ctr in &mut road.complicated_turn_restrictions {
+                let mut prev = road.id;
+                let mut via = Vec::new();
+                for x in std::mem::take(&mut ctr.via) {
+                    if x != r {
+                        via.push(x);
+                    } else if at_src.contains(&prev) {
+                        via.extend([r, new_r]);
+                    } else {
+                        via.extend([new_r, r]);
+                    }
+                    prev = x;
+                }
+                if ctr.to == r && !at_src.contains(&prev) {
+                    ctr.to = new_r;
+                }
+                ctr.via = via;
+            }
+        }
+
+        self.insert_road(old_road);
+        self.insert_road(new_road);
+        self.finish_geometry_edit(&BTreeSet::from([src_i, dst_i, new_i]));
+        Ok((new_i, new_r, undo))
+    }
+
+    /// Joins the two roads meeting at an intersection into one, removing the intersection. The
+    /// first road's tags and lanes are kept. Fails if the intersection doesn't have exactly two
+    /// roads, or they form a loop.
+    pub fn join_roads(&mut self, i: IntersectionID) -> Result<UndoEdit> {
+        let roads = self.intersections[&i].roads.clone();
+        if roads.len() != 2 {
+            bail!(
+                "Can't join roads at {i}, because it has {} roads",
+                roads.len()
+            );
+        }
+
+        let mut affected = BTreeSet::from([i]);
+        for r in &roads {
+            affected.extend(self.roads[r].endpoints());
+        }
+        let mut extra_roads = self.roads_at(&affected);
+        for r in &roads {
+            extra_roads.extend(self.roads_referencing(*r));
+        }
+        let mut undo = self.snapshot(extra_roads, affected.clone());
+
+        self.collapse_intersection(i);
+        if self.intersections.contains_key(&i) {
+            bail!("Can't join roads at {i}, because they form a loop");
+        }
+        affected.remove(&i);
+        self.finish_geometry_edit(&affected);
+        Ok(undo)
+    }
+
+    /// Reverts an edit. Returns the undo data for reverting the undo.
+    pub fn undo(&mut self, undo: UndoEdit) -> UndoEdit {
+        let redo = self.snapshot(
+            undo.roads.keys().cloned().collect(),
+            undo.intersections.keys().cloned().collect(),
+        );
+        for (id, road) in undo.roads {
+            match road {
+                Some(road) => {
+                    self.roads.insert(id, road);
+                }
+                None => {
+                    self.roads.remove(&id);
+                }
+            }
+        }
+        for (id, intersection) in undo.intersections {
+            match intersection {
+                Some(intersection) => {
+                    self.intersections.insert(id, intersection);
+                }
+                None => {
+                    self.intersections.remove(&id);
+                }
+            }
+        }
+        redo
+    }
+
+    fn snapshot(
+        &self,
+        roads: BTreeSet<RoadID>,
+        intersections: BTreeSet<IntersectionID>,
+    ) -> UndoEdit {
+        UndoEdit {
+            roads: roads
+                .into_iter()
+                .map(|r| (r, self.roads.get(&r).cloned()))
+                .collect(),
+            intersections: intersections
+                .into_iter()
+                .map(|i| (i, self.intersections.get(&i).cloned()))
+                .collect(),
+        }
+    }
+
+    fn roads_at(&self, intersections: &BTreeSet<IntersectionID>) -> BTreeSet<RoadID> {
+        intersections
+            .iter()
+            .flat_map(|i| self.intersections[i].roads.clone())
+            .collect()
+    }
+
+    /// Roads with a turn restriction involving `r`
+    fn roads_referencing(&self, r: RoadID) -> BTreeSet<RoadID> {
+        self.roads
+            .values()
+            .filter(|road| {
+                road.turn_restrictions.iter().any(|tr| tr.to == r)
+                    || road
+                        .complicated_turn_restrictions
+                        .iter()
+                        .any(|ctr| ctr.to == r || ctr.via.contains(&r))
+            })
+            .map(|road| road.id)
+            .collect()
+    }
+
+    /// Trim points remembered from collapsing short roads no longer apply to a road whose shape
+    /// changed.
+    fn forget_trim_for_merging(&mut self, r: RoadID) {
+        let road = &self.roads[&r];
+        let (src_i, dst_i) = (road.src_i, road.dst_i);
+        for (i, is_src) in [(src_i, true), (dst_i, false)] {
+            self.intersections
+                .get_mut(&i)
+                .unwrap()
+                .trim_roads_for_merging
+                .remove(&(r, is_src));
+        }
+    }
+
+    /// Re-sorts roads, then recalculates geometry and movements around some intersections.
+    fn finish_geometry_edit(&mut self, intersections: &BTreeSet<IntersectionID>) {
+        for i in intersections {
+            self.sort_roads(*i);
+        }
+        self.regenerate_geometry_around(intersections);
+        for i in intersections {
+            if self.intersections.contains_key(i) {
+                self.update_movements(*i);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lanes::{LtrLaneNum, Placement, RoadPosition};
+    use crate::{
+        ComplicatedTurnRestriction, RestrictionConditions, RestrictionType, TurnRestriction,
+        WidthProfile,
+    };
+
+    fn ban(to: RoadID) -> TurnRestriction {
+        TurnRestriction {
+            restriction: RestrictionType::BanTurns,
+            to,
+            conditions: RestrictionConditions::default(),
+        }
+    }
+
+    fn ban_via(via: Vec<RoadID>, to: RoadID) -> ComplicatedTurnRestriction {
+        ComplicatedTurnRestriction {
+            restriction: RestrictionType::BanTurns,
+            via,
+            to,
+            conditions: RestrictionConditions::default(),
+        }
+    }
+
+    #[test]
+    fn test_undo_round_trip() {
+        // i0 --r0-- i1 --r1-- i2 --r3-- i4
+        //           |
+        //           r2
+        //           |
+        //           i3
+        let mut original = StreetNetwork::blank();
+        let i0 = original.add_test_intersection(0.0, 0.0);
+        let i1 = original.add_test_intersection(100.0, 0.0);
+        let i2 = original.add_test_intersection(200.0, 0.0);
+        let i3 = original.add_test_intersection(100.0, 100.0);
+        let i4 = original.add_test_intersection(300.0, 0.0);
+        let r0 = original.add_test_road(i0, i1, "sdds", "vv^^");
+        let r1 = original.add_test_road(i1, i2, "sdds", "vv^^");
+        original.add_test_road(i1, i3, "sdds", "vv^^");
+        original.add_test_road(i2, i4, "sdds", "vv^^");
+        original
+            .roads
+            .get_mut(&r0)
+            .unwrap()
+            .turn_restrictions
+            .push(ban(r1));
+
+        let edits: Vec<(&str, Box<dyn Fn(&mut StreetNetwork) -> UndoEdit>)> = vec![
+            (
+                "move_intersection",
+                Box::new(move |streets: &mut StreetNetwork| {
+                    streets
+                        .move_intersection(i1, Pt2D::new(110.0, 10.0))
+                        .unwrap()
+                }),
+            ),
+            (
+                "split_road",
+                Box::new(move |streets: &mut StreetNetwork| {
+                    streets.split_road(r1, Distance::meters(50.0)).unwrap().2
+                }),
+            ),
+            (
+                "join_roads",
+                Box::new(move |streets: &mut StreetNetwork| streets.join_roads(i2).unwrap()),
+            ),
+        ];
+        for (description, edit) in edits {
+            let mut streets = original.clone();
+            let undo = edit(&mut streets);
+            let edited = streets.clone();
+            assert_ne!(
+                streets.roads, original.roads,
+                "{description} didn't change anything"
+            );
+
+            let redo = streets.undo(undo);
+            assert_eq!(streets.roads, original.roads, "undoing {description}");
+            assert_eq!(
+                streets.intersections, original.intersections,
+                "undoing {description}"
+            );
+
+            streets.undo(redo);
+            assert_eq!(streets.roads, edited.roads, "redoing {description}");
+            assert_eq!(
+                streets.intersections, edited.intersections,
+                "redoing {description}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_road_restrictions() {
+        // The road from A to B gets split. Each end has roads on both sides.
+        //
+        //                   north_i
+        //                      |
+        // west_i --- A ------- B --- east_i
+        //            |
+        //         south_i
+        let mut streets = StreetNetwork::blank();
+        let a = streets.add_test_intersection(0.0, 0.0);
+        let b = streets.add_test_intersection(200.0, 0.0);
+        let west_i = streets.add_test_intersection(-100.0, 0.0);
+        let east_i = streets.add_test_intersection(300.0, 0.0);
+        let south_i = streets.add_test_intersection(0.0, 100.0);
+        let north_i = streets.add_test_intersection(200.0, -100.0);
+        let r = streets.add_test_road(a, b, "dd", "v^");
+        let west = streets.add_test_road(west_i, a, "dd", "v^");
+        let east = streets.add_test_road(b, east_i, "dd", "v^");
+        let south = streets.add_test_road(south_i, a, "dd", "v^");
+        let north = streets.add_test_road(b, north_i, "dd", "v^");
+
+        streets.roads.get_mut(&r).unwrap().turn_restrictions = vec![ban(south), ban(north)];
+        streets.roads.get_mut(&west).unwrap().turn_restrictions = vec![ban(r)];
+        streets.roads.get_mut(&east).unwrap().turn_restrictions = vec![ban(r)];
+        streets
+            .roads
+            .get_mut(&south)
+            .unwrap()
+            .complicated_turn_restrictions = vec![ban_via(vec![r], north)];
+        streets
+            .roads
+            .get_mut(&north)
+            .unwrap()
+            .complicated_turn_restrictions = vec![ban_via(vec![r], south)];
+
+        let (_, new_r, _) = streets.split_road(r, Distance::meters(100.0)).unwrap();
+
+        // Restrictions stay at the same end of the road
+        assert_eq!(streets.roads[&r].turn_restrictions, vec![ban(south)]);
+        assert_eq!(streets.roads[&new_r].turn_restrictions, vec![ban(north)]);
+        assert_eq!(streets.roads[&west].turn_restrictions, vec![ban(r)]);
+        assert_eq!(streets.roads[&east].turn_restrictions, vec![ban(new_r)]);
+        // Sequences pass through both pieces, in the direction travelled
+        assert_eq!(
+            streets.roads[&south].complicated_turn_restrictions,
+            vec![ban_via(vec![r, new_r], north)]
+        );
+        assert_eq!(
+            streets.roads[&north].complicated_turn_restrictions,
+            vec![ban_via(vec![new_r, r], south)]
+        );
+    }
+
+    #[test]
+    fn test_split_road_varying_along() {
+        let mut streets = StreetNetwork::blank();
+        let i0 = streets.add_test_intersection(0.0, 0.0);
+        let i1 = streets.add_test_intersection(100.0, 0.0);
+        let i2 = streets.add_test_intersection(0.0, 100.0);
+        let i3 = streets.add_test_intersection(100.0, 100.0);
+        let driving_side = streets.config.driving_side;
+
+        // The road shifts from its center to the middle of the forward lane
+        let placed = streets.add_test_road(i0, i1, "dd", "v^");
+        let road = streets.roads.get_mut(&placed).unwrap();
+        road.reference_line_placement = Placement::Varying(
+            RoadPosition::Center,
+            RoadPosition::MiddleOf(LtrLaneNum::Forward(1)),
+        );
+        road.update_center_line(driving_side);
+        let center_line = road.center_line.clone();
+
+        // The forward lane widens from 3m to 5m
+        let widening = streets.add_test_road(i2, i3, "dd", "v^");
+        streets.roads.get_mut(&widening).unwrap().lane_specs_ltr[1].set_width_profile(
+            WidthProfile::linear(Distance::meters(3.0), Distance::meters(5.0)),
+        );
+
+        let (_, placed2, _) = streets.split_road(placed, Distance::meters(25.0)).unwrap();
+        let (_, widening2, _) = streets
+            .split_road(widening, Distance::meters(50.0))
+            .unwrap();
+
+        // Splitting trims the roads, so compare the untrimmed center lines
+        let untrimmed = |r: RoadID| {
+            let mut road = streets.roads[&r].clone();
+            road.update_center_line(driving_side);
+            road.center_line
+        };
+        let close = |pt1: Pt2D, pt2: Pt2D| pt1.approx_eq(pt2, Distance::meters(0.01));
+        assert_eq!(streets.roads[&placed].way_fractions, (0.0, 0.25));
+        assert_eq!(streets.roads[&placed2].way_fractions, (0.25, 1.0));
+        let (first, second) = (untrimmed(placed), untrimmed(placed2));
+        assert!(close(first.first_pt(), center_line.first_pt()));
+        assert!(close(
+            first.last_pt(),
+            center_line.must_dist_along(Distance::meters(25.0)).0
+        ));
+        assert!(close(second.first_pt(), first.last_pt()));
+        assert!(close(second.last_pt(), center_line.last_pt()));
+
+        let width = |r: RoadID, fraction: f64| {
+            streets.roads[&r].lane_specs_ltr[1]
+                .width_at(fraction)
+                .inner_meters()
+        };
+        for (r, fraction, expected) in [
+            (widening, 0.0, 3.0),
+            (widening, 1.0, 4.0),
+            (widening2, 0.0, 4.0),
+            (widening2, 1.0, 5.0),
+        ] {
+            assert!(
+                (width(r, fraction) - expected).abs() < 0.01,
+                "width of {r} at {fraction}"
+            );
+        }
+        assert_eq!(
+            streets.roads[&widening].lane_specs_ltr[1].width,
+            Distance::meters(4.0)
+        );
+    }
+}
@@ -2,3 +2,8 @@
 
 mod collapse_intersection;
 mod collapse_short_road;
+mod edit_geometry;
+mod update_tags;
+
+pub use self::edit_geometry::UndoEdit;
+pub use self::update_tags::ChangedObjects;
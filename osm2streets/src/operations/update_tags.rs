@@ -0,0 +1,176 @@
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use abstutil::{Tags, Timer};
+use geom::{Distance, PolyLine, Pt2D};
+
+use crate::transform::intersection_geometry;
+use crate::{osm, IntersectionID, MapConfig, Road, RoadID, StreetNetwork};
+
+/// The roads and intersections touched by an incremental update. Some of the intersections may
+/// have been removed.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChangedObjects {
+    pub roads: BTreeSet<RoadID>,
+    pub intersections: BTreeSet<IntersectionID>,
+}
+
+impl StreetNetwork {
+    /// Replaces the OSM tags of every road belonging to one way, then re-derives only what depends
+    /// on them: those roads' lanes and other attributes, the movements, kind, and polygon of their
+    /// endpoints, and the trimmed geometry of other roads connected there. This is much cheaper
+    /// than rerunning `Transformation::GenerateIntersectionGeometry`. Transformations that already
+    /// ran, like merging short roads, aren't repeated.
+    pub fn update_osm_tags_for_way(
+        &mut self,
+        way: osm::WayID,
+        tags: &Tags,
+    ) -> Result<ChangedObjects> {
+        let roads: Vec<RoadID> = self
+            .roads
+            .values()
+            .filter(|r| r.osm_ids.iter().any(|x| x.osm_way_id == way))
+            .map(|r| r.id)
+            .collect();
+        if roads.is_empty() {
+            bail!("No roads belong to {way}");
+        }
+
+        let config = self.config.clone();
+        for r in &roads {
+            self.roads
+                .get_mut(r)
+                .unwrap()
+                .update_from_tags(tags.clone(), &config)?;
+        }
+        for r in &roads {
+            self.resolve_transition_placement(*r);
+        }
+
+        let mut intersections = BTreeSet::new();
+        for r in &roads {
+            intersections.extend(self.roads[r].endpoints());
+        }
+        let mut changed = self.regenerate_geometry_around(&intersections);
+        for i in &intersections {
+            if self.intersections.contains_key(i) {
+                self.update_movements(*i);
+            }
+        }
+        // Crossings or the roads they cross may have changed
+        self.update_crossings();
+        changed.roads.extend(roads);
+        Ok(changed)
+    }
+
+    /// Recalculates the polygons of some intersections and the trimmed geometry of every road
+    /// connected to them. Roads keep their existing trimming at other intersections.
+    pub fn regenerate_geometry_around(
+        &mut self,
+        intersections: &BTreeSet<IntersectionID>,
+    ) -> ChangedObjects {
+        let mut changed = ChangedObjects {
+            roads: BTreeSet::new(),
+            intersections: intersections.clone(),
+        };
+        for i in intersections {
+            if let Some(intersection) = self.intersections.get(i) {
+                changed.roads.extend(intersection.roads.iter().cloned());
+            }
+        }
+
+        // Untrim each road only at the ends being regenerated
+        let driving_side = self.config.driving_side;
+        for r in &changed.roads {
+            let road = self.roads.get_mut(r).unwrap();
+            let trimmed = road.center_line.clone();
+            road.update_center_line(driving_side);
+            let untrimmed = road.center_line.clone();
+
+            let start = if intersections.contains(&road.src_i) {
+                None
+            } else {
+                let dist = find_trimmed_end(&untrimmed, trimmed.first_pt());
+                if dist.is_none() {
+                    warn!(
+                        "Can't keep the trimming of {r} at {}, so it'll overlap that intersection",
+                        road.src_i
+                    );
+                }
+                dist
+            };
+            let end = if intersections.contains(&road.dst_i) {
+                None
+            } else {
+                let dist = find_trimmed_end(&untrimmed, trimmed.last_pt());
+                if dist.is_none() {
+                    warn!(
+                        "Can't keep the trimming of {r} at {}, so it'll overlap that intersection",
+                        road.dst_i
+                    );
+                }
+                dist
+            };
+            if start.is_some() || end.is_some() {
+                let start = start.unwrap_or(Distance::ZERO);
+                let end = end.unwrap_or_else(|| untrimmed.length());
+                if let Ok(pl) = untrimmed.maybe_exact_slice(start, end) {
+                    road.center_line = pl;
+                }
+            }
+        }
+
+        let intersections: Vec<IntersectionID> = intersections
+            .iter()
+            .filter(|i| self.intersections.contains_key(i))
+            .cloned()
+            .collect();
+        intersection_geometry::generate_for(self, &intersections, &mut Timer::throwaway());
+        changed
+    }
+}
+
+/// Finds how far along an untrimmed center line one end of the trimmed line was. If the end isn't
+/// exactly on the untrimmed line, like when the new tags changed the road's placement, the closest
+/// point is used.
+fn find_trimmed_end(untrimmed: &PolyLine, pt: Pt2D) -> Option<Distance> {
+    untrimmed
+        .dist_along_of_point(pt)
+        .or_else(|| untrimmed.dist_along_of_point(untrimmed.project_pt(pt)))
+        .map(|(dist, _)| dist)
+}
+
+impl Road {
+    /// Re-derives everything that comes from OSM tags, like lanes, after the tags change. Geometry,
+    /// turn restrictions, and features along the road are kept. Fails without changing anything if
+    /// the new tags don't describe a road.
+    pub fn update_from_tags(&mut self, tags: Tags, config: &MapConfig) -> Result<()> {
+        if !tags.contains_key(osm::HIGHWAY) && !tags.contains_key("railway") {
+            bail!("{} needs a highway or railway tag", self.id);
+        }
+        let fresh = Road::new(
+            self.id,
+            self.osm_ids.clone(),
+            self.src_i,
+            self.dst_i,
+            self.reference_line.clone(),
+            tags,
+            config,
+        );
+        self.highway_type = fresh.highway_type;
+        self.name = fresh.name;
+        self.internal_junction_road = fresh.internal_junction_road;
+        self.layer = fresh.layer;
+        self.crossing = fresh.crossing;
+        self.access = fresh.access;
+        self.speed_limits = fresh.speed_limits;
+        self.reference_line_placement = fresh.reference_line_placement;
+        self.lane_specs_ltr = fresh.lane_specs_ltr;
+        self.lane_provenance = fresh.lane_provenance;
+        self.slice_width_profiles_to_way();
+        self.update_center_line(config.driving_side);
+        Ok(())
+    }
+}
@@ -1,14 +1,20 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
 use geom::Distance;
 use petgraph::graphmap::DiGraphMap;
 
-use crate::{Direction, IntersectionID, LaneType, RoadID, StreetNetwork};
+use crate::{
+    Direction, Intersection, IntersectionID, LaneType, Movement, Road, RoadID, StreetNetwork,
+    TravelMode,
+};
 
 // A/B Street's map_model has lots of pathfinding support at both a road segment and lane level.
 // This is a delibrately simple subset of functionality for now.
 
 impl StreetNetwork {
     /// Calculates a rough driving distance between intersections, excluding the turning movement
-    /// through intersections.
+    /// through intersections. Use a `RoutingGraph` to respect movements and make many queries.
     pub fn path_dist_to(&self, from: IntersectionID, to: IntersectionID) -> Option<Distance> {
         let mut graph = DiGraphMap::new();
         for r in self.roads.values() {
@@ -31,18 +37,27 @@ impl StreetNetwork {
         from: IntersectionID,
         to: IntersectionID,
         lane_types: &[LaneType],
+    ) -> Option<Vec<(RoadID, Direction)>> {
+        self.simple_path_for_mode(from, to, lane_types, TravelMode::Car)
+    }
+
+    /// Calculates a path between intersections, using only lanes of the given types that the mode
+    /// is allowed to use. The result says which direction to cross each road.
+    pub fn simple_path_for_mode(
+        &self,
+        from: IntersectionID,
+        to: IntersectionID,
+        lane_types: &[LaneType],
+        mode: TravelMode,
     ) -> Option<Vec<(RoadID, Direction)>> {
         let mut graph = DiGraphMap::new();
         for r in self.roads.values() {
             let mut fwd = false;
             let mut back = false;
-            for lane in &r.lane_specs_ltr {
-                if lane_types.contains(&lane.lt) {
-                    if lane.dir == Direction::Fwd {
-                        fwd = true;
-                    } else {
-                        back = true;
-                    }
+            for (idx, lane) in r.lane_specs_ltr.iter().enumerate() {
+                if lane_types.contains(&lane.lt) && r.lane_allows(idx, mode) {
+                    fwd |= lane.dir.includes(Direction::Fwd);
+                    back |= lane.dir.includes(Direction::Back);
                 }
             }
             if fwd {
@@ -66,3 +81,581 @@ impl StreetNetwork {
         Some(roads)
     }
 }
+
+/// A graph for finding paths for one mode, built once and reused for many queries. Paths follow
+/// the lanes the mode may use, `Intersection::movements` for cars, and turn restrictions for other
+/// vehicles. All vehicles follow complicated turn restrictions via entire roads.
+pub struct RoutingGraph {
+    mode: TravelMode,
+    graph: DiGraphMap<Node, Distance>,
+    /// Sequences of roads that may not be crossed in order, from `banned_sequences`
+    banned: Vec<Vec<RoadID>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Node {
+    /// Where paths from an intersection begin
+    Start(IntersectionID),
+    /// Where paths to an intersection end
+    End(IntersectionID),
+    /// Crossing a road in one direction
+    Road(RoadID, Direction),
+}
+
+/// A node during a search, along with the last few roads crossed to get there. Only as many roads
+/// are remembered as could still be the start of a banned sequence, usually none.
+type State = (Node, Vec<RoadID>);
+
+/// A path found by a `RoutingGraph`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    /// Each road crossed, in order, and the direction it's crossed
+    pub steps: Vec<(RoadID, Direction)>,
+    /// The total length of the trimmed roads crossed
+    pub distance: Distance,
+}
+
+impl RoutingGraph {
+    /// Roads are measured along their trimmed `center_line`, so this should be built after
+    /// intersection geometry is generated.
+    pub fn new(streets: &StreetNetwork, mode: TravelMode) -> Self {
+        let mut graph = DiGraphMap::new();
+        for road in streets.roads.values() {
+            let length = road.center_line.length();
+            for dir in directions(road, mode) {
+                let (from, to) = match dir {
+                    Direction::Fwd => (road.src_i, road.dst_i),
+                    Direction::Back => (road.dst_i, road.src_i),
+                    Direction::Both => unreachable!(),
+                };
+                graph.add_edge(Node::Start(from), Node::Road(road.id, dir), length);
+                graph.add_edge(Node::Road(road.id, dir), Node::End(to), Distance::ZERO);
+            }
+        }
+
+        for i in streets.intersections.values() {
+            for (from, to) in turns(streets, i, mode) {
+                let from_dir = if streets.roads[&from].dst_i == i.id {
+                    Direction::Fwd
+                } else {
+                    Direction::Back
+                };
+                let to_dir = if streets.roads[&to].src_i == i.id {
+                    Direction::Fwd
+                } else {
+                    Direction::Back
+                };
+                let from_node = Node::Road(from, from_dir);
+                let to_node = Node::Road(to, to_dir);
+                if graph.contains_node(from_node) && graph.contains_node(to_node) {
+                    graph.add_edge(from_node, to_node, streets.roads[&to].center_line.length());
+                }
+            }
+        }
+
+        // Pedestrians don't follow turn restrictions
+        let banned = if mode == TravelMode::Walk {
+            Vec::new()
+        } else {
+            streets.banned_sequences(mode, None)
+        };
+
+        Self {
+            mode,
+            graph,
+            banned,
+        }
+    }
+
+    pub fn mode(&self) -> TravelMode {
+        self.mode
+    }
+
+    /// Finds the shortest path between two intersections.
+    pub fn path(&self, from: IntersectionID, to: IntersectionID) -> Option<Route> {
+        if from == to {
+            return Some(Route {
+                steps: Vec::new(),
+                distance: Distance::ZERO,
+            });
+        }
+
+        let (costs, prev) = self.search(from, Some(to), None);
+        let mut state = (Node::End(to), Vec::new());
+        let distance = *costs.get(&state)?;
+        let mut steps = Vec::new();
+        while let Some(prev_state) = prev.get(&state) {
+            if let Node::Road(r, dir) = prev_state.0 {
+                steps.push((r, dir));
+            }
+            state = prev_state.clone();
+        }
+        steps.reverse();
+        Some(Route { steps, distance })
+    }
+
+    /// Finds the distance to every intersection reachable from one intersection, optionally only
+    /// up to some limit. This is useful for isochrones.
+    pub fn distances_from(
+        &self,
+        from: IntersectionID,
+        limit: Option<Distance>,
+    ) -> BTreeMap<IntersectionID, Distance> {
+        let mut results = BTreeMap::new();
+        results.insert(from, Distance::ZERO);
+        for ((node, _), cost) in self.search(from, None, limit).0 {
+            if let Node::End(i) = node {
+                results.insert(i, cost);
+            }
+        }
+        results
+    }
+
+    /// Calculates the distance from every source to every target. `result[a][b]` is the distance
+    /// from `sources[a]` to `targets[b]`, or None if there's no path.
+    ///
+    /// This is no faster than calling `distances_from` for each source: it's just N separate
+    /// single-source searches for N sources, with nothing shared between them. The cost grows with
+    /// the number of sources times the size of the graph, so keep `sources` small.
+    pub fn many_to_many(
+        &self,
+        sources: &[IntersectionID],
+        targets: &[IntersectionID],
+    ) -> Vec<Vec<Option<Distance>>> {
+        sources
+            .iter()
+            .map(|from| {
+                let distances = self.distances_from(*from, None);
+                targets
+                    .iter()
+                    .map(|to| distances.get(to).copied())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Runs Dijkstra's algorithm from one intersection, stopping early after reaching `to` or
+    /// going past `limit`. Returns the cost to reach each state and the state it was reached
+    /// from. `End` nodes are always reached with no remembered roads.
+    fn search(
+        &self,
+        from: IntersectionID,
+        to: Option<IntersectionID>,
+        limit: Option<Distance>,
+    ) -> (HashMap<State, Distance>, HashMap<State, State>) {
+        let mut costs = HashMap::new();
+        let mut prev = HashMap::new();
+        if !self.graph.contains_node(Node::Start(from)) {
+            return (costs, prev);
+        }
+        let start = (Node::Start(from), Vec::new());
+
+        let mut queue = BinaryHeap::new();
+        costs.insert(start.clone(), Distance::ZERO);
+        queue.push(Reverse((Distance::ZERO, start)));
+        while let Some(Reverse((cost, state))) = queue.pop() {
+            if cost > costs[&state] {
+                continue;
+            }
+            if to.map(Node::End) == Some(state.0) {
+                break;
+            }
+            for (_, next, weight) in self.graph.edges(state.0) {
+                let next_cost = cost + *weight;
+                if limit.map(|limit| next_cost > limit).unwrap_or(false) {
+                    continue;
+                }
+                let history = match next {
+                    Node::Road(r, _) => {
+                        let mut history = state.1.clone();
+                        history.push(r);
+                        if self.banned.iter().any(|seq| history.ends_with(seq)) {
+                            continue;
+                        }
+                        self.trim_history(history)
+                    }
+                    Node::Start(_) | Node::End(_) => Vec::new(),
+                };
+                let next = (next, history);
+                if costs.get(&next).map(|x| next_cost < *x).unwrap_or(true) {
+                    costs.insert(next.clone(), next_cost);
+                    prev.insert(next.clone(), state.clone());
+                    queue.push(Reverse((next_cost, next)));
+                }
+            }
+        }
+        (costs, prev)
+    }
+
+    /// Forgets the roads crossed longer ago than the start of any banned sequence they could still
+    /// turn into. If a banned sequence ends with the next road, its beginning is still remembered.
+    fn trim_history(&self, mut history: Vec<RoadID>) -> Vec<RoadID> {
+        while !history.is_empty()
+            && !self
+                .banned
+                .iter()
+                .any(|seq| seq.len() > history.len() && seq.starts_with(&history))
+        {
+            history.remove(0);
+        }
+        history
+    }
+}
+
+/// Which directions can a mode travel along a road?
+fn directions(road: &Road, mode: TravelMode) -> Vec<Direction> {
+    let mut fwd = false;
+    let mut back = false;
+    for (idx, lane) in road.lane_specs_ltr.iter().enumerate() {
+        if !road.lane_allows(idx, mode) {
+            continue;
+        }
+        // Pedestrians can walk in both directions, no matter how the lane is oriented
+        let both = mode == TravelMode::Walk;
+        fwd |= both || lane.dir.includes(Direction::Fwd);
+        back |= both || lane.dir.includes(Direction::Back);
+    }
+
+    let mut results = Vec::new();
+    if fwd {
+        results.push(Direction::Fwd);
+    }
+    if back {
+        results.push(Direction::Back);
+    }
+    results
+}
+
+/// The turns a mode can make through an intersection, as pairs of roads
+fn turns(streets: &StreetNetwork, i: &Intersection, mode: TravelMode) -> Vec<Movement> {
+    match mode {
+        TravelMode::Car => i.movements.clone(),
+        TravelMode::Bus | TravelMode::Hgv => {
+            streets.calculate_movements_and_kind_for(i.id, mode, None).0
+        }
+        TravelMode::Bike | TravelMode::Walk => {
+            let mut results = Vec::new();
+            for from in &i.roads {
+                for to in &i.roads {
+                    // Pedestrians don't follow turn restrictions
+                    if from != to
+                        && (mode == TravelMode::Walk
+                            || streets.roads[from].allowed_to_turn_to_for(*to, mode, None))
+                    {
+                        results.push((*from, *to));
+                    }
+                }
+            }
+            results
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ComplicatedTurnRestriction, LaneSpec, RestrictionConditions, RestrictionType,
+        TurnRestriction,
+    };
+
+    // A triangle hanging off a dead-end. The diagonal from i3 to i1 is a one-way.
+    //
+    // i0 --r0-- i1 --r1-- i2
+    //             `        |
+    //              r2      r3
+    //                `     |
+    //                  `- i3
+    fn triangle() -> (StreetNetwork, Vec<IntersectionID>, Vec<RoadID>) {
+        let mut streets = StreetNetwork::blank();
+        let i0 = streets.add_test_intersection(0.0, 0.0);
+        let i1 = streets.add_test_intersection(100.0, 0.0);
+        let i2 = streets.add_test_intersection(200.0, 0.0);
+        let i3 = streets.add_test_intersection(200.0, 100.0);
+        let r0 = streets.add_test_road(i0, i1, "sdds", "vv^^");
+        let r1 = streets.add_test_road(i1, i2, "sdds", "vv^^");
+        let r2 = streets.add_test_road(i3, i1, "sdds", "v^^^");
+        let r3 = streets.add_test_road(i2, i3, "sdds", "vv^^");
+        (streets, vec![i0, i1, i2, i3], vec![r0, r1, r2, r3])
+    }
+
+    fn length(streets: &StreetNetwork, roads: &[RoadID]) -> Distance {
+        let mut total = Distance::ZERO;
+        for r in roads {
+            total = total + streets.roads[r].center_line.length();
+        }
+        total
+    }
+
+    #[test]
+    fn test_routing_graph_paths() {
+        let (streets, i, r) = triangle();
+        let (fwd, back) = (Direction::Fwd, Direction::Back);
+
+        let mut banned = streets.clone();
+        banned
+            .roads
+            .get_mut(&r[2])
+            .unwrap()
+            .turn_restrictions
+            .push(TurnRestriction {
+                restriction: RestrictionType::BanTurns,
+                to: r[0],
+                conditions: RestrictionConditions::default(),
+            });
+        banned.update_movements(i[1]);
+
+        let mut missing = streets.clone();
+        missing
+            .intersections
+            .get_mut(&i[1])
+            .unwrap()
+            .movements
+            .retain(|m| *m != (r[2], r[0]));
+
+        // The one-way is a shared-use path instead
+        let mut shared_use = streets.clone();
+        shared_use.roads.get_mut(&r[2]).unwrap().lane_specs_ltr =
+            LaneSpec::create_for_test("F", "^");
+
+        let mut ok = true;
+        for (description, streets, mode, from, to, expected) in vec![
+            (
+                "driving can't go the wrong way down the one-way",
+                &streets,
+                TravelMode::Car,
+                i[0],
+                i[3],
+                vec![(r[0], fwd), (r[1], fwd), (r[3], fwd)],
+            ),
+            (
+                "walking can go against the one-way",
+                &streets,
+                TravelMode::Walk,
+                i[0],
+                i[3],
+                vec![(r[0], fwd), (r[2], back)],
+            ),
+            (
+                "driving takes the one-way when it can",
+                &streets,
+                TravelMode::Car,
+                i[3],
+                i[0],
+                vec![(r[2], fwd), (r[0], back)],
+            ),
+            (
+                "driving avoids a banned turn",
+                &banned,
+                TravelMode::Car,
+                i[3],
+                i[0],
+                vec![(r[3], back), (r[1], back), (r[0], back)],
+            ),
+            (
+                "cycling avoids a banned turn",
+                &banned,
+                TravelMode::Bike,
+                i[3],
+                i[0],
+                vec![(r[3], back), (r[1], back), (r[0], back)],
+            ),
+            (
+                "driving avoids a missing movement",
+                &missing,
+                TravelMode::Car,
+                i[3],
+                i[0],
+                vec![(r[3], back), (r[1], back), (r[0], back)],
+            ),
+            (
+                "walking can go against a one-way shared-use path",
+                &shared_use,
+                TravelMode::Walk,
+                i[0],
+                i[3],
+                vec![(r[0], fwd), (r[2], back)],
+            ),
+            (
+                "cycling can't go against a one-way shared-use path",
+                &shared_use,
+                TravelMode::Bike,
+                i[0],
+                i[3],
+                vec![(r[0], fwd), (r[1], fwd), (r[3], fwd)],
+            ),
+        ] {
+            let roads: Vec<RoadID> = expected.iter().map(|(r, _)| *r).collect();
+            let expected = Route {
+                distance: length(streets, &roads),
+                steps: expected,
+            };
+            let actual = RoutingGraph::new(streets, mode).path(from, to);
+            if actual.as_ref() != Some(&expected) {
+                ok = false;
+                println!("For {description}");
+                println!("Got: {actual:?}");
+                println!("Expected: {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+
+    #[test]
+    fn test_routing_graph_distances() {
+        let (mut streets, i, r) = triangle();
+
+        // Pretend the first road was trimmed by intersection geometry
+        let road = streets.roads.get_mut(&r[0]).unwrap();
+        road.center_line = road.center_line.exact_slice(
+            Distance::meters(10.0),
+            road.center_line.length() - Distance::meters(10.0),
+        );
+        let trimmed = road.center_line.length();
+        assert!(trimmed < road.untrimmed_length());
+
+        let graph = RoutingGraph::new(&streets, TravelMode::Car);
+        assert_eq!(graph.path(i[0], i[1]).unwrap().distance, trimmed);
+
+        let mut expected = BTreeMap::new();
+        expected.insert(i[0], Distance::ZERO);
+        expected.insert(i[1], trimmed);
+        assert_eq!(
+            graph.distances_from(i[0], Some(trimmed + Distance::meters(50.0))),
+            expected
+        );
+        expected.insert(i[2], length(&streets, &[r[0], r[1]]));
+        expected.insert(i[3], length(&streets, &[r[0], r[1], r[3]]));
+        assert_eq!(graph.distances_from(i[0], None), expected);
+
+        // An intersection only reachable by going the wrong way down a one-way
+        let mut streets = streets;
+        let i4 = streets.add_test_intersection(300.0, 200.0);
+        streets.add_test_road(i4, i[3], "dd", "^^");
+        let graph = RoutingGraph::new(&streets, TravelMode::Car);
+
+        let all = vec![i[0], i[1], i[2], i[3], i4];
+        let matrix = graph.many_to_many(&all, &all);
+        for (a, from) in all.iter().enumerate() {
+            for (b, to) in all.iter().enumerate() {
+                assert_eq!(
+                    matrix[a][b],
+                    graph.path(*from, *to).map(|route| route.distance),
+                    "from {from} to {to}"
+                );
+            }
+        }
+        assert_eq!(matrix[0][4], None);
+    }
+
+    #[test]
+    fn test_routing_graph_complicated_restrictions() {
+        // A straight road, with a detour in the middle
+        //
+        // i0 --r0-- i1 --r1-- i2 --r2-- i3
+        //             `       /
+        //             r3    r4
+        //               `  /
+        //                i4
+        let mut streets = StreetNetwork::blank();
+        let i0 = streets.add_test_intersection(0.0, 0.0);
+        let i1 = streets.add_test_intersection(100.0, 0.0);
+        let i2 = streets.add_test_intersection(200.0, 0.0);
+        let i3 = streets.add_test_intersection(300.0, 0.0);
+        let i4 = streets.add_test_intersection(150.0, 100.0);
+        let r0 = streets.add_test_road(i0, i1, "sdds", "vv^^");
+        let r1 = streets.add_test_road(i1, i2, "sdds", "vv^^");
+        let r2 = streets.add_test_road(i2, i3, "sdds", "vv^^");
+        let r3 = streets.add_test_road(i1, i4, "sdds", "vv^^");
+        let r4 = streets.add_test_road(i4, i2, "sdds", "vv^^");
+        let fwd = Direction::Fwd;
+
+        // Coming from r0, don't go straight through r1 onto r2
+        let mut banned = streets.clone();
+        banned
+            .roads
+            .get_mut(&r0)
+            .unwrap()
+            .complicated_turn_restrictions
+            .push(ComplicatedTurnRestriction {
+                restriction: RestrictionType::BanTurns,
+                via: vec![r1],
+                to: r2,
+                conditions: RestrictionConditions::default(),
+            });
+
+        let mut ok = true;
+        for (description, streets, mode, from, to, expected) in vec![
+            (
+                "straight without the restriction",
+                &streets,
+                TravelMode::Car,
+                i0,
+                i3,
+                vec![(r0, fwd), (r1, fwd), (r2, fwd)],
+            ),
+            (
+                "driving takes the detour",
+                &banned,
+                TravelMode::Car,
+                i0,
+                i3,
+                vec![(r0, fwd), (r3, fwd), (r4, fwd), (r2, fwd)],
+            ),
+            (
+                "cycling takes the detour",
+                &banned,
+                TravelMode::Bike,
+                i0,
+                i3,
+                vec![(r0, fwd), (r3, fwd), (r4, fwd), (r2, fwd)],
+            ),
+            (
+                "walking ignores the restriction",
+                &banned,
+                TravelMode::Walk,
+                i0,
+                i3,
+                vec![(r0, fwd), (r1, fwd), (r2, fwd)],
+            ),
+            (
+                "the via road can still be used",
+                &banned,
+                TravelMode::Car,
+                i0,
+                i2,
+                vec![(r0, fwd), (r1, fwd)],
+            ),
+            (
+                "starting partway through the sequence",
+                &banned,
+                TravelMode::Car,
+                i1,
+                i3,
+                vec![(r1, fwd), (r2, fwd)],
+            ),
+        ] {
+            let roads: Vec<RoadID> = expected.iter().map(|(r, _)| *r).collect();
+            let expected = Route {
+                distance: length(streets, &roads),
+                steps: expected,
+            };
+            let actual = RoutingGraph::new(streets, mode).path(from, to);
+            if actual.as_ref() != Some(&expected) {
+                ok = false;
+                println!("For {description}");
+                println!("Got: {actual:?}");
+                println!("Expected: {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+
+        // Distances follow the restriction too
+        let graph = RoutingGraph::new(&banned, TravelMode::Car);
+        let distances = graph.distances_from(i0, None);
+        assert_eq!(distances[&i2], length(&banned, &[r0, r1]));
+        assert_eq!(distances[&i3], length(&banned, &[r0, r3, r4, r2]));
+    }
+}
@@ -4,10 +4,13 @@ use std::io::Write;
 use std::path::Path;
 
 use anyhow::Result;
-use geom::{ArrowCap, Distance, Line, PolyLine, Polygon, Ring};
+use geom::{ArrowCap, Circle, Distance, Line, PolyLine, Polygon, Ring};
 
 use crate::road::RoadEdge;
-use crate::{DebugStreets, Direction, DrivingSide, Intersection, LaneType, StreetNetwork};
+use crate::{
+    Crossing, CrossingKind, DebugStreets, Direction, DrivingSide, Intersection, LaneParser,
+    LaneType, RoadID, StreetNetwork,
+};
 
 impl StreetNetwork {
     /// Saves the plain GeoJSON rendering to a file.
@@ -22,28 +25,75 @@ impl StreetNetwork {
     /// Generates a plain GeoJSON rendering with one polygon per road and intersection.
     pub fn to_geojson(&self) -> Result<String> {
         let mut pairs = Vec::new();
+        let crossings: BTreeMap<RoadID, Crossing> = self
+            .crossings()
+            .into_iter()
+            .map(|crossing| (crossing.road, crossing))
+            .collect();
 
         // Add a polygon per road
         for road in self.roads.values() {
-            pairs.push((
-                road.center_line
-                    .make_polygons(road.total_width())
-                    .to_geojson(Some(&self.gps_bounds)),
-                make_props(&[
-                    ("type", "road".into()),
+            let mut props = make_props(&[
+                ("type", "road".into()),
+                (
+                    "osm_way_ids",
+                    serde_json::Value::Array(
+                        road.osm_ids
+                            .iter()
+                            .map(|id| id.osm_way_id.0.into())
+                            .collect(),
+                    ),
+                ),
+                ("src_i", road.src_i.0.into()),
+                ("dst_i", road.dst_i.0.into()),
+            ]);
+            // Only separately mapped crossings get these, so other output is unchanged
+            if let Some(crossing) = crossings.get(&road.id) {
+                props.extend(make_props(&[
+                    ("crossing", format!("{:?}", crossing.kind).into()),
+                    (
+                        "crosses",
+                        serde_json::Value::Array(
+                            crossing.crosses.iter().map(|r| r.0.into()).collect(),
+                        ),
+                    ),
+                    (
+                        "sidewalks",
+                        serde_json::Value::Array(
+                            crossing.sidewalks.iter().map(|r| r.0.into()).collect(),
+                        ),
+                    ),
+                ]));
+            }
+            // Likewise, only roads not parsed by the classic algorithm, or with problems parsing
+            // lanes, get these
+            let provenance = &road.lane_provenance;
+            if provenance.parser != LaneParser::Classic
+                || !provenance.warnings.is_empty()
+                || provenance.disagreement.is_some()
+            {
+                props.extend(make_props(&[
+                    ("lane_parser", format!("{:?}", provenance.parser).into()),
                     (
-                        "osm_way_ids",
+                        "lane_warnings",
                         serde_json::Value::Array(
-                            road.osm_ids
+                            provenance
+                                .warnings
                                 .iter()
-                                .map(|id| id.osm_way_id.0.into())
+                                .cloned()
+                                .map(|x| x.into())
                                 .collect(),
                         ),
                     ),
-                    ("src_i", road.src_i.0.into()),
-                    ("dst_i", road.dst_i.0.into()),
-                ]),
-            ));
+                ]));
+                if let Some(ref disagreement) = provenance.disagreement {
+                    props.extend(make_props(&[(
+                        "lane_parser_disagreement",
+                        disagreement.clone().into(),
+                    )]));
+                }
+            }
+            pairs.push((road.get_polygon().to_geojson(Some(&self.gps_bounds)), props));
         }
 
         // Polygon per intersection
@@ -88,14 +138,13 @@ impl StreetNetwork {
         let mut pairs = Vec::new();
 
         for road in self.roads.values() {
-            for (lane, pl) in road
+            for (lane, polygon) in road
                 .lane_specs_ltr
                 .iter()
-                .zip(road.get_lane_center_lines().into_iter())
+                .zip(road.get_lane_polygons().into_iter())
             {
                 pairs.push((
-                    pl.make_polygons(lane.width)
-                        .to_geojson(Some(&self.gps_bounds)),
+                    polygon.to_geojson(Some(&self.gps_bounds)),
                     make_props(&[
                         ("type", format!("{:?}", lane.lt).into()),
                         ("road", road.id.0.into()),
@@ -139,11 +188,12 @@ impl StreetNetwork {
         for road in self.roads.values() {
             // Always oriented in the direction of the road
             let mut lane_centers = road.get_lane_center_lines();
+            let lane_edges = road.get_lane_edges();
 
             for (idx, pair) in road.lane_specs_ltr.windows(2).enumerate() {
                 // Generate a "center line" between lanes of different directions
                 if pair[0].dir != pair[1].dir {
-                    let between = lane_centers[idx].shift_right(pair[0].width / 2.0)?;
+                    let between = &lane_edges[idx + 1];
                     // TODO Ideally we would return a full LineString, and the caller would choose
                     // how to style these as thickened dashed lines.
                     // TODO We could also at least return a MultiPolygon here
@@ -162,7 +212,7 @@ impl StreetNetwork {
 
                 // Generate a "lane separator" between driving lanes only
                 if pair[0].lt == LaneType::Driving && pair[1].lt == LaneType::Driving {
-                    let between = lane_centers[idx].shift_right(pair[0].width / 2.0)?;
+                    let between = &lane_edges[idx + 1];
                     for poly in between.dashed_lines(
                         Distance::meters(0.25),
                         Distance::meters(1.0),
@@ -194,23 +244,39 @@ impl StreetNetwork {
                 let arrow_len = Distance::meters(1.75);
                 let thickness = Distance::meters(0.25);
                 for (pt, angle) in center.step_along(step_size, buffer_ends) {
-                    let arrow = PolyLine::must_new(vec![
-                        pt.project_away(arrow_len / 2.0, angle.opposite()),
-                        pt.project_away(arrow_len / 2.0, angle),
-                    ])
-                    .make_arrow(thickness * 2.0, ArrowCap::Triangle)
-                    .get_outer_ring()
-                    .to_outline(thickness / 2.0);
-                    pairs.push((
-                        arrow.to_geojson(gps_bounds),
-                        make_props(&[("type", "lane arrow".into())]),
-                    ));
+                    // Lanes used both ways get an arrow pointing each way from the same point
+                    let arrows = if lane.dir == Direction::Both {
+                        vec![
+                            vec![pt, pt.project_away(arrow_len / 2.0, angle)],
+                            vec![pt, pt.project_away(arrow_len / 2.0, angle.opposite())],
+                        ]
+                    } else {
+                        vec![vec![
+                            pt.project_away(arrow_len / 2.0, angle.opposite()),
+                            pt.project_away(arrow_len / 2.0, angle),
+                        ]]
+                    };
+                    for pts in arrows {
+                        let arrow = PolyLine::must_new(pts)
+                            .make_arrow(thickness * 2.0, ArrowCap::Triangle)
+                            .get_outer_ring()
+                            .to_outline(thickness / 2.0);
+                        pairs.push((
+                            arrow.to_geojson(gps_bounds),
+                            make_props(&[("type", "lane arrow".into())]),
+                        ));
+                    }
                 }
             }
 
             // Add stripes to show buffers. Ignore the type of the buffer for now -- we need to
             // decide all the types and how to render them.
-            for (lane, center) in road.lane_specs_ltr.iter().zip(lane_centers.iter()) {
+            for (idx, (lane, center)) in road
+                .lane_specs_ltr
+                .iter()
+                .zip(lane_centers.iter())
+                .enumerate()
+            {
                 if !matches!(lane.lt, LaneType::Buffer(_)) {
                     continue;
                 }
@@ -218,15 +284,15 @@ impl StreetNetwork {
                 // Mark the sides of the lane clearly
                 let thickness = Distance::meters(0.25);
                 pairs.push((
-                    center
-                        .must_shift_right((lane.width - thickness) / 2.0)
+                    lane_edges[idx]
+                        .must_shift_right(thickness / 2.0)
                         .make_polygons(thickness)
                         .to_geojson(gps_bounds),
                     make_props(&[("type", "buffer edge".into())]),
                 ));
                 pairs.push((
-                    center
-                        .must_shift_left((lane.width - thickness) / 2.0)
+                    lane_edges[idx + 1]
+                        .must_shift_left(thickness / 2.0)
                         .make_polygons(thickness)
                         .to_geojson(gps_bounds),
                     make_props(&[("type", "buffer edge".into())]),
@@ -235,14 +301,21 @@ impl StreetNetwork {
                 // Diagonal stripes along the lane
                 let step_size = Distance::meters(3.0);
                 let buffer_ends = Distance::meters(5.0);
-                for (center, angle) in center.step_along(step_size, buffer_ends) {
+                let mut dist = buffer_ends;
+                while dist <= center.length() - buffer_ends {
+                    let (pt, angle) = center.must_dist_along(dist);
+                    // The center may point backwards, but the width is along the road
+                    let mut fraction = dist / center.length();
+                    if lane.dir == Direction::Back {
+                        fraction = 1.0 - fraction;
+                    }
+                    let half_width = lane.width_at(road.untrimmed_fraction(fraction)) / 2.0;
+                    dist += step_size;
+
                     // Extend the stripes into the side lines
-                    let left =
-                        center.project_away(lane.width / 2.0 + thickness, angle.rotate_degs(45.0));
-                    let right = center.project_away(
-                        lane.width / 2.0 + thickness,
-                        angle.rotate_degs(45.0).opposite(),
-                    );
+                    let left = pt.project_away(half_width + thickness, angle.rotate_degs(45.0));
+                    let right =
+                        pt.project_away(half_width + thickness, angle.rotate_degs(45.0).opposite());
                     pairs.push((
                         Line::must_new(left, right)
                             .make_polygons(thickness)
@@ -347,6 +420,56 @@ impl StreetNetwork {
         Ok(output)
     }
 
+    /// Draws an arrow for every lane-level movement, from the end of the incoming lane to the
+    /// start of the outgoing lane.
+    pub fn debug_lane_movements_geojson(&self) -> Result<String> {
+        let mut pairs = Vec::new();
+
+        for (i, intersection) in &self.intersections {
+            let mut lane_endpoints = BTreeMap::new();
+            for r in &intersection.roads {
+                let road = &self.roads[r];
+                let pts: Vec<_> = road
+                    .get_lane_center_lines()
+                    .into_iter()
+                    .map(|pl| {
+                        if road.src_i == *i {
+                            pl.first_pt()
+                        } else {
+                            pl.last_pt()
+                        }
+                    })
+                    .collect();
+                lane_endpoints.insert(*r, pts);
+            }
+
+            for movement in &intersection.lane_movements {
+                let (from_r, from_idx) = movement.from;
+                let (to_r, to_idx) = movement.to;
+                if let Ok(line) = Line::new(
+                    lane_endpoints[&from_r][from_idx],
+                    lane_endpoints[&to_r][to_idx],
+                ) {
+                    pairs.push((
+                        line.to_polyline()
+                            .make_arrow(Distance::meters(0.3), ArrowCap::Triangle)
+                            .to_geojson(Some(&self.gps_bounds)),
+                        make_props(&[
+                            ("from_road", from_r.0.into()),
+                            ("from_lane", from_idx.into()),
+                            ("to_road", to_r.0.into()),
+                            ("to_lane", to_idx.into()),
+                        ]),
+                    ));
+                }
+            }
+        }
+
+        let obj = geom::geometries_with_properties_to_geojson(pairs);
+        let output = serde_json::to_string_pretty(&obj)?;
+        Ok(output)
+    }
+
     pub fn to_intersection_markings_geojson(&self) -> Result<String> {
         let mut pairs = Vec::new();
 
@@ -358,6 +481,55 @@ impl StreetNetwork {
                 ));
             }
         }
+
+        // Features along roads
+        for road in self.roads.values() {
+            for feature in &road.features {
+                let pt = road
+                    .reference_line
+                    .dist_along(feature.dist)
+                    .map(|(pt, _)| pt)
+                    .unwrap_or(feature.pt);
+                pairs.push((
+                    Circle::new(pt, Distance::meters(1.0))
+                        .to_polygon()
+                        .to_geojson(Some(&self.gps_bounds)),
+                    make_props(&[
+                        ("type", feature.kind.label().into()),
+                        ("osm_node_id", feature.osm_node.0.into()),
+                        (
+                            "direction",
+                            match feature.direction {
+                                Some(dir) => format!("{dir}").into(),
+                                None => serde_json::Value::Null,
+                            },
+                        ),
+                    ]),
+                ));
+            }
+        }
+
+        // Zebra stripes for marked crossings
+        for road in self.roads.values() {
+            if road.crossing != Some(CrossingKind::Marked) {
+                continue;
+            }
+            let step_size = Distance::meters(1.0);
+            let buffer_ends = Distance::meters(0.5);
+            let stripe_len = Distance::meters(3.0);
+            let thickness = Distance::meters(0.5);
+            for (pt, angle) in road.center_line.step_along(step_size, buffer_ends) {
+                pairs.push((
+                    Line::must_new(
+                        pt.project_away(stripe_len / 2.0, angle.rotate_degs(90.0)),
+                        pt.project_away(stripe_len / 2.0, angle.rotate_degs(-90.0)),
+                    )
+                    .make_polygons(thickness)
+                    .to_geojson(Some(&self.gps_bounds)),
+                    make_props(&[("type", "crossing stripe".into())]),
+                ));
+            }
+        }
         let obj = geom::geometries_with_properties_to_geojson(pairs);
         let output = serde_json::to_string_pretty(&obj)?;
         Ok(output)
@@ -388,7 +560,9 @@ impl DebugStreets {
     }
 }
 
-fn make_props(list: &[(&str, serde_json::Value)]) -> serde_json::Map<String, serde_json::Value> {
+pub(crate) fn make_props(
+    list: &[(&str, serde_json::Value)],
+) -> serde_json::Map<String, serde_json::Value> {
     let mut props = serde_json::Map::new();
     for (x, y) in list {
         props.insert(x.to_string(), y.clone());
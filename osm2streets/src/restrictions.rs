@@ -0,0 +1,768 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use abstutil::Tags;
+
+use crate::time_window::time_windows_overlap;
+use crate::{RoadID, StreetNetwork, TravelMode};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RestrictionType {
+    BanTurns,
+    OnlyAllowTurns,
+}
+
+impl RestrictionType {
+    pub fn new(restriction: &str) -> Option<RestrictionType> {
+        // TODO There's a huge space of things not represented yet, like no right turn on red.
+        // Vehicle classes and time conditions are handled separately, by RestrictionConditions.
+
+        // There are so many possibilities:
+        // https://taginfo.openstreetmap.org/keys/restriction#values
+        // Just attempt to bucket into allow / deny.
+        if restriction.contains("no_") || restriction == "psv" {
+            Some(RestrictionType::BanTurns)
+        } else if restriction.contains("only_") {
+            Some(RestrictionType::OnlyAllowTurns)
+        } else {
+            None
+        }
+    }
+}
+
+/// A simple turn restriction from the road that stores it to another road, through their common
+/// intersection.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TurnRestriction {
+    pub restriction: RestrictionType,
+    pub to: RoadID,
+    pub conditions: RestrictionConditions,
+}
+
+/// A turn restriction from the road that stores it, through a sequence of other roads, to a final
+/// road. These come from OSM restrictions with `via` ways.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ComplicatedTurnRestriction {
+    pub restriction: RestrictionType,
+    /// The roads travelled between the source and the target, in order. Never empty.
+    pub via: Vec<RoadID>,
+    pub to: RoadID,
+    pub conditions: RestrictionConditions,
+}
+
+impl ComplicatedTurnRestriction {
+    /// The full sequence of roads, starting from the road storing this restriction.
+    pub fn sequence(&self, from: RoadID) -> Vec<RoadID> {
+        let mut seq = vec![from];
+        seq.extend(self.via.clone());
+        seq.push(self.to);
+        seq
+    }
+}
+
+/// Limits who a turn restriction applies to and when. The default value applies to everybody, all
+/// the time.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RestrictionConditions {
+    /// If this isn't empty, the restriction only applies to these modes. This comes from
+    /// `restriction:hgv` and similar.
+    pub only_for: BTreeSet<TravelMode>,
+    /// The restriction doesn't apply to these modes, from `except=*`.
+    pub except: BTreeSet<TravelMode>,
+    /// The restriction only applies during this time window, in the `opening_hours` syntax. This
+    /// comes from `restriction:conditional` and similar. The deprecated `day_on` / `hour_on` tags
+    /// are ignored, so those restrictions keep applying all the time, like they always have.
+    pub time: Option<String>,
+}
+
+impl RestrictionConditions {
+    /// True if the restriction applies to everybody, all the time.
+    pub fn is_unconditional(&self) -> bool {
+        self.only_for.is_empty() && self.except.is_empty() && self.time.is_none()
+    }
+
+    pub fn applies_to_mode(&self, mode: TravelMode) -> bool {
+        (self.only_for.is_empty() || self.only_for.contains(&mode)) && !self.except.contains(&mode)
+    }
+
+    /// True if the restriction applies at any moment of a time window, in the `opening_hours`
+    /// syntax understood by `TimeWindow`. `None` means outside of any time window, so only
+    /// restrictions without a time condition apply.
+    pub fn applies_during(&self, time: Option<&str>) -> bool {
+        match (&self.time, time) {
+            (Some(window), Some(time)) => time_windows_overlap(window, time),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+}
+
+/// A turn restriction that refers to roads that no longer exist or aren't connected anymore.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DanglingTurnRestriction {
+    /// The road storing the restriction
+    pub from: RoadID,
+    /// The full sequence of roads in the restriction, starting with `from`
+    pub sequence: Vec<RoadID>,
+    pub problem: DanglingProblem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DanglingProblem {
+    /// The road doesn't exist
+    MissingRoad(RoadID),
+    /// These two consecutive roads in the sequence don't share an intersection
+    Disconnected(RoadID, RoadID),
+}
+
+impl StreetNetwork {
+    /// Finds all turn restrictions that refer to missing roads or roads that aren't connected in
+    /// sequence. Operations that delete roads or disconnect them should leave nothing here.
+    pub fn find_dangling_turn_restrictions(&self) -> Vec<DanglingTurnRestriction> {
+        let mut results = Vec::new();
+        for road in self.roads.values() {
+            let sequences = road
+                .turn_restrictions
+                .iter()
+                .map(|tr| vec![road.id, tr.to])
+                .chain(
+                    road.complicated_turn_restrictions
+                        .iter()
+                        .map(|ctr| ctr.sequence(road.id)),
+                );
+            for sequence in sequences {
+                if let Some(problem) = self.check_restriction_sequence(&sequence) {
+                    results.push(DanglingTurnRestriction {
+                        from: road.id,
+                        sequence,
+                        problem,
+                    });
+                }
+            }
+        }
+        results
+    }
+
+    /// Repairs the turn restrictions found by `find_dangling_turn_restrictions` where possible,
+    /// and removes the rest. A complicated restriction is repaired by skipping missing via roads,
+    /// when the roads left are still connected in sequence. (This happens when a via road was
+    /// merged into an intersection.) If no via roads are left, it becomes a simple restriction.
+    /// Returns (the number repaired, the number removed).
+    pub fn fix_dangling_turn_restrictions(&mut self) -> (usize, usize) {
+        let mut repaired = 0;
+        let mut removed = 0;
+        let ids: Vec<RoadID> = self.roads.keys().cloned().collect();
+        for id in ids {
+            let road = &self.roads[&id];
+            let mut keep_simple = Vec::new();
+            for tr in &road.turn_restrictions {
+                if self.check_restriction_sequence(&[id, tr.to]).is_none() {
+                    keep_simple.push(tr.clone());
+                } else {
+                    removed += 1;
+                }
+            }
+            let mut keep_complicated = Vec::new();
+            for ctr in &road.complicated_turn_restrictions {
+                if self.check_restriction_sequence(&ctr.sequence(id)).is_none() {
+                    keep_complicated.push(ctr.clone());
+                    continue;
+                }
+                let mut fixed = ctr.clone();
+                fixed.via.retain(|r| self.roads.contains_key(r));
+                if fixed.via.len() < ctr.via.len()
+                    && self
+                        .check_restriction_sequence(&fixed.sequence(id))
+                        .is_none()
+                {
+                    keep_complicated.push(fixed);
+                    repaired += 1;
+                } else {
+                    removed += 1;
+                }
+            }
+
+            let road = self.roads.get_mut(&id).unwrap();
+            road.turn_restrictions = keep_simple;
+            road.complicated_turn_restrictions = keep_complicated;
+        }
+        if repaired > 0 {
+            self.normalize_turn_restrictions();
+        }
+        (repaired, removed)
+    }
+
+    fn check_restriction_sequence(&self, sequence: &[RoadID]) -> Option<DanglingProblem> {
+        for r in sequence {
+            if !self.roads.contains_key(r) {
+                return Some(DanglingProblem::MissingRoad(*r));
+            }
+        }
+        for pair in sequence.windows(2) {
+            let a = &self.roads[&pair[0]];
+            let b = &self.roads[&pair[1]];
+            if !a
+                .endpoints()
+                .into_iter()
+                .any(|i| b.endpoints().contains(&i))
+            {
+                return Some(DanglingProblem::Disconnected(pair[0], pair[1]));
+            }
+        }
+        None
+    }
+
+    /// Returns every sequence of roads that may not be travelled in order, because of complicated
+    /// turn restrictions applying to one mode during one time window. `OnlyAllowTurns`
+    /// restrictions are expressed by banning every alternative after the last `via` road. Simple
+    /// turn restrictions aren't included; use `Road::allowed_to_turn_to_for` for those.
+    pub fn banned_sequences(&self, mode: TravelMode, time: Option<&str>) -> Vec<Vec<RoadID>> {
+        let mut results = Vec::new();
+        for road in self.roads.values() {
+            for ctr in &road.complicated_turn_restrictions {
+                if !ctr.conditions.applies_to_mode(mode) || !ctr.conditions.applies_during(time) {
+                    continue;
+                }
+                let seq = ctr.sequence(road.id);
+                match ctr.restriction {
+                    RestrictionType::BanTurns => {
+                        results.push(seq);
+                    }
+                    RestrictionType::OnlyAllowTurns => {
+                        // Find where the last via road exits
+                        let last = seq[seq.len() - 2];
+                        let prev = seq[seq.len() - 3];
+                        let (last_road, prev_road) =
+                            match (self.roads.get(&last), self.roads.get(&prev)) {
+                                (Some(a), Some(b)) => (a, b),
+                                _ => continue,
+                            };
+                        let exit = if prev_road.endpoints().contains(&last_road.src_i) {
+                            last_road.dst_i
+                        } else {
+                            last_road.src_i
+                        };
+                        for alt in &self.intersections[&exit].roads {
+                            if *alt != last && *alt != ctr.to {
+                                let mut banned = seq[0..seq.len() - 1].to_vec();
+                                banned.push(*alt);
+                                results.push(banned);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        results
+    }
+
+    /// Checks if a path of consecutive roads obeys all simple and complicated turn restrictions
+    /// applying to one mode during one time window.
+    pub fn is_path_allowed(&self, path: &[RoadID], mode: TravelMode, time: Option<&str>) -> bool {
+        for pair in path.windows(2) {
+            if !self.roads[&pair[0]].allowed_to_turn_to_for(pair[1], mode, time) {
+                return false;
+            }
+        }
+        for banned in self.banned_sequences(mode, time) {
+            if path
+                .windows(banned.len())
+                .any(|window| window == banned.as_slice())
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Removes all turn restrictions that refer to a road, which is about to be deleted.
+    pub(crate) fn remove_turn_restrictions_involving(&mut self, r: RoadID) {
+        for road in self.roads.values_mut() {
+            road.turn_restrictions.retain(|tr| tr.to != r);
+            road.complicated_turn_restrictions
+                .retain(|ctr| ctr.to != r && !ctr.via.contains(&r));
+        }
+    }
+
+    /// After roads have been merged or deleted, complicated turn restrictions may have repeated or
+    /// empty `via` roads. Clean them up, turning them into simple restrictions when possible, and
+    /// remove duplicates.
+    pub(crate) fn normalize_turn_restrictions(&mut self) {
+        for road in self.roads.values_mut() {
+            let mut keep = Vec::new();
+            for mut ctr in std::mem::take(&mut road.complicated_turn_restrictions) {
+                let mut via = Vec::new();
+                let mut prev = road.id;
+                for r in ctr.via {
+                    if r != prev {
+                        via.push(r);
+                        prev = r;
+                    }
+                }
+                if ctr.to == prev {
+                    continue;
+                }
+                if via.is_empty() {
+                    road.turn_restrictions.push(TurnRestriction {
+                        restriction: ctr.restriction,
+                        to: ctr.to,
+                        conditions: ctr.conditions,
+                    });
+                } else {
+                    ctr.via = via;
+                    keep.push(ctr);
+                }
+            }
+            let mut seen = BTreeSet::new();
+            keep.retain(|ctr| seen.insert(ctr.clone()));
+            road.complicated_turn_restrictions = keep;
+
+            let id = road.id;
+            let mut seen = BTreeSet::new();
+            road.turn_restrictions
+                .retain(|tr| tr.to != id && seen.insert(tr.clone()));
+        }
+    }
+}
+
+/// Parses all the restrictions expressed by the tags of a `type=restriction` relation. There may
+/// be several, because of `restriction:hgv` and `restriction:conditional` variants.
+pub fn parse_restrictions(tags: &Tags) -> Vec<(RestrictionType, RestrictionConditions)> {
+    let mut except = BTreeSet::new();
+    if let Some(list) = tags.get("except") {
+        for key in list.split(';') {
+            except.extend(TravelMode::from_osm_key(key.trim()));
+        }
+    }
+
+    let mut results = Vec::new();
+    for (key, value) in tags.inner() {
+        let rest = match key.strip_prefix("restriction") {
+            Some(rest) => rest,
+            None => continue,
+        };
+        let (rest, conditional) = match rest.strip_suffix(":conditional") {
+            Some(rest) => (rest, true),
+            None => (rest, false),
+        };
+        let only_for: BTreeSet<TravelMode> = if rest.is_empty() {
+            BTreeSet::new()
+        } else {
+            match rest.strip_prefix(':') {
+                Some(mode) => TravelMode::from_osm_key(mode).into_iter().collect(),
+                None => continue,
+            }
+        };
+        if !rest.is_empty() && only_for.is_empty() {
+            warn!("Ignoring turn restriction with unknown mode: {key} = {value}");
+            continue;
+        }
+
+        // Multiple conditional values are separated by semicolons, outside of the parentheses
+        let values = if conditional {
+            split_conditional(value)
+                .into_iter()
+                .map(|(value, condition)| (value, Some(condition)))
+                .collect()
+        } else {
+            vec![(value.to_string(), None)]
+        };
+        for (value, time) in values {
+            if let Some(rt) = RestrictionType::new(&value) {
+                results.push((
+                    rt,
+                    RestrictionConditions {
+                        only_for: only_for.clone(),
+                        except: except.clone(),
+                        time,
+                    },
+                ));
+            }
+        }
+    }
+    results
+}
+
+/// Splits something like `no_left_turn @ (Mo-Fr 07:00-09:00); no_right_turn @ (Sa)` into pairs of
+/// values and conditions.
+pub(crate) fn split_conditional(raw: &str) -> Vec<(String, String)> {
+    let mut results = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in raw.chars().chain(std::iter::once(';')) {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ';' if depth == 0 => {
+                if let Some((value, condition)) = current.split_once('@') {
+                    let condition = condition.trim();
+                    let condition = condition
+                        .strip_prefix('(')
+                        .and_then(|x| x.strip_suffix(')'))
+                        .unwrap_or(condition);
+                    results.push((value.trim().to_string(), condition.trim().to_string()));
+                } else if !current.trim().is_empty() {
+                    warn!("Ignoring malformed conditional value {}", raw);
+                }
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_restrictions() {
+        let mut ok = true;
+        for (input, expected) in vec![
+            (
+                vec!["restriction=no_left_turn"],
+                vec![(RestrictionType::BanTurns, vec![], vec![], None)],
+            ),
+            (
+                vec!["restriction=only_straight_on", "except=psv;bicycle"],
+                vec![(
+                    RestrictionType::OnlyAllowTurns,
+                    vec![],
+                    vec![TravelMode::Bike, TravelMode::Bus],
+                    None,
+                )],
+            ),
+            (
+                vec!["restriction:hgv=no_right_turn"],
+                vec![(
+                    RestrictionType::BanTurns,
+                    vec![TravelMode::Hgv],
+                    vec![],
+                    None,
+                )],
+            ),
+            (
+                vec!["restriction:conditional=no_left_turn @ (Mo-Fr 07:00-09:00; Sa 10:00-12:00)"],
+                vec![(
+                    RestrictionType::BanTurns,
+                    vec![],
+                    vec![],
+                    Some("Mo-Fr 07:00-09:00; Sa 10:00-12:00"),
+                )],
+            ),
+            (
+                vec![
+                    "restriction=no_u_turn",
+                    "day_on=Mo",
+                    "day_off=Fr",
+                    "hour_on=07:00",
+                    "hour_off=09:00",
+                ],
+                vec![(RestrictionType::BanTurns, vec![], vec![], None)],
+            ),
+            (
+                vec!["restriction:hgv:conditional=no_right_turn @ (22:00-06:00)"],
+                vec![(
+                    RestrictionType::BanTurns,
+                    vec![TravelMode::Hgv],
+                    vec![],
+                    Some("22:00-06:00"),
+                )],
+            ),
+            (
+                vec!["restriction:conditional=no_left_turn @ (Mo-Fr); only_straight_on @ (Sa)"],
+                vec![
+                    (RestrictionType::BanTurns, vec![], vec![], Some("Mo-Fr")),
+                    (RestrictionType::OnlyAllowTurns, vec![], vec![], Some("Sa")),
+                ],
+            ),
+            (vec!["restriction=give_way"], vec![]),
+        ] {
+            let mut tags = Tags::empty();
+            for kv in &input {
+                let parts = kv.split_once('=').unwrap();
+                tags.insert(parts.0, parts.1);
+            }
+            let actual = parse_restrictions(&tags);
+            let expected: Vec<(RestrictionType, RestrictionConditions)> = expected
+                .into_iter()
+                .map(|(rt, only_for, except, time)| {
+                    (
+                        rt,
+                        RestrictionConditions {
+                            only_for: only_for.into_iter().collect(),
+                            except: except.into_iter().collect(),
+                            time: time.map(|x: &str| x.to_string()),
+                        },
+                    )
+                })
+                .collect();
+            if actual != expected {
+                ok = false;
+                println!("For input {:?}", input);
+                println!("Got:      {:?}", actual);
+                println!("Expected: {:?}", expected);
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+
+    #[test]
+    fn test_is_path_allowed() {
+        // A four-way junction, with two roads leaving the north end
+        let mut streets = StreetNetwork::blank();
+        let center = streets.add_test_intersection(0.0, 0.0);
+        let west_i = streets.add_test_intersection(-100.0, 0.0);
+        let east_i = streets.add_test_intersection(100.0, 0.0);
+        let north_i = streets.add_test_intersection(0.0, -100.0);
+        let south_i = streets.add_test_intersection(0.0, 100.0);
+        let far_north_i = streets.add_test_intersection(0.0, -200.0);
+        let north_east_i = streets.add_test_intersection(100.0, -100.0);
+        let west = streets.add_test_road(west_i, center, "dd", "v^");
+        let east = streets.add_test_road(center, east_i, "dd", "v^");
+        let north = streets.add_test_road(center, north_i, "dd", "v^");
+        let south = streets.add_test_road(south_i, center, "dd", "v^");
+        let far_north = streets.add_test_road(north_i, far_north_i, "dd", "v^");
+        let north_east = streets.add_test_road(north_i, north_east_i, "dd", "v^");
+
+        let unconditional = RestrictionConditions::default();
+        // No turning from south to west
+        streets
+            .roads
+            .get_mut(&south)
+            .unwrap()
+            .turn_restrictions
+            .push(TurnRestriction {
+                restriction: RestrictionType::BanTurns,
+                to: west,
+                conditions: unconditional.clone(),
+            });
+        // From west, only go straight, except for bikes
+        streets
+            .roads
+            .get_mut(&west)
+            .unwrap()
+            .turn_restrictions
+            .push(TurnRestriction {
+                restriction: RestrictionType::OnlyAllowTurns,
+                to: east,
+                conditions: RestrictionConditions {
+                    except: vec![TravelMode::Bike].into_iter().collect(),
+                    ..Default::default()
+                },
+            });
+        // From south through north, don't go north-east on weekdays
+        streets
+            .roads
+            .get_mut(&south)
+            .unwrap()
+            .complicated_turn_restrictions
+            .push(ComplicatedTurnRestriction {
+                restriction: RestrictionType::BanTurns,
+                via: vec![north],
+                to: north_east,
+                conditions: RestrictionConditions {
+                    time: Some("Mo-Fr".to_string()),
+                    ..Default::default()
+                },
+            });
+        // From east through north, only continue further north
+        streets
+            .roads
+            .get_mut(&east)
+            .unwrap()
+            .complicated_turn_restrictions
+            .push(ComplicatedTurnRestriction {
+                restriction: RestrictionType::OnlyAllowTurns,
+                via: vec![north],
+                to: far_north,
+                conditions: unconditional,
+            });
+
+        let mut ok = true;
+        for (description, path, mode, time, expected) in vec![
+            (
+                "simple ban",
+                vec![south, west],
+                TravelMode::Car,
+                None,
+                false,
+            ),
+            (
+                "around a ban",
+                vec![south, east],
+                TravelMode::Car,
+                None,
+                true,
+            ),
+            ("only allow", vec![west, east], TravelMode::Car, None, true),
+            (
+                "not the only allowed",
+                vec![west, north],
+                TravelMode::Car,
+                None,
+                false,
+            ),
+            (
+                "except bikes",
+                vec![west, north],
+                TravelMode::Bike,
+                None,
+                true,
+            ),
+            (
+                "complicated ban outside its time",
+                vec![south, north, north_east],
+                TravelMode::Car,
+                None,
+                true,
+            ),
+            (
+                "complicated ban",
+                vec![south, north, north_east],
+                TravelMode::Car,
+                Some("Mo-Fr"),
+                false,
+            ),
+            (
+                "complicated ban during part of its time",
+                vec![south, north, north_east],
+                TravelMode::Car,
+                Some("We 08:00-09:00"),
+                false,
+            ),
+            (
+                "complicated ban on the weekend",
+                vec![south, north, north_east],
+                TravelMode::Car,
+                Some("Sa"),
+                true,
+            ),
+            (
+                "around a complicated ban",
+                vec![south, north, far_north],
+                TravelMode::Car,
+                Some("Mo-Fr"),
+                true,
+            ),
+            (
+                "complicated only allow",
+                vec![east, north, far_north],
+                TravelMode::Car,
+                None,
+                true,
+            ),
+            (
+                "not the complicated only allowed",
+                vec![east, north, north_east],
+                TravelMode::Car,
+                None,
+                false,
+            ),
+            (
+                "only the start of a complicated restriction",
+                vec![east, north],
+                TravelMode::Car,
+                None,
+                true,
+            ),
+        ] {
+            let actual = streets.is_path_allowed(&path, mode, time);
+            if actual != expected {
+                ok = false;
+                println!("For {description}, {path:?} by {mode:?} at {time:?}");
+                println!("Got {actual}, expected {expected}");
+                println!();
+            }
+        }
+        assert!(ok);
+
+        // The time of the complicated restriction matters where it starts
+        assert_eq!(
+            streets.restriction_time_windows(center),
+            vec!["Mo-Fr".to_string()].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_dangling_turn_restrictions() {
+        // Three roads in a row, and a fourth leading into the first
+        let mut streets = StreetNetwork::blank();
+        let i0 = streets.add_test_intersection(0.0, 0.0);
+        let i1 = streets.add_test_intersection(100.0, 0.0);
+        let i2 = streets.add_test_intersection(200.0, 0.0);
+        let i3 = streets.add_test_intersection(300.0, 0.0);
+        let i4 = streets.add_test_intersection(0.0, 100.0);
+        let a = streets.add_test_road(i0, i1, "dd", "v^");
+        let b = streets.add_test_road(i1, i2, "dd", "v^");
+        let c = streets.add_test_road(i2, i3, "dd", "v^");
+        let d = streets.add_test_road(i4, i0, "dd", "v^");
+        let missing1 = RoadID(98);
+        let missing2 = RoadID(99);
+
+        let ban = |to| TurnRestriction {
+            restriction: RestrictionType::BanTurns,
+            to,
+            conditions: RestrictionConditions::default(),
+        };
+        let complicated_ban = |via: Vec<RoadID>, to| ComplicatedTurnRestriction {
+            restriction: RestrictionType::BanTurns,
+            via,
+            to,
+            conditions: RestrictionConditions::default(),
+        };
+        {
+            let road = streets.roads.get_mut(&a).unwrap();
+            road.turn_restrictions = vec![ban(b), ban(c), ban(missing2)];
+            road.complicated_turn_restrictions = vec![complicated_ban(vec![missing1, b], c)];
+        }
+        streets
+            .roads
+            .get_mut(&d)
+            .unwrap()
+            .complicated_turn_restrictions = vec![
+            complicated_ban(vec![missing1], a),
+            complicated_ban(vec![b], c),
+        ];
+
+        let dangling = |from, sequence, problem| DanglingTurnRestriction {
+            from,
+            sequence,
+            problem,
+        };
+        assert_eq!(
+            streets.find_dangling_turn_restrictions(),
+            vec![
+                dangling(a, vec![a, c], DanglingProblem::Disconnected(a, c)),
+                dangling(a, vec![a, missing2], DanglingProblem::MissingRoad(missing2)),
+                dangling(
+                    a,
+                    vec![a, missing1, b, c],
+                    DanglingProblem::MissingRoad(missing1)
+                ),
+                dangling(
+                    d,
+                    vec![d, missing1, a],
+                    DanglingProblem::MissingRoad(missing1)
+                ),
+                dangling(d, vec![d, b, c], DanglingProblem::Disconnected(d, b)),
+            ]
+        );
+
+        // Skipping the missing via road fixes two of them
+        assert_eq!(streets.fix_dangling_turn_restrictions(), (2, 3));
+        assert!(streets.find_dangling_turn_restrictions().is_empty());
+        assert_eq!(streets.roads[&a].turn_restrictions, vec![ban(b)]);
+        assert_eq!(
+            streets.roads[&a].complicated_turn_restrictions,
+            vec![complicated_ban(vec![b], c)]
+        );
+        assert_eq!(streets.roads[&d].turn_restrictions, vec![ban(a)]);
+        assert!(streets.roads[&d].complicated_turn_restrictions.is_empty());
+    }
+}
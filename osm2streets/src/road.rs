@@ -1,14 +1,20 @@
+use std::collections::BTreeMap;
+
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 use abstutil::Tags;
-use geom::{Angle, Distance, PolyLine};
+use geom::{Angle, Distance, PolyLine, Polygon, Pt2D, Ring};
 
-use crate::lanes::{Placement, RoadPosition};
+use crate::access::apply_lane_access;
+use crate::ids::{next_stable_id, stable_hash};
+use crate::lanes::{apply_width_lanes, Placement, RoadPosition};
 use crate::{
-    get_lane_specs_ltr, osm, CommonEndpoint, Direction, DrivingSide, InputRoad, IntersectionID,
-    LaneSpec, LaneType, MapConfig, OriginalRoad, RestrictionType, RoadID, RoadWithEndpoints,
-    StreetNetwork,
+    compare_lane_parsers, get_lane_specs_ltr_with, osm, AccessRules, CommonEndpoint,
+    ComplicatedTurnRestriction, CrossingKind, Direction, DrivingSide, InputRoad, IntersectionID,
+    LaneParser, LaneProvenance, LaneSpec, LaneType, MapConfig, OriginalRoad, RestrictionType,
+    RoadFeature, RoadID, RoadWithEndpoints, SpeedLimits, StreetNetwork, TravelMode,
+    TurnRestriction,
 };
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -34,22 +40,43 @@ pub struct Road {
     /// The vertical layer of the road, with 0 the default and negative values lower down. See
     /// <https://wiki.openstreetmap.org/wiki/Key:layer>.
     pub layer: isize,
+    /// Set for separately mapped pedestrian crossings. These only exist when
+    /// `MapConfig::inferred_sidewalks` is false.
+    pub crossing: Option<CrossingKind>,
+    /// For crossings, the driveable roads crossed. Set by `StreetNetwork::update_crossings`.
+    #[serde(default)]
+    pub crosses: Vec<RoadID>,
+    /// Access explicitly tagged for the whole road. See `Road::allows` and `Road::lane_allows`.
+    pub access: AccessRules,
+    /// The legal speed limit in each direction, from `maxspeed` or a guessed default
+    pub speed_limits: SpeedLimits,
 
     /// The original OSM geometry (slightly smoothed). This will extend beyond the extent of the
     /// resulting trimmed road, be positioned somewhere within the road according to the placement
     /// tag and might be nonsense for the first/last segment.
     pub reference_line: PolyLine,
     pub reference_line_placement: Placement,
+    /// The part of the original OSM way this road covers, as fractions of the way's length from 0
+    /// to 1. A `Placement::Varying` changes over the whole way, so a road that's only one piece
+    /// of it only covers part of that change.
+    #[serde(default = "whole_way")]
+    pub way_fractions: (f64, f64),
     /// The physical center of all the lanes, including sidewalks (at RoadPosition::FullWidthCenter).
     /// This will differ from reference_line and modified by transformations, notably it will be
     /// offset based on reference_line_placement and trimmed by
     /// `Transformation::GenerateIntersectionGeometry`.
     pub center_line: PolyLine,
-    pub turn_restrictions: Vec<(RestrictionType, RoadID)>,
-    /// (via, to). For turn restrictions where 'via' is an entire road. Only BanTurns.
-    pub complicated_turn_restrictions: Vec<(RoadID, RoadID)>,
+    /// Restrictions on turning from this road to others, through a common intersection.
+    pub turn_restrictions: Vec<TurnRestriction>,
+    /// Turn restrictions from this road, where 'via' is one or more entire roads.
+    pub complicated_turn_restrictions: Vec<ComplicatedTurnRestriction>,
 
     pub lane_specs_ltr: Vec<LaneSpec>,
+    /// Which parser produced `lane_specs_ltr`, and what it warned about
+    #[serde(default)]
+    pub lane_provenance: LaneProvenance,
+    /// Crossings, barriers, traffic calming, and signs along the road, ordered by distance
+    pub features: Vec<RoadFeature>,
 }
 
 impl Road {
@@ -62,7 +89,25 @@ impl Road {
         osm_tags: Tags,
         config: &MapConfig,
     ) -> Self {
-        let lane_specs_ltr = get_lane_specs_ltr(&osm_tags, config);
+        let parser = if config.osm2lanes
+            || osm_ids
+                .iter()
+                .any(|id| config.osm2lanes_ways.contains(&id.osm_way_id))
+        {
+            LaneParser::Osm2lanes
+        } else {
+            LaneParser::Classic
+        };
+        let (mut lane_specs_ltr, mut lane_provenance) =
+            get_lane_specs_ltr_with(&osm_tags, config, parser);
+        if config.compare_lane_parsers {
+            lane_provenance.disagreement = compare_lane_parsers(&osm_tags, config);
+            if let Some(ref disagreement) = lane_provenance.disagreement {
+                warn!("Lane parsers disagree about {id}: {disagreement}");
+            }
+        }
+        apply_lane_access(&mut lane_specs_ltr, &osm_tags);
+        apply_width_lanes(&mut lane_specs_ltr, &osm_tags);
 
         let layer = if let Some(layer) = osm_tags.get("layer") {
             match layer.parse::<f64>() {
@@ -96,13 +141,20 @@ impl Road {
             name: osm_tags.get("name").cloned(),
             internal_junction_road: osm_tags.is("junction", "intersection"),
             layer,
+            crossing: CrossingKind::from_tags(&osm_tags),
+            crosses: Vec::new(),
+            access: AccessRules::new(&osm_tags),
+            speed_limits: SpeedLimits::new(&osm_tags, config.default_speed_unit),
             reference_line,
             reference_line_placement: placement,
+            way_fractions: whole_way(),
             center_line: PolyLine::dummy(),
             turn_restrictions: Vec::new(),
             complicated_turn_restrictions: Vec::new(),
 
             lane_specs_ltr,
+            lane_provenance,
+            features: Vec::new(),
         };
 
         result.update_center_line(config.driving_side); // TODO delay this until trim_start and trim_end are calculated
@@ -110,32 +162,50 @@ impl Road {
     }
 
     /// Calculates and sets the center_line from reference_line, reference_line_placement
-    /// (and TODO trim_start, trim_end).
+    /// (and TODO trim_start, trim_end). With varying placement, the offset changes linearly along
+    /// the OSM way, and this road covers the part of that change given by `way_fractions`.
     pub fn update_center_line(&mut self, driving_side: DrivingSide) {
-        let ref_position = match self.reference_line_placement {
-            Placement::Consistent(p) => p,
-            Placement::Varying(p, _) => {
-                warn!("varying placement not yet supported, using placement:start");
-                p
-            }
-            Placement::Transition => {
-                // We haven't calculated the transition yet. At early stages of understanding the
-                // OSM data, we pretend these `Road`s have default placement.
-                RoadPosition::Center
-            }
-        };
-        let ref_offset = self.left_edge_offset_of(ref_position, driving_side);
+        let (start, end) = self.reference_positions();
         let target_offset = self.left_edge_offset_of(RoadPosition::FullWidthCenter, driving_side);
+        let shift_start = target_offset - self.left_edge_offset_of(start, driving_side);
+        let shift_end = target_offset - self.left_edge_offset_of(end, driving_side);
+        let shift_at = |fraction: f64| shift_start + (shift_end - shift_start) * fraction;
+        let (from, to) = self.way_fractions;
 
-        self.center_line = self
-            .reference_line
-            .shift_either_direction(target_offset - ref_offset)
+        self.center_line = shift_varying(&self.reference_line, shift_at(from), shift_at(to))
             .unwrap_or_else(|_| {
                 warn!("resulting center_line is degenerate!");
                 self.reference_line.clone()
             });
     }
 
+    /// Lanes are parsed from the tags of the whole OSM way, so widths varying along the way with
+    /// `width:lanes:start` and `width:lanes:end` describe the whole way. Keeps only the part of
+    /// that change that this road covers, according to `way_fractions`.
+    pub fn slice_width_profiles_to_way(&mut self) {
+        let (from, to) = self.way_fractions;
+        if (from, to) == whole_way() {
+            return;
+        }
+        for lane in &mut self.lane_specs_ltr {
+            lane.slice_width_profile(from, to);
+        }
+    }
+
+    /// Where the reference_line lies at the start and end of the road
+    fn reference_positions(&self) -> (RoadPosition, RoadPosition) {
+        match self.reference_line_placement {
+            Placement::Consistent(p) => (p, p),
+            Placement::Varying(start, end) => (start, end),
+            Placement::Transition => {
+                // We haven't calculated the transition yet (see
+                // `StreetNetwork::resolve_transition_placement`). At early stages of understanding
+                // the OSM data, we pretend these `Road`s have default placement.
+                (RoadPosition::Center, RoadPosition::Center)
+            }
+        }
+    }
+
     pub fn is_light_rail(&self) -> bool {
         self.lane_specs_ltr
             .iter()
@@ -164,6 +234,23 @@ impl Road {
             .any(|spec| spec.lt == LaneType::Driving)
     }
 
+    /// A rough ordering of how important a road is, based on its highway type. Higher values are
+    /// more important. This is used to guess which roads have priority at intersections.
+    pub fn rank(&self) -> usize {
+        match self.highway_type.trim_end_matches("_link") {
+            "motorway" => 10,
+            "trunk" => 9,
+            "primary" => 8,
+            "secondary" => 7,
+            "tertiary" => 6,
+            "unclassified" => 5,
+            "residential" => 4,
+            "living_street" => 3,
+            "service" => 2,
+            _ => 1,
+        }
+    }
+
     pub fn oneway_for_driving(&self) -> Option<Direction> {
         LaneSpec::oneway_for_driving(&self.lane_specs_ltr)
     }
@@ -192,17 +279,32 @@ impl Road {
         return true;
     }
 
+    /// Checks turn restrictions that apply to cars all the time.
     pub fn allowed_to_turn_to(&self, dest: RoadID) -> bool {
+        self.allowed_to_turn_to_for(dest, TravelMode::Car, None)
+    }
+
+    /// Checks turn restrictions that apply to one mode during one time window. See
+    /// `RestrictionConditions::applies_during` for how time windows are matched.
+    pub fn allowed_to_turn_to_for(
+        &self,
+        dest: RoadID,
+        mode: TravelMode,
+        time: Option<&str>,
+    ) -> bool {
         let mut has_exclusive_allows = false;
-        for (t, other) in self.turn_restrictions.iter() {
-            match t {
+        for tr in &self.turn_restrictions {
+            if !tr.conditions.applies_to_mode(mode) || !tr.conditions.applies_during(time) {
+                continue;
+            }
+            match tr.restriction {
                 RestrictionType::BanTurns => {
-                    if *other == dest {
+                    if tr.to == dest {
                         return false;
                     }
                 }
                 RestrictionType::OnlyAllowTurns => {
-                    if *other == dest {
+                    if tr.to == dest {
                         return true;
                     }
                     has_exclusive_allows = true;
@@ -226,19 +328,18 @@ impl Road {
 
     /// Returns an untrimmed line along `RoadPosition::Center`
     pub fn untrimmed_road_geometry(&self, driving_side: DrivingSide) -> PolyLine {
-        let ref_position = match self.reference_line_placement {
-            Placement::Consistent(p) => p,
-            Placement::Varying(p, _) => p,
-            Placement::Transition => RoadPosition::Center, // Best we can do for now.
-        };
-        let ref_offset = self.left_edge_offset_of(ref_position, driving_side);
+        let (start, end) = self.reference_positions();
         let center_offset = self.left_edge_offset_of(RoadPosition::Center, driving_side);
 
-        self.reference_line
-            .shift_either_direction(center_offset - ref_offset)
-            .unwrap()
+        shift_varying(
+            &self.reference_line,
+            center_offset - self.left_edge_offset_of(start, driving_side),
+            center_offset - self.left_edge_offset_of(end, driving_side),
+        )
+        .unwrap()
     }
 
+    /// The sum of lane widths. When some lanes vary in width, this uses their widest points.
     pub fn total_width(&self) -> Distance {
         self.lane_specs_ltr.iter().map(|l| l.width).sum()
     }
@@ -246,6 +347,22 @@ impl Road {
         self.total_width() / 2.0
     }
 
+    /// The width of the road some fraction of the way along it, from 0 at the start to 1 at the
+    /// end.
+    pub fn total_width_at(&self, fraction: f64) -> Distance {
+        self.lane_specs_ltr
+            .iter()
+            .map(|l| l.width_at(fraction))
+            .sum()
+    }
+
+    /// Does the width of any lane change along the road?
+    pub fn has_varying_width(&self) -> bool {
+        self.lane_specs_ltr
+            .iter()
+            .any(|l| l.width_profile.is_some())
+    }
+
     /// Calculates the number of (forward, both_ways, backward) lanes. The order of the lanes
     /// doesn't matter.
     pub fn _travel_lane_counts(&self) -> (usize, usize, usize) {
@@ -254,7 +371,7 @@ impl Road {
             if !lane.lt.is_tagged_by_lanes_suffix() {
                 continue;
             }
-            if lane.lt == LaneType::SharedLeftTurn {
+            if lane.lt == LaneType::SharedLeftTurn || lane.dir == Direction::Both {
                 result.1 += 1;
             } else if lane.dir == Direction::Fwd {
                 result.0 += 1;
@@ -315,7 +432,9 @@ impl Road {
                 let mut median_width = Distance::ZERO;
                 let mut dist_so_far = Distance::ZERO;
                 for lane in &self.lane_specs_ltr {
-                    if lane.lt == LaneType::SharedLeftTurn {
+                    if lane.lt == LaneType::SharedLeftTurn
+                        || (lane.lt.is_tagged_by_lanes_suffix() && lane.dir == Direction::Both)
+                    {
                         // "separation" is the middle of this lane by definition.
                         return dist_so_far + lane.width / 2.0;
                     } else if lane.lt.is_tagged_by_lanes_suffix() {
@@ -353,7 +472,7 @@ impl Road {
                     Box::new(self.lane_specs_ltr.iter().rev())
                 };
                 for lane in lanes {
-                    if lane.dir == target_dir {
+                    if lane.dir.includes(target_dir) {
                         lanes_found += 1;
                         if lanes_found == target_num {
                             // The side of the name lane is defined in the direction of the lane
@@ -383,8 +502,19 @@ impl Road {
 
     /// Returns one PolyLine representing the center of each lane in this road. This must be called
     /// after `Transformation::GenerateIntersectionGeometry` is run. The result also faces the same
-    /// direction as the road.
+    /// direction as the road. Lanes varying in width move the centers of other lanes too.
     pub(crate) fn get_lane_center_lines(&self) -> Vec<PolyLine> {
+        if self.has_varying_width() {
+            return (0..self.lane_specs_ltr.len())
+                .map(|idx| {
+                    self.shift_center_line(|fraction| {
+                        (self.edge_offset(idx, fraction) + self.edge_offset(idx + 1, fraction))
+                            / 2.0
+                    })
+                })
+                .collect();
+        }
+
         let total_width = self.total_width();
 
         let mut width_so_far = Distance::ZERO;
@@ -401,6 +531,110 @@ impl Road {
         output
     }
 
+    /// Returns the boundaries between lanes, from the left side of the road to the right, so lane
+    /// `idx` lies between `idx` and `idx + 1`. Like `get_lane_center_lines`, these follow lanes
+    /// varying in width and face the same direction as the road.
+    pub(crate) fn get_lane_edges(&self) -> Vec<PolyLine> {
+        (0..=self.lane_specs_ltr.len())
+            .map(|idx| self.shift_center_line(|fraction| self.edge_offset(idx, fraction)))
+            .collect()
+    }
+
+    /// Returns a polygon per lane, following lanes varying in width.
+    pub(crate) fn get_lane_polygons(&self) -> Vec<Polygon> {
+        let centers = self.get_lane_center_lines();
+        if !self.has_varying_width() {
+            return self
+                .lane_specs_ltr
+                .iter()
+                .zip(centers)
+                .map(|(lane, pl)| pl.make_polygons(lane.width))
+                .collect();
+        }
+        let edges = self.get_lane_edges();
+        self.lane_specs_ltr
+            .iter()
+            .zip(centers)
+            .zip(edges.windows(2))
+            .map(|((lane, pl), pair)| {
+                polygon_between(&pair[0], &pair[1]).unwrap_or_else(|_| pl.make_polygons(lane.width))
+            })
+            .collect()
+    }
+
+    /// The polygon of the whole road, following lanes varying in width.
+    pub(crate) fn get_polygon(&self) -> Polygon {
+        if !self.has_varying_width() {
+            return self.center_line.make_polygons(self.total_width());
+        }
+        let left = self.shift_center_line(|fraction| self.edge_offset(0, fraction));
+        let right = self
+            .shift_center_line(|fraction| self.edge_offset(self.lane_specs_ltr.len(), fraction));
+        polygon_between(&left, &right)
+            .unwrap_or_else(|_| self.center_line.make_polygons(self.total_width()))
+    }
+
+    /// How far right of the center_line the left edge of lane `idx` is, some fraction of the way
+    /// along the road. `idx` may be the number of lanes, for the right side of the road.
+    fn edge_offset(&self, idx: usize, fraction: f64) -> Distance {
+        let left: Distance = self.lane_specs_ltr[..idx]
+            .iter()
+            .map(|l| l.width_at(fraction))
+            .sum();
+        left - self.total_width_at(fraction) / 2.0
+    }
+
+    /// Width profiles are defined along the road as parsed, but the center_line may be trimmed
+    /// back from intersections. Converts a fraction of the way along the center_line to a fraction
+    /// of the way along the untrimmed road, which `LaneSpec::width_at` expects.
+    pub(crate) fn untrimmed_fraction(&self, fraction: f64) -> f64 {
+        let (start, end) = self.trimmed_range();
+        start + (end - start) * fraction
+    }
+
+    /// The part of the untrimmed road that the center_line covers, as fractions of the
+    /// reference_line's length
+    fn trimmed_range(&self) -> (f64, f64) {
+        let length = self.reference_line.length();
+        let along = |pt: Pt2D| {
+            self.reference_line
+                .dist_along_of_point(self.reference_line.project_pt(pt))
+                .map(|(dist, _)| dist / length)
+        };
+        match (
+            along(self.center_line.first_pt()),
+            along(self.center_line.last_pt()),
+        ) {
+            (Some(start), Some(end)) if start < end => (start, end),
+            _ => (0.0, 1.0),
+        }
+    }
+
+    /// Shifts the center_line by an offset depending on lane widths. `offset_at` takes a fraction
+    /// of the way along the untrimmed road.
+    fn shift_center_line<F: Fn(f64) -> Distance>(&self, offset_at: F) -> PolyLine {
+        if !self.has_varying_width() {
+            return self
+                .center_line
+                .shift_either_direction(offset_at(0.0))
+                .unwrap_or_else(|_| self.center_line.clone());
+        }
+        let (start, end) = self.trimmed_range();
+        let mut breakpoints: Vec<f64> = self
+            .lane_specs_ltr
+            .iter()
+            .filter_map(|l| l.width_profile.as_ref())
+            .flat_map(|profile| profile.breakpoints())
+            .map(|fraction| (fraction - start) / (end - start))
+            .filter(|fraction| *fraction > 0.0 && *fraction < 1.0)
+            .collect();
+        breakpoints.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        shift_along(&self.center_line, &breakpoints, |fraction| {
+            offset_at(start + (end - start) * fraction)
+        })
+        .unwrap_or_else(|_| self.center_line.clone())
+    }
+
     /// Returns the untrimmed left and right side of the road, oriented in the same direction of
     /// the road
     pub fn get_untrimmed_sides(&self, driving_side: DrivingSide) -> Result<(PolyLine, PolyLine)> {
@@ -447,10 +681,252 @@ impl Road {
 
 impl StreetNetwork {
     pub fn next_road_id(&mut self) -> RoadID {
-        let id = RoadID(self.road_id_counter);
-        self.road_id_counter += 1;
-        id
+        loop {
+            let id = RoadID(self.road_id_counter);
+            self.road_id_counter += 1;
+            // With stable IDs, the counter might run into a hashed ID
+            if !self.roads.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Picks the ID for a new road made from some OSM way segments. When
+    /// `MapConfig::stable_ids` is set, this is a hash of the segments, so the same road gets the
+    /// same ID when importing a slightly different area. Otherwise, IDs are assigned in order.
+    pub fn new_road_id(&mut self, osm_ids: &[OriginalRoad]) -> RoadID {
+        if !self.config.stable_ids || osm_ids.is_empty() {
+            return self.next_road_id();
+        }
+        let mut values = Vec::new();
+        for id in osm_ids {
+            values.extend([id.osm_way_id.0, id.i1.0, id.i2.0]);
+        }
+        let mut id = stable_hash(&values);
+        while self.roads.contains_key(&RoadID(id)) {
+            id = next_stable_id(id);
+        }
+        RoadID(id)
     }
+
+    /// Finds the road that an original OSM way segment is now part of. This still works after
+    /// roads are merged together. When the segment was collapsed into an intersection by
+    /// `collapse_short_road`, this returns a road at that intersection from the same OSM way, if
+    /// there is one. See `find_intersection_by_collapsed_road` to find the intersection instead.
+    pub fn find_road_by_original(&self, orig: OriginalRoad) -> Option<RoadID> {
+        if let Some(road) = self.roads.values().find(|r| r.osm_ids.contains(&orig)) {
+            return Some(road.id);
+        }
+        let i = self.find_intersection_by_collapsed_road(orig)?;
+        self.road_continuing_way(i, orig)
+    }
+
+    /// Like `find_road_by_original`, but for every original segment at once.
+    pub fn original_road_lookup(&self) -> BTreeMap<OriginalRoad, RoadID> {
+        let mut results = BTreeMap::new();
+        for road in self.roads.values() {
+            for orig in &road.osm_ids {
+                results.insert(*orig, road.id);
+            }
+        }
+        for i in self.intersections.values() {
+            for orig in &i.collapsed_osm_roads {
+                if let Some(r) = self.road_continuing_way(i.id, *orig) {
+                    results.insert(*orig, r);
+                }
+            }
+        }
+        results
+    }
+
+    /// Finds the intersection that a short road was collapsed into.
+    pub fn find_intersection_by_collapsed_road(
+        &self,
+        orig: OriginalRoad,
+    ) -> Option<IntersectionID> {
+        self.intersections
+            .values()
+            .find(|i| i.collapsed_osm_roads.contains(&orig))
+            .map(|i| i.id)
+    }
+
+    fn road_continuing_way(&self, i: IntersectionID, orig: OriginalRoad) -> Option<RoadID> {
+        self.roads_per_intersection(i)
+            .into_iter()
+            .find(|r| r.osm_ids.iter().any(|x| x.osm_way_id == orig.osm_way_id))
+            .map(|r| r.id)
+    }
+
+    /// A road with `Placement::Transition` moves between the placements of the roads it connects.
+    /// This works out those positions from the neighbouring roads and replaces the placement with
+    /// `Placement::Varying`. A side without a suitable neighbour uses `RoadPosition::Center`. Does
+    /// nothing for other placements.
+    ///
+    /// The transition spans the whole OSM way, so if the way was split into several roads, the
+    /// positions come from the neighbours at the ends of the way, and each road covers the part of
+    /// the transition given by its `way_fractions`.
+    pub fn resolve_transition_placement(&mut self, r: RoadID) {
+        if self.roads[&r].reference_line_placement != Placement::Transition {
+            return;
+        }
+        let first = self.end_of_way(r, true);
+        let last = self.end_of_way(r, false);
+        let start = self
+            .neighbor_position(first, self.roads[&first].src_i)
+            .unwrap_or(RoadPosition::Center);
+        let end = self
+            .neighbor_position(last, self.roads[&last].dst_i)
+            .unwrap_or(RoadPosition::Center);
+
+        let driving_side = self.config.driving_side;
+        let road = self.roads.get_mut(&r).unwrap();
+        road.reference_line_placement = Placement::Varying(start, end);
+        road.update_center_line(driving_side);
+    }
+
+    /// The road covering the start (or end) of the OSM way that `r` comes from. If that part of the
+    /// way is gone, falls back to `r`.
+    fn end_of_way(&self, r: RoadID, start: bool) -> RoadID {
+        let road = &self.roads[&r];
+        let covers_end = |other: &Road| {
+            if start {
+                other.way_fractions.0 == 0.0
+            } else {
+                other.way_fractions.1 == 1.0
+            }
+        };
+        if covers_end(road) {
+            return r;
+        }
+        let way = match road.osm_ids.first() {
+            Some(id) => id.osm_way_id,
+            None => return r,
+        };
+        self.roads
+            .values()
+            .find(|other| {
+                other.osm_ids.first().map(|id| id.osm_way_id) == Some(way) && covers_end(other)
+            })
+            .map(|other| other.id)
+            .unwrap_or(r)
+    }
+
+    /// The placement of the road continuing most straight from `r` through `i`, at `i`, and
+    /// interpreted in the direction of `r`.
+    fn neighbor_position(&self, r: RoadID, i: IntersectionID) -> Option<RoadPosition> {
+        let road = &self.roads[&r];
+        // Angles pointing away from i
+        let leaving = |road: &Road| {
+            if road.src_i == i {
+                road.reference_line.first_line().angle()
+            } else {
+                road.reference_line.last_line().angle().opposite()
+            }
+        };
+        let our_angle = leaving(road);
+
+        let other = self
+            .roads_per_intersection(i)
+            .into_iter()
+            .filter(|other| {
+                other.id != r && other.reference_line_placement != Placement::Transition
+            })
+            .min_by_key(|other| {
+                let rotation = leaving(other)
+                    .opposite()
+                    .simple_shortest_rotation_towards(our_angle);
+                (rotation.abs() * 1000.0) as usize
+            })?;
+
+        let position = match other.reference_line_placement {
+            Placement::Consistent(p) => p,
+            Placement::Varying(start, end) => {
+                if other.dst_i == i {
+                    end
+                } else {
+                    start
+                }
+            }
+            Placement::Transition => unreachable!(),
+        };
+        // Lanes are numbered relative to the direction of each road
+        let same_direction = if road.src_i == i {
+            other.dst_i == i
+        } else {
+            other.src_i == i
+        };
+        if same_direction {
+            Some(position)
+        } else {
+            Some(position.reverse())
+        }
+    }
+}
+
+fn whole_way() -> (f64, f64) {
+    (0.0, 1.0)
+}
+
+/// Shifts a line by an offset varying linearly from `start` to `end` along its length. Positive
+/// offsets shift right.
+fn shift_varying(pl: &PolyLine, start: Distance, end: Distance) -> Result<PolyLine> {
+    if start == end {
+        return pl.shift_either_direction(start);
+    }
+    shift_along(pl, &[], |fraction| start + (end - start) * fraction)
+}
+
+/// Shifts a line by an offset that changes along it. `offset_at` takes a fraction of the way
+/// along the line. Each point moves perpendicular to the direction of the line there, and points
+/// are added at each of the sorted `breakpoints`, so offsets changing linearly between them are
+/// followed exactly.
+fn shift_along<F: Fn(f64) -> Distance>(
+    pl: &PolyLine,
+    breakpoints: &[f64],
+    offset_at: F,
+) -> Result<PolyLine> {
+    let pts = pl.points();
+    let length = pl.length();
+    let mut result = Vec::new();
+    let mut dist = Distance::ZERO;
+    for (idx, pt) in pts.iter().enumerate() {
+        if idx > 0 {
+            let prev = pts[idx - 1];
+            let segment = prev.dist_to(*pt);
+            let angle = prev.angle_to(*pt);
+            for fraction in breakpoints {
+                let along = length * *fraction - dist;
+                if along > Distance::meters(0.01) && along < segment - Distance::meters(0.01) {
+                    let between = prev.project_away(along, angle);
+                    result.push(shift_pt(between, angle, offset_at(*fraction)));
+                }
+            }
+            dist += segment;
+        }
+        // Approximate the direction at each point by its neighbours
+        let prev = pts[idx.saturating_sub(1)];
+        let next = pts[(idx + 1).min(pts.len() - 1)];
+        result.push(shift_pt(*pt, prev.angle_to(next), offset_at(dist / length)));
+    }
+    PolyLine::deduping_new(result)
+}
+
+fn shift_pt(pt: Pt2D, angle: Angle, offset: Distance) -> Pt2D {
+    if offset == Distance::ZERO {
+        pt
+    } else if offset > Distance::ZERO {
+        pt.project_away(offset, angle.rotate_degs(90.0))
+    } else {
+        pt.project_away(-offset, angle.rotate_degs(-90.0))
+    }
+}
+
+/// The polygon between two lines facing the same direction
+fn polygon_between(left: &PolyLine, right: &PolyLine) -> Result<Polygon> {
+    let mut pts = left.points().clone();
+    pts.extend(right.points().iter().rev());
+    pts.push(pts[0]);
+    Ok(Ring::deduping_new(pts)?.into_polygon())
 }
 
 /// The edge of a road, pointed into some intersection
@@ -493,3 +969,205 @@ impl RoadEdge {
         edges
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lanes::LtrLaneNum;
+    use crate::WidthProfile;
+
+    #[test]
+    fn test_shift_varying() {
+        let straight = PolyLine::must_new(vec![Pt2D::new(0.0, 0.0), Pt2D::new(100.0, 0.0)]);
+        let bent = PolyLine::must_new(vec![
+            Pt2D::new(0.0, 0.0),
+            Pt2D::new(100.0, 0.0),
+            Pt2D::new(100.0, 100.0),
+        ]);
+        let mut ok = true;
+        for (description, pl, start, end, expected) in [
+            (
+                "a constant shift",
+                &straight,
+                5.0,
+                5.0,
+                vec![Pt2D::new(0.0, 5.0), Pt2D::new(100.0, 5.0)],
+            ),
+            (
+                "a shift growing to the right",
+                &straight,
+                0.0,
+                10.0,
+                vec![Pt2D::new(0.0, 0.0), Pt2D::new(100.0, 10.0)],
+            ),
+            (
+                "a shift growing to the left",
+                &straight,
+                0.0,
+                -10.0,
+                vec![Pt2D::new(0.0, 0.0), Pt2D::new(100.0, -10.0)],
+            ),
+            (
+                // The corner moves halfway, along the bisector of the turn
+                "a shift around a corner",
+                &bent,
+                0.0,
+                20.0,
+                vec![
+                    Pt2D::new(0.0, 0.0),
+                    Pt2D::new(100.0 - 50.0_f64.sqrt(), 50.0_f64.sqrt()),
+                    Pt2D::new(80.0, 100.0),
+                ],
+            ),
+        ] {
+            let actual = shift_varying(pl, Distance::meters(start), Distance::meters(end)).unwrap();
+            let same = actual.points().len() == expected.len()
+                && actual
+                    .points()
+                    .iter()
+                    .zip(&expected)
+                    .all(|(pt1, pt2)| pt1.approx_eq(*pt2, Distance::meters(0.01)));
+            if !same {
+                ok = false;
+                println!("For {description}");
+                println!("Got: {:?}", actual.points());
+                println!("Expected: {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+
+    #[test]
+    fn test_resolve_transition_placement() {
+        use LtrLaneNum::*;
+        use RoadPosition::*;
+
+        let mut ok = true;
+        // One way split in the middle, moving from the placement of the road to the west to the
+        // placement of whatever continues at the east end
+        for (description, east, north, expected_end) in [
+            (
+                "the way continuing east",
+                Some(false),
+                true,
+                RightOf(Forward(1)),
+            ),
+            (
+                "the way continuing east, drawn the other way",
+                Some(true),
+                true,
+                RightOf(Backward(1)),
+            ),
+            (
+                "only a side road at the end",
+                None,
+                true,
+                MiddleOf(Forward(1)),
+            ),
+            ("nothing at the end", None, false, Center),
+        ] {
+            let mut streets = StreetNetwork::blank();
+            let west_i = streets.add_test_intersection(-100.0, 0.0);
+            let a = streets.add_test_intersection(0.0, 0.0);
+            let middle = streets.add_test_intersection(100.0, 0.0);
+            let b = streets.add_test_intersection(200.0, 0.0);
+            let east_i = streets.add_test_intersection(300.0, 0.0);
+            let north_i = streets.add_test_intersection(200.0, -100.0);
+
+            let mut neighbors = vec![(
+                streets.add_test_road(west_i, a, "dd", "v^"),
+                LeftOf(Forward(1)),
+            )];
+            match east {
+                Some(false) => neighbors.push((
+                    streets.add_test_road(b, east_i, "dd", "v^"),
+                    RightOf(Forward(1)),
+                )),
+                Some(true) => neighbors.push((
+                    streets.add_test_road(east_i, b, "dd", "v^"),
+                    RightOf(Forward(1)),
+                )),
+                None => {}
+            }
+            if north {
+                neighbors.push((
+                    streets.add_test_road(b, north_i, "dd", "v^"),
+                    MiddleOf(Forward(1)),
+                ));
+            }
+            for (r, position) in neighbors {
+                streets.roads.get_mut(&r).unwrap().reference_line_placement =
+                    Placement::Consistent(position);
+            }
+
+            let pieces = [
+                (streets.add_test_road(a, middle, "dd", "v^"), (0.0, 0.5)),
+                (streets.add_test_road(middle, b, "dd", "v^"), (0.5, 1.0)),
+            ];
+            for (r, way_fractions) in pieces {
+                let road = streets.roads.get_mut(&r).unwrap();
+                road.osm_ids = vec![OriginalRoad::new(50, (a.0 as i64, b.0 as i64))];
+                road.way_fractions = way_fractions;
+                road.reference_line_placement = Placement::Transition;
+            }
+            // Resolve the later piece first, while the other is still a transition
+            for (r, _) in pieces.iter().rev() {
+                streets.resolve_transition_placement(*r);
+            }
+
+            let expected = Placement::Varying(LeftOf(Forward(1)), expected_end);
+            for (r, _) in pieces {
+                let actual = streets.roads[&r].reference_line_placement;
+                if actual != expected {
+                    ok = false;
+                    println!("For {description}, {r}");
+                    println!("Got: {actual:?}");
+                    println!("Expected: {expected:?}");
+                    println!();
+                }
+            }
+            // The pieces still line up where the way was split
+            let joint1 = streets.roads[&pieces[0].0].center_line.last_pt();
+            let joint2 = streets.roads[&pieces[1].0].center_line.first_pt();
+            if !joint1.approx_eq(joint2, Distance::meters(0.01)) {
+                ok = false;
+                println!("For {description}, the pieces meet at {joint1} and {joint2}");
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+
+    #[test]
+    fn test_width_profile_along_the_way() {
+        let mut streets = StreetNetwork::blank();
+        let i1 = streets.add_test_intersection(0.0, 0.0);
+        let i2 = streets.add_test_intersection(100.0, 0.0);
+        let r = streets.add_test_road(i1, i2, "dd", "v^");
+        let road = streets.roads.get_mut(&r).unwrap();
+        road.lane_specs_ltr[0].width = Distance::meters(3.0);
+        road.lane_specs_ltr[1].set_width_profile(WidthProfile::linear(
+            Distance::meters(3.0),
+            Distance::meters(5.0),
+        ));
+        let close = |pt1: Pt2D, pt2: Pt2D| pt1.approx_eq(pt2, Distance::meters(0.01));
+
+        // Trimmed back from both intersections, the right edge still follows the widths at those
+        // points of the road as tagged
+        road.center_line = PolyLine::must_new(vec![Pt2D::new(20.0, 0.0), Pt2D::new(80.0, 0.0)]);
+        assert!((road.untrimmed_fraction(0.0) - 0.2).abs() < 0.001);
+        assert!((road.untrimmed_fraction(1.0) - 0.8).abs() < 0.001);
+        let right_edge = road.get_lane_edges().pop().unwrap();
+        assert!(close(right_edge.first_pt(), Pt2D::new(20.0, 3.2)));
+        assert!(close(right_edge.last_pt(), Pt2D::new(80.0, 3.8)));
+
+        // The second half of a way only covers the second half of the change
+        road.way_fractions = (0.5, 1.0);
+        road.slice_width_profiles_to_way();
+        let lane = &road.lane_specs_ltr[1];
+        assert_eq!(lane.width_at(0.0), Distance::meters(4.0));
+        assert_eq!(lane.width_at(1.0), Distance::meters(5.0));
+        assert_eq!(lane.width, Distance::meters(5.0));
+    }
+}
@@ -0,0 +1,492 @@
+use serde::{Deserialize, Serialize};
+
+use abstutil::Tags;
+use geom::{LonLat, Speed};
+
+use crate::osm;
+use crate::restrictions::split_conditional;
+
+/// The speed limit in each direction of a road.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpeedLimits {
+    pub fwd: SpeedLimit,
+    pub back: SpeedLimit,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpeedLimit {
+    pub speed: Speed,
+    /// The unit the limit is expressed in, so it can be displayed the way it's signed
+    pub unit: SpeedUnit,
+    pub source: SpeedLimitSource,
+    /// Other limits that apply only some of the time, from `maxspeed:conditional`. The condition
+    /// is kept as written, like `Mo-Fr 07:00-09:00` or `wet`.
+    pub conditional: Vec<(Speed, String)>,
+    /// Limits for individual lanes from `maxspeed:lanes`, ordered left to right from the
+    /// perspective of somebody travelling in this direction. Empty if not tagged; None for lanes
+    /// without a value.
+    pub per_lane: Vec<Option<Speed>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpeedUnit {
+    KmPerHour,
+    MilesPerHour,
+}
+
+impl SpeedUnit {
+    /// The unit speed limits are signed in at some point. Only the UK, the Isle of Man, and the
+    /// Channel Islands use mph; the rest of the world uses km/h. (The US also uses mph, but its
+    /// defaults have always been in km/h here.) The areas are rough boxes, so places near the
+    /// border between Northern Ireland and Ireland may get the wrong unit.
+    pub fn signed_at(pt: LonLat) -> SpeedUnit {
+        // (min longitude, min latitude, max longitude, max latitude)
+        let mph_areas = [
+            // England and Wales, south of Dublin's latitude
+            (-5.8, 49.8, 1.8, 53.0),
+            // Northern England, north Wales, and the Isle of Man
+            (-4.8, 53.0, 1.8, 55.0),
+            // Northern Ireland and Galloway
+            (-8.2, 54.05, -4.8, 55.0),
+            // Scotland
+            (-7.7, 55.0, -0.7, 61.0),
+            // The Channel Islands
+            (-2.7, 49.1, -2.0, 49.8),
+        ];
+        let (lon, lat) = (pt.x(), pt.y());
+        if mph_areas.iter().any(|(lon1, lat1, lon2, lat2)| {
+            *lon1 <= lon && lon <= *lon2 && *lat1 <= lat && lat <= *lat2
+        }) {
+            SpeedUnit::MilesPerHour
+        } else {
+            SpeedUnit::KmPerHour
+        }
+    }
+}
+
+/// Where a speed limit came from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpeedLimitSource {
+    /// An explicit `maxspeed` value
+    Tagged,
+    /// A zone like `DE:urban`, from `maxspeed`, `source:maxspeed`, or `maxspeed:type`
+    Zone(String),
+    /// Nothing was tagged, so this is a guess from `highway` and the driving side
+    Default,
+}
+
+impl SpeedLimits {
+    /// Reads `maxspeed` and its directional, conditional, and per-lane forms. Directions without
+    /// any tagged limit get a zone or default limit, in `default_unit`.
+    pub fn new(tags: &Tags, default_unit: SpeedUnit) -> Self {
+        let highway_type = tags.get(osm::HIGHWAY).map(|x| x.as_str()).unwrap_or("");
+        let default = SpeedLimit::tagged(tags, "maxspeed")
+            .unwrap_or_else(|| SpeedLimit::default_for(highway_type, default_unit));
+
+        let mut fwd =
+            SpeedLimit::tagged(tags, "maxspeed:forward").unwrap_or_else(|| default.clone());
+        let mut back =
+            SpeedLimit::tagged(tags, "maxspeed:backward").unwrap_or_else(|| default.clone());
+
+        let mut conditional = parse_conditional(tags, "maxspeed:conditional");
+        fwd.conditional = parse_conditional(tags, "maxspeed:forward:conditional");
+        if fwd.conditional.is_empty() {
+            fwd.conditional = conditional.clone();
+        }
+        back.conditional = parse_conditional(tags, "maxspeed:backward:conditional");
+        if back.conditional.is_empty() {
+            back.conditional = std::mem::take(&mut conditional);
+        }
+
+        fwd.per_lane = parse_lanes(tags, "maxspeed:lanes:forward");
+        back.per_lane = parse_lanes(tags, "maxspeed:lanes:backward");
+        // On one-way roads, the plain key describes the only direction
+        if tags.is("oneway", "-1") {
+            if back.per_lane.is_empty() {
+                back.per_lane = parse_lanes(tags, "maxspeed:lanes");
+            }
+        } else if tags.is("oneway", "yes") || tags.is("junction", "roundabout") {
+            if fwd.per_lane.is_empty() {
+                fwd.per_lane = parse_lanes(tags, "maxspeed:lanes");
+            }
+        }
+
+        Self { fwd, back }
+    }
+
+    /// Combines the limits of two roads being merged into one. Explicitly tagged limits win over
+    /// guessed ones. If `other` points the opposite way, its directions are swapped first.
+    pub fn merge(&mut self, mut other: SpeedLimits, reversed: bool) {
+        if reversed {
+            std::mem::swap(&mut other.fwd, &mut other.back);
+        }
+        if self.fwd.source == SpeedLimitSource::Default
+            && other.fwd.source != SpeedLimitSource::Default
+        {
+            self.fwd = other.fwd;
+        }
+        if self.back.source == SpeedLimitSource::Default
+            && other.back.source != SpeedLimitSource::Default
+        {
+            self.back = other.back;
+        }
+    }
+}
+
+impl SpeedLimit {
+    /// Interprets one `maxspeed` key. Zone values like `DE:urban` are looked up in a table. Values
+    /// that aren't a fixed number, like `none`, `signals`, or `walk`, are ignored.
+    fn tagged(tags: &Tags, key: &str) -> Option<SpeedLimit> {
+        let value = tags.get(key)?;
+        if let Some((speed, unit)) = parse_speed(value) {
+            let source = match zone_tag(tags) {
+                Some(zone) if key == "maxspeed" => SpeedLimitSource::Zone(zone),
+                _ => SpeedLimitSource::Tagged,
+            };
+            return Some(SpeedLimit::new(speed, unit, source));
+        }
+        if let Some((speed, unit)) = zone_speed(value) {
+            return Some(SpeedLimit::new(
+                speed,
+                unit,
+                SpeedLimitSource::Zone(value.to_string()),
+            ));
+        }
+        if key == "maxspeed" {
+            if let Some(zone) = zone_tag(tags) {
+                if let Some((speed, unit)) = zone_speed(&zone) {
+                    return Some(SpeedLimit::new(speed, unit, SpeedLimitSource::Zone(zone)));
+                }
+            }
+        }
+        None
+    }
+
+    /// A rough guess from the highway type. Without knowing the country, this distinguishes only
+    /// by unit: places signed in mph get typical UK limits, and places signed in km/h get typical
+    /// continental European limits. See `SpeedUnit::signed_at`.
+    ///
+    /// | highway                         | mph | km/h |
+    /// |---------------------------------|-----|------|
+    /// | motorway                        | 70  | 120  |
+    /// | trunk                           | 60  | 100  |
+    /// | primary                         | 40  | 70   |
+    /// | secondary, tertiary             | 30  | 50   |
+    /// | unclassified, residential, etc  | 30  | 50   |
+    /// | living_street                   | 10  | 10   |
+    /// | service                         | 10  | 20   |
+    ///
+    /// `_link` roads use the same limit as what they link. Residential roads get the same default
+    /// as other local roads; 30 km/h zones need to be tagged.
+    pub fn default_for(highway_type: &str, unit: SpeedUnit) -> SpeedLimit {
+        let (mph, kmh) = match highway_type.trim_end_matches("_link") {
+            "motorway" => (70.0, 120.0),
+            "trunk" => (60.0, 100.0),
+            "primary" => (40.0, 70.0),
+            "living_street" => (10.0, 10.0),
+            "service" => (10.0, 20.0),
+            _ => (30.0, 50.0),
+        };
+        let speed = match unit {
+            SpeedUnit::MilesPerHour => Speed::miles_per_hour(mph),
+            SpeedUnit::KmPerHour => Speed::km_per_hour(kmh),
+        };
+        SpeedLimit::new(speed, unit, SpeedLimitSource::Default)
+    }
+
+    fn new(speed: Speed, unit: SpeedUnit, source: SpeedLimitSource) -> SpeedLimit {
+        SpeedLimit {
+            speed,
+            unit,
+            source,
+            conditional: Vec::new(),
+            per_lane: Vec::new(),
+        }
+    }
+
+    /// The limit for some lane (indexed in this direction, left to right), if it's tagged
+    /// separately.
+    pub fn for_lane(&self, idx: usize) -> Speed {
+        self.per_lane
+            .get(idx)
+            .copied()
+            .flatten()
+            .unwrap_or(self.speed)
+    }
+}
+
+/// Parses values like `50`, `30 mph`, or `50 km/h`. km/h is the default unit.
+fn parse_speed(value: &str) -> Option<(Speed, SpeedUnit)> {
+    let value = value.trim();
+    let (number, unit) = if let Some(x) = value.strip_suffix("mph") {
+        (x, SpeedUnit::MilesPerHour)
+    } else if let Some(x) = value
+        .strip_suffix("km/h")
+        .or_else(|| value.strip_suffix("kmh"))
+        .or_else(|| value.strip_suffix("kph"))
+    {
+        (x, SpeedUnit::KmPerHour)
+    } else {
+        (value, SpeedUnit::KmPerHour)
+    };
+    let number = number.trim().parse::<f64>().ok()?;
+    Some((to_speed(number, unit), unit))
+}
+
+/// Finds a zone like `DE:urban` describing the limit, from the tags that usually hold it.
+fn zone_tag(tags: &Tags) -> Option<String> {
+    for key in ["source:maxspeed", "maxspeed:type", "zone:maxspeed"] {
+        if let Some(value) = tags.get(key) {
+            if value.contains(':') {
+                return Some(value.clone());
+            }
+        }
+    }
+    None
+}
+
+/// Looks up the implicit limit of a zone, ignoring case. Zones like `DE:zone30` or `FR:zone:30`
+/// carry their value. See <https://wiki.openstreetmap.org/wiki/Default_speed_limits>.
+fn zone_speed(zone: &str) -> Option<(Speed, SpeedUnit)> {
+    let (country, kind) = zone.split_once(':')?;
+    let country = country.trim().to_uppercase();
+    let kind = kind.trim().to_lowercase();
+    let (country, kind) = (country.as_str(), kind.as_str());
+    if let Some(value) = kind
+        .strip_prefix("zone:")
+        .or_else(|| kind.strip_prefix("zone"))
+    {
+        let unit = if country == "GB" || country == "UK" || country == "US" {
+            SpeedUnit::MilesPerHour
+        } else {
+            SpeedUnit::KmPerHour
+        };
+        let number = value.parse::<f64>().ok()?;
+        return Some((to_speed(number, unit), unit));
+    }
+
+    let (number, unit) = match (country, kind) {
+        ("GB" | "UK", "nsl_single") => (60.0, SpeedUnit::MilesPerHour),
+        ("GB" | "UK", "nsl_dual" | "motorway") => (70.0, SpeedUnit::MilesPerHour),
+        ("GB" | "UK", "urban") => (30.0, SpeedUnit::MilesPerHour),
+        (_, "living_street" | "walk") => (7.0, SpeedUnit::KmPerHour),
+        ("AT" | "DE", "rural") => (100.0, SpeedUnit::KmPerHour),
+        ("AT" | "FR" | "IT" | "NL", "motorway") => (130.0, SpeedUnit::KmPerHour),
+        ("BE" | "CH" | "ES", "motorway") => (120.0, SpeedUnit::KmPerHour),
+        ("BE" | "CH" | "FR" | "NL", "rural") => (80.0, SpeedUnit::KmPerHour),
+        ("ES" | "IT", "rural") => (90.0, SpeedUnit::KmPerHour),
+        (_, "urban") => (50.0, SpeedUnit::KmPerHour),
+        _ => return None,
+    };
+    Some((to_speed(number, unit), unit))
+}
+
+fn to_speed(number: f64, unit: SpeedUnit) -> Speed {
+    match unit {
+        SpeedUnit::KmPerHour => Speed::km_per_hour(number),
+        SpeedUnit::MilesPerHour => Speed::miles_per_hour(number),
+    }
+}
+
+fn parse_conditional(tags: &Tags, key: &str) -> Vec<(Speed, String)> {
+    let mut results = Vec::new();
+    if let Some(raw) = tags.get(key) {
+        for (value, condition) in split_conditional(raw) {
+            if let Some((speed, _)) = parse_speed(&value) {
+                results.push((speed, condition));
+            }
+        }
+    }
+    results
+}
+
+fn parse_lanes(tags: &Tags, key: &str) -> Vec<Option<Speed>> {
+    match tags.get(key) {
+        Some(raw) => raw
+            .split('|')
+            .map(|value| parse_speed(value).map(|(speed, _)| speed))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_speed_limits() {
+        let mut ok = true;
+        for (input, fwd, back) in vec![
+            (
+                vec!["highway=residential", "maxspeed=30"],
+                (
+                    Speed::km_per_hour(30.0),
+                    SpeedUnit::KmPerHour,
+                    SpeedLimitSource::Tagged,
+                ),
+                (
+                    Speed::km_per_hour(30.0),
+                    SpeedUnit::KmPerHour,
+                    SpeedLimitSource::Tagged,
+                ),
+            ),
+            (
+                vec![
+                    "highway=primary",
+                    "maxspeed:forward=40 mph",
+                    "maxspeed:backward=30 mph",
+                ],
+                (
+                    Speed::miles_per_hour(40.0),
+                    SpeedUnit::MilesPerHour,
+                    SpeedLimitSource::Tagged,
+                ),
+                (
+                    Speed::miles_per_hour(30.0),
+                    SpeedUnit::MilesPerHour,
+                    SpeedLimitSource::Tagged,
+                ),
+            ),
+            (
+                vec!["highway=secondary", "maxspeed=DE:rural"],
+                (
+                    Speed::km_per_hour(100.0),
+                    SpeedUnit::KmPerHour,
+                    SpeedLimitSource::Zone("DE:rural".to_string()),
+                ),
+                (
+                    Speed::km_per_hour(100.0),
+                    SpeedUnit::KmPerHour,
+                    SpeedLimitSource::Zone("DE:rural".to_string()),
+                ),
+            ),
+            (
+                vec!["highway=tertiary", "source:maxspeed=GB:nsl_single"],
+                (
+                    Speed::miles_per_hour(60.0),
+                    SpeedUnit::MilesPerHour,
+                    SpeedLimitSource::Zone("GB:nsl_single".to_string()),
+                ),
+                (
+                    Speed::miles_per_hour(60.0),
+                    SpeedUnit::MilesPerHour,
+                    SpeedLimitSource::Zone("GB:nsl_single".to_string()),
+                ),
+            ),
+            (
+                vec!["highway=residential", "source:maxspeed=de:urban"],
+                (
+                    Speed::km_per_hour(50.0),
+                    SpeedUnit::KmPerHour,
+                    SpeedLimitSource::Zone("de:urban".to_string()),
+                ),
+                (
+                    Speed::km_per_hour(50.0),
+                    SpeedUnit::KmPerHour,
+                    SpeedLimitSource::Zone("de:urban".to_string()),
+                ),
+            ),
+            (
+                vec!["highway=secondary", "maxspeed=DE:Rural"],
+                (
+                    Speed::km_per_hour(100.0),
+                    SpeedUnit::KmPerHour,
+                    SpeedLimitSource::Zone("DE:Rural".to_string()),
+                ),
+                (
+                    Speed::km_per_hour(100.0),
+                    SpeedUnit::KmPerHour,
+                    SpeedLimitSource::Zone("DE:Rural".to_string()),
+                ),
+            ),
+            (
+                vec!["highway=motorway", "maxspeed=none"],
+                (
+                    Speed::km_per_hour(120.0),
+                    SpeedUnit::KmPerHour,
+                    SpeedLimitSource::Default,
+                ),
+                (
+                    Speed::km_per_hour(120.0),
+                    SpeedUnit::KmPerHour,
+                    SpeedLimitSource::Default,
+                ),
+            ),
+        ] {
+            let mut tags = Tags::empty();
+            for kv in &input {
+                let parts = kv.split_once('=').unwrap();
+                tags.insert(parts.0, parts.1);
+            }
+            let actual = SpeedLimits::new(&tags, SpeedUnit::KmPerHour);
+            let actual = (
+                (actual.fwd.speed, actual.fwd.unit, actual.fwd.source),
+                (actual.back.speed, actual.back.unit, actual.back.source),
+            );
+            if actual != (fwd.clone(), back.clone()) {
+                ok = false;
+                println!("For input {:?}", input);
+                println!("Got:      {:?}", actual);
+                println!("Expected: {:?}", (fwd, back));
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+
+    #[test]
+    fn test_conditional_and_lanes() {
+        let mut tags = Tags::empty();
+        tags.insert("highway", "primary");
+        tags.insert("oneway", "yes");
+        tags.insert("maxspeed", "50");
+        tags.insert("maxspeed:conditional", "30 @ (Mo-Fr 07:00-09:00)");
+        tags.insert("maxspeed:lanes", "50|30");
+        let limits = SpeedLimits::new(&tags, SpeedUnit::KmPerHour);
+
+        assert_eq!(
+            limits.fwd.conditional,
+            vec![(Speed::km_per_hour(30.0), "Mo-Fr 07:00-09:00".to_string())]
+        );
+        assert_eq!(limits.fwd.for_lane(0), Speed::km_per_hour(50.0));
+        assert_eq!(limits.fwd.for_lane(1), Speed::km_per_hour(30.0));
+        assert_eq!(limits.fwd.for_lane(2), Speed::km_per_hour(50.0));
+        assert!(limits.back.per_lane.is_empty());
+    }
+
+    #[test]
+    fn test_default_units() {
+        let mut ok = true;
+        for (description, lon, lat, expected) in vec![
+            ("London", -0.13, 51.51, SpeedUnit::MilesPerHour),
+            ("Edinburgh", -3.19, 55.95, SpeedUnit::MilesPerHour),
+            ("Belfast", -5.93, 54.6, SpeedUnit::MilesPerHour),
+            ("Dublin", -6.26, 53.35, SpeedUnit::KmPerHour),
+            ("Sydney", 151.21, -33.87, SpeedUnit::KmPerHour),
+            ("Tokyo", 139.69, 35.69, SpeedUnit::KmPerHour),
+            ("Mumbai", 72.88, 19.08, SpeedUnit::KmPerHour),
+            ("Johannesburg", 28.05, -26.2, SpeedUnit::KmPerHour),
+            ("Paris", 2.35, 48.86, SpeedUnit::KmPerHour),
+        ] {
+            let actual = SpeedUnit::signed_at(LonLat::new(lon, lat));
+            if actual != expected {
+                ok = false;
+                println!("For {description}");
+                println!("Got: {actual:?}");
+                println!("Expected: {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+
+        // Left-handed places outside the UK still get km/h defaults
+        let mut tags = Tags::empty();
+        tags.insert("highway", "primary");
+        let sydney = SpeedUnit::signed_at(LonLat::new(151.21, -33.87));
+        let limits = SpeedLimits::new(&tags, sydney);
+        assert_eq!(limits.fwd.speed, Speed::km_per_hour(70.0));
+        assert_eq!(limits.fwd.unit, SpeedUnit::KmPerHour);
+        assert_eq!(limits.fwd.source, SpeedLimitSource::Default);
+    }
+}
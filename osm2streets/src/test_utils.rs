@@ -0,0 +1,71 @@
+use abstutil::Tags;
+use geom::{PolyLine, Pt2D};
+
+use crate::{
+    osm, IntersectionControl, IntersectionID, IntersectionKind, LaneSpec, OriginalRoad, Road,
+    RoadID, StreetNetwork,
+};
+
+impl StreetNetwork {
+    /// Adds an intersection at a point, without any OSM nodes. Useful for unit tests.
+    pub fn add_test_intersection(&mut self, x: f64, y: f64) -> IntersectionID {
+        self.insert_intersection(
+            Vec::new(),
+            Pt2D::new(x, y),
+            IntersectionKind::Intersection,
+            IntersectionControl::Uncontrolled,
+        )
+    }
+
+    /// Adds a straight residential road between two intersections. The lanes are described like
+    /// `LaneSpec::create_for_test`, with typical widths. The road's single `OriginalRoad` uses the
+    /// road and intersection IDs. Useful for unit tests.
+    pub fn add_test_road(
+        &mut self,
+        src_i: IntersectionID,
+        dst_i: IntersectionID,
+        lt: &str,
+        dir: &str,
+    ) -> RoadID {
+        let id = self.next_road_id();
+        let mut tags = Tags::empty();
+        tags.insert("highway", "residential");
+        let mut road = Road::new(
+            id,
+            vec![OriginalRoad::new(
+                id.0 as i64,
+                (src_i.0 as i64, dst_i.0 as i64),
+            )],
+            src_i,
+            dst_i,
+            PolyLine::must_new(vec![
+                self.intersections[&src_i].point,
+                self.intersections[&dst_i].point,
+            ]),
+            tags,
+            &self.config,
+        );
+        road.lane_specs_ltr = LaneSpec::create_for_test(lt, dir);
+        for lane in &mut road.lane_specs_ltr {
+            lane.width = LaneSpec::typical_lane_width(lane.lt);
+        }
+        road.update_center_line(self.config.driving_side);
+        self.insert_road(road);
+        id
+    }
+
+    /// Like `add_test_road`, but the road's `OriginalRoad` belongs to an OSM way, so several roads
+    /// can come from the same way. Useful for unit tests.
+    pub fn add_test_road_for_way(
+        &mut self,
+        way: i64,
+        src_i: IntersectionID,
+        dst_i: IntersectionID,
+        lt: &str,
+        dir: &str,
+    ) -> RoadID {
+        let id = self.add_test_road(src_i, dst_i, lt, dir);
+        self.roads.get_mut(&id).unwrap().osm_ids[0].osm_way_id = osm::WayID(way);
+        id
+    }
+}
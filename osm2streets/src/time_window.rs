@@ -0,0 +1,164 @@
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const MINUTES_PER_WEEK: u32 = 7 * MINUTES_PER_DAY;
+const WEEKDAYS: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+
+/// The subset of the `opening_hours` syntax used by conditional restrictions and access, like
+/// `Mo-Fr 07:00-09:00,16:00-18:00; Sa 10:00-12:00`. Only weekdays, hour ranges, and `24/7` are
+/// understood. Holidays, months, and everything else aren't.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeWindow {
+    /// Half-open spans of minutes since Monday 00:00
+    spans: Vec<(u32, u32)>,
+}
+
+impl TimeWindow {
+    /// Returns `None` if the window uses anything outside of the understood subset.
+    pub fn parse(raw: &str) -> Option<TimeWindow> {
+        let mut spans = Vec::new();
+        for rule in raw.split(';') {
+            let rule = rule.trim();
+            if rule.is_empty() {
+                continue;
+            }
+            if rule == "24/7" {
+                spans.push((0, MINUTES_PER_WEEK));
+                continue;
+            }
+            let (days, hours) = match rule.split_once(' ') {
+                Some((days, hours)) => (parse_days(days)?, parse_hours(hours)?),
+                None if rule.starts_with(|c: char| c.is_ascii_digit()) => {
+                    ((0..7).collect(), parse_hours(rule)?)
+                }
+                None => (parse_days(rule)?, vec![(0, MINUTES_PER_DAY)]),
+            };
+            for day in days {
+                let start_of_day = day * MINUTES_PER_DAY;
+                for (start, end) in &hours {
+                    if start < end {
+                        spans.push((start_of_day + start, start_of_day + end));
+                    } else {
+                        // The range continues past midnight, into the next day
+                        spans.push((start_of_day + start, start_of_day + MINUTES_PER_DAY));
+                        let next_day = ((day + 1) % 7) * MINUTES_PER_DAY;
+                        if *end > 0 {
+                            spans.push((next_day, next_day + end));
+                        }
+                    }
+                }
+            }
+        }
+        if spans.is_empty() {
+            return None;
+        }
+        Some(TimeWindow { spans })
+    }
+
+    /// True if there's any moment inside both windows.
+    pub fn overlaps(&self, other: &TimeWindow) -> bool {
+        self.spans.iter().any(|(start1, end1)| {
+            other
+                .spans
+                .iter()
+                .any(|(start2, end2)| start1 < end2 && start2 < end1)
+        })
+    }
+}
+
+/// True if two time windows from tags overlap. When either one can't be parsed, the raw strings
+/// have to match instead.
+pub(crate) fn time_windows_overlap(a: &str, b: &str) -> bool {
+    match (TimeWindow::parse(a), TimeWindow::parse(b)) {
+        (Some(a), Some(b)) => a.overlaps(&b),
+        _ => a.trim() == b.trim(),
+    }
+}
+
+/// Parses something like `Mo-Fr` or `Sa,Su` into day indices, starting from Monday.
+fn parse_days(raw: &str) -> Option<Vec<u32>> {
+    let mut days = Vec::new();
+    for part in raw.split(',') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let to = parse_day(to)?;
+                let mut day = parse_day(from)?;
+                // Ranges like Fr-Mo wrap around the week
+                loop {
+                    days.push(day);
+                    if day == to {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            }
+            None => days.push(parse_day(part)?),
+        }
+    }
+    Some(days)
+}
+
+fn parse_day(raw: &str) -> Option<u32> {
+    WEEKDAYS
+        .iter()
+        .position(|day| *day == raw.trim())
+        .map(|idx| idx as u32)
+}
+
+/// Parses something like `07:00-09:00,16:00-18:00` into minutes since midnight.
+fn parse_hours(raw: &str) -> Option<Vec<(u32, u32)>> {
+    raw.split(',')
+        .map(|range| {
+            let (start, end) = range.trim().split_once('-')?;
+            Some((parse_time(start)?, parse_time(end)?))
+        })
+        .collect()
+}
+
+fn parse_time(raw: &str) -> Option<u32> {
+    let (hours, minutes) = raw.trim().split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    if minutes >= 60 || hours * 60 + minutes > MINUTES_PER_DAY {
+        return None;
+    }
+    Some(hours * 60 + minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_windows_overlap() {
+        let mut ok = true;
+        for (a, b, expected) in [
+            ("Mo-Fr 07:00-09:00", "Mo-Fr 07:00-09:00", true),
+            ("Mo-Fr 07:00-09:00", "Mo-Fr 08:30-09:30", true),
+            ("Mo-Fr 07:00-09:00", "Mo-Fr 09:00-10:00", false),
+            ("Mo-Fr 07:00-09:00", "We", true),
+            ("Mo-Fr 07:00-09:00", "Sa", false),
+            ("Mo-Fr 07:00-09:00", "Sa,Su", false),
+            ("Mo-Fr 07:00-09:00,16:00-18:00", "17:00-17:30", true),
+            ("Mo-Fr 07:00-09:00; Sa 10:00-12:00", "Sa 11:00-11:30", true),
+            // Wrapping day ranges and hours past midnight
+            ("Fr-Mo", "Su 12:00-13:00", true),
+            ("Fr-Mo", "We", false),
+            ("Su 22:00-02:00", "Mo 01:00-03:00", true),
+            ("Sa 22:00-02:00", "Mo 01:00-03:00", false),
+            ("24/7", "Tu 03:00-04:00", true),
+            // Outside of the understood subset, only the same text matches
+            ("PH off", "PH off", true),
+            ("PH off", "Mo-Fr", false),
+            ("Mo-Fr 07:00-09:00", "wet", false),
+        ] {
+            let actual = time_windows_overlap(a, b);
+            if actual != expected {
+                ok = false;
+                println!("For {a} and {b}");
+                println!("Got: {actual}");
+                println!("Expected: {expected}");
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+
+use geom::Distance;
+
+use crate::{
+    Direction, IntersectionControl, IntersectionID, IntersectionKind, Road, RoadFeatureKind,
+    RoadID, StreetNetwork, TrafficInterruption,
+};
+
+/// Decides the control of every intersection and how each approach to it is interrupted.
+///
+/// - Signals and construction are left alone. Approaches with a signal get `Signal`.
+/// - `highway=stop` and `highway=give_way` nodes on a road apply to the end of the road they
+///   point towards (using `direction`), or the closer end if there's no direction.
+/// - Signs mapped on the intersection node itself apply to the roads ranked lower than the most
+///   important road there, or to every road if they're all equally important.
+/// - `stop=all` makes every approach stop.
+/// - Without any signs, minor roads meeting a road at least as important as `tertiary` are
+///   assumed to yield. Everything else is uncontrolled.
+/// - Intersections already marked as `Signed` at this point (collapsed roundabouts) yield on every
+///   approach.
+pub fn infer(streets: &mut StreetNetwork) {
+    let mut results: Vec<(
+        IntersectionID,
+        IntersectionControl,
+        BTreeMap<RoadID, TrafficInterruption>,
+    )> = Vec::new();
+
+    for i in streets.intersections.values() {
+        if i.kind == IntersectionKind::MapEdge || i.control == IntersectionControl::Construction {
+            continue;
+        }
+        let approaches: Vec<&Road> = streets
+            .roads_per_intersection(i.id)
+            .into_iter()
+            .filter(|road| road.is_driveable() && road.can_drive_out_of_end(i.id))
+            .collect();
+
+        let mut approach_controls = BTreeMap::new();
+        if i.control == IntersectionControl::Signalled {
+            for road in approaches {
+                // When signals were mapped on specific approaches, the others (like a slip lane)
+                // aren't controlled by them
+                let interruption = if i.signalized_approaches.is_empty()
+                    || i.signalized_approaches.contains(&road.id)
+                {
+                    TrafficInterruption::Signal
+                } else {
+                    TrafficInterruption::Uninterrupted
+                };
+                approach_controls.insert(road.id, interruption);
+            }
+            results.push((i.id, i.control, approach_controls));
+            continue;
+        }
+        if i.kind == IntersectionKind::Terminus {
+            for road in approaches {
+                approach_controls.insert(road.id, TrafficInterruption::DeadEnd);
+            }
+            results.push((i.id, IntersectionControl::Uncontrolled, approach_controls));
+            continue;
+        }
+
+        let max_rank = approaches.iter().map(|r| r.rank()).max().unwrap_or(0);
+        let min_rank = approaches.iter().map(|r| r.rank()).min().unwrap_or(0);
+        let mut all_way_stop = false;
+        let mut any_signs = false;
+        for road in &approaches {
+            let mut interruption = TrafficInterruption::Uninterrupted;
+            for feature in &road.features {
+                if !feature.kind.is_traffic_sign() {
+                    continue;
+                }
+                let applies = if i.osm_ids.contains(&feature.osm_node) {
+                    road.rank() < max_rank || min_rank == max_rank
+                } else {
+                    sign_applies_to(road, feature.direction, feature.dist) == i.id
+                };
+                if !applies {
+                    continue;
+                }
+                any_signs = true;
+                match feature.kind {
+                    RoadFeatureKind::StopSign { all_way } => {
+                        all_way_stop |= all_way;
+                        interruption = TrafficInterruption::Stop;
+                    }
+                    RoadFeatureKind::GiveWay => {
+                        if interruption != TrafficInterruption::Stop {
+                            interruption = TrafficInterruption::Yield;
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            approach_controls.insert(road.id, interruption);
+        }
+
+        let control = if all_way_stop {
+            for interruption in approach_controls.values_mut() {
+                *interruption = TrafficInterruption::Stop;
+            }
+            IntersectionControl::Signed
+        } else if any_signs {
+            IntersectionControl::Signed
+        } else if i.control == IntersectionControl::Signed {
+            for interruption in approach_controls.values_mut() {
+                *interruption = TrafficInterruption::Yield;
+            }
+            IntersectionControl::Signed
+        } else if i.kind == IntersectionKind::Intersection && max_rank >= 6 && min_rank < max_rank {
+            for road in &approaches {
+                if road.rank() < max_rank {
+                    approach_controls.insert(road.id, TrafficInterruption::Yield);
+                }
+            }
+            IntersectionControl::Signed
+        } else {
+            IntersectionControl::Uncontrolled
+        };
+        results.push((i.id, control, approach_controls));
+    }
+
+    for (i, control, approach_controls) in results {
+        let intersection = streets.intersections.get_mut(&i).unwrap();
+        intersection.control = control;
+        intersection.approach_controls = approach_controls;
+    }
+}
+
+/// Which end of a road does a sign at some distance along it face?
+fn sign_applies_to(road: &Road, direction: Option<Direction>, dist: Distance) -> IntersectionID {
+    match direction {
+        Some(Direction::Fwd) => road.dst_i,
+        Some(Direction::Back) => road.src_i,
+        Some(Direction::Both) | None => {
+            if dist > road.reference_line.length() / 2.0 {
+                road.dst_i
+            } else {
+                road.src_i
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geom::Pt2D;
+
+    use super::*;
+    use crate::osm;
+
+    #[test]
+    fn test_infer_intersection_control() {
+        use TrafficInterruption::*;
+
+        // A primary road going east meets a residential road going south
+        let mut streets = StreetNetwork::blank();
+        let center = streets.add_test_intersection(0.0, 0.0);
+        let west_i = streets.add_test_intersection(-100.0, 0.0);
+        let east_i = streets.add_test_intersection(100.0, 0.0);
+        let north_i = streets.add_test_intersection(0.0, -100.0);
+        let south_i = streets.add_test_intersection(0.0, 100.0);
+        let west = streets.add_test_road(west_i, center, "dd", "v^");
+        let east = streets.add_test_road(center, east_i, "dd", "v^");
+        let north = streets.add_test_road(north_i, center, "dd", "v^");
+        let south = streets.add_test_road(center, south_i, "dd", "v^");
+        for r in [west, east] {
+            streets.roads.get_mut(&r).unwrap().highway_type = "primary".to_string();
+        }
+        // For signs mapped on the intersection
+        let center_node = osm::NodeID(100);
+        streets.intersections.get_mut(&center).unwrap().osm_ids = vec![center_node];
+
+        let stop = RoadFeatureKind::StopSign { all_way: false };
+        let mut ok = true;
+        for (description, all_residential, signs, expected_control, expected) in vec![
+            (
+                "no signs, minor road yields",
+                false,
+                vec![],
+                IntersectionControl::Signed,
+                [Uninterrupted, Uninterrupted, Yield, Yield],
+            ),
+            (
+                "no signs, equal roads",
+                true,
+                vec![],
+                IntersectionControl::Uncontrolled,
+                [Uninterrupted, Uninterrupted, Uninterrupted, Uninterrupted],
+            ),
+            (
+                "stop sign facing forwards, far from the intersection",
+                false,
+                vec![(north, stop.clone(), (0.0, -90.0), Some(Direction::Fwd))],
+                IntersectionControl::Signed,
+                [Uninterrupted, Uninterrupted, Stop, Uninterrupted],
+            ),
+            (
+                "stop sign facing backwards, near the intersection",
+                false,
+                vec![(north, stop.clone(), (0.0, -10.0), Some(Direction::Back))],
+                IntersectionControl::Signed,
+                [Uninterrupted, Uninterrupted, Yield, Yield],
+            ),
+            (
+                "give way without direction, near the start of the road",
+                false,
+                vec![(south, RoadFeatureKind::GiveWay, (0.0, 5.0), None)],
+                IntersectionControl::Signed,
+                [Uninterrupted, Uninterrupted, Uninterrupted, Yield],
+            ),
+            (
+                "stop sign on the intersection, applying to the minor road",
+                false,
+                vec![
+                    (west, stop.clone(), (0.0, 0.0), None),
+                    (east, stop.clone(), (0.0, 0.0), None),
+                    (north, stop.clone(), (0.0, 0.0), None),
+                    (south, stop.clone(), (0.0, 0.0), None),
+                ],
+                IntersectionControl::Signed,
+                [Uninterrupted, Uninterrupted, Stop, Stop],
+            ),
+            (
+                "stop sign on the intersection, with equal roads",
+                true,
+                vec![
+                    (west, stop.clone(), (0.0, 0.0), None),
+                    (east, stop.clone(), (0.0, 0.0), None),
+                    (north, stop.clone(), (0.0, 0.0), None),
+                    (south, stop.clone(), (0.0, 0.0), None),
+                ],
+                IntersectionControl::Signed,
+                [Stop, Stop, Stop, Stop],
+            ),
+            (
+                "all-way stop",
+                false,
+                vec![(
+                    north,
+                    RoadFeatureKind::StopSign { all_way: true },
+                    (0.0, -10.0),
+                    None,
+                )],
+                IntersectionControl::Signed,
+                [Stop, Stop, Stop, Stop],
+            ),
+        ] {
+            let mut copy = streets.clone();
+            if all_residential {
+                for road in copy.roads.values_mut() {
+                    road.highway_type = "residential".to_string();
+                }
+            }
+            for (r, kind, (x, y), direction) in signs {
+                let osm_node = if (x, y) == (0.0, 0.0) {
+                    center_node
+                } else {
+                    osm::NodeID(1)
+                };
+                copy.roads.get_mut(&r).unwrap().add_feature(
+                    kind,
+                    osm_node,
+                    Pt2D::new(x, y),
+                    direction,
+                );
+            }
+
+            infer(&mut copy);
+            let intersection = &copy.intersections[&center];
+            let expected: BTreeMap<RoadID, TrafficInterruption> = [west, east, north, south]
+                .into_iter()
+                .zip(expected)
+                .collect();
+            if intersection.control != expected_control
+                || intersection.approach_controls != expected
+            {
+                ok = false;
+                println!("For {description}");
+                println!(
+                    "Got:      {:?}, {:?}",
+                    intersection.control, intersection.approach_controls
+                );
+                println!("Expected: {expected_control:?}, {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+}
@@ -1,7 +1,7 @@
 use abstutil::Timer;
 use geom::{Circle, Distance};
 
-use crate::{IntersectionControl, StreetNetwork};
+use crate::{IntersectionControl, IntersectionID, StreetNetwork};
 
 pub fn generate(streets: &mut StreetNetwork, timer: &mut Timer) {
     // TODO intersection_polygon assumes untrimmed lines as input, so reset here. Once we always
@@ -11,15 +11,25 @@ pub fn generate(streets: &mut StreetNetwork, timer: &mut Timer) {
         road.update_center_line(streets.config.driving_side);
     }
 
+    let intersections: Vec<IntersectionID> = streets.intersections.keys().cloned().collect();
+    generate_for(streets, &intersections, timer);
+}
+
+/// Calculates the polygon of some intersections, and trims the connected roads at those ends. The
+/// roads must not already be trimmed at these ends, but may be trimmed at their other end.
+/// Intersections without any roads are removed.
+pub(crate) fn generate_for(
+    streets: &mut StreetNetwork,
+    intersections: &[IntersectionID],
+    timer: &mut Timer,
+) {
     let mut remove_dangling_nodes = Vec::new();
-    timer.start_iter(
-        "find each intersection polygon",
-        streets.intersections.len(),
-    );
+    timer.start_iter("find each intersection polygon", intersections.len());
     // It'd be nice to mutate in the loop, but the borrow checker won't let us
     let mut set_polygons = Vec::new();
     let mut make_stop_signs = Vec::new();
-    for i in streets.intersections.values() {
+    for id in intersections {
+        let i = &streets.intersections[id];
         timer.next();
         let input_roads = i
             .roads
@@ -1,3 +1,5 @@
+use anyhow::Result;
+
 use abstutil::Timer;
 
 use crate::StreetNetwork;
@@ -6,11 +8,13 @@ mod collapse_intersections;
 mod collapse_short_road;
 mod dual_carriageways;
 mod find_short_roads;
-mod intersection_geometry;
+mod intersection_control;
+pub(crate) mod intersection_geometry;
 mod remove_disconnected;
 mod sausage_links;
 mod separate_cycletracks;
 mod shrink_roads;
+mod turn_pockets;
 
 /// An in-place transformation of a `StreetNetwork`.
 pub enum Transformation {
@@ -22,6 +26,8 @@ pub enum Transformation {
     CollapseShortRoads,
     CollapseDegenerateIntersections,
     CollapseSausageLinks,
+    MergeTurnPockets,
+    InferIntersectionControl,
     ShrinkOverlappingRoads,
     MergeDualCarriageways,
     GenerateIntersectionGeometry,
@@ -38,6 +44,7 @@ impl Transformation {
             },
             Transformation::CollapseShortRoads,
             Transformation::CollapseDegenerateIntersections,
+            Transformation::InferIntersectionControl,
             Transformation::ShrinkOverlappingRoads,
             Transformation::GenerateIntersectionGeometry,
         ]
@@ -77,6 +84,8 @@ impl Transformation {
             Transformation::CollapseShortRoads => "collapse short roads",
             Transformation::CollapseDegenerateIntersections => "collapse degenerate intersections",
             Transformation::CollapseSausageLinks => "collapse sausage links",
+            Transformation::MergeTurnPockets => "merge turn pockets",
+            Transformation::InferIntersectionControl => "infer intersection control",
             Transformation::ShrinkOverlappingRoads => "shrink overlapping roads",
             Transformation::MergeDualCarriageways => "merge dual carriageways",
             Transformation::GenerateIntersectionGeometry => "generate intersection geometry",
@@ -109,6 +118,12 @@ impl Transformation {
             Transformation::CollapseSausageLinks => {
                 sausage_links::collapse_sausage_links(streets);
             }
+            Transformation::MergeTurnPockets => {
+                turn_pockets::merge_turn_pockets(streets);
+            }
+            Transformation::InferIntersectionControl => {
+                intersection_control::infer(streets);
+            }
             Transformation::ShrinkOverlappingRoads => {
                 shrink_roads::shrink(streets, timer);
             }
@@ -124,16 +139,45 @@ impl Transformation {
 }
 
 impl StreetNetwork {
+    /// Apply a sequence of transformations. With `MapConfig::check_invariants`, this panics at the
+    /// first step that breaks an invariant; use `apply_transformations_checked` to handle that
+    /// instead.
     pub fn apply_transformations(
         &mut self,
         transformations: Vec<Transformation>,
         timer: &mut Timer,
     ) {
+        if let Err(err) = self.apply_transformations_checked(transformations, timer) {
+            panic!("{err}");
+        }
+    }
+
+    /// Apply a sequence of transformations. With `MapConfig::check_invariants`, this also runs
+    /// `check_invariants` before the first and after every step, and stops at the first step that
+    /// breaks something, returning an error naming it. Otherwise, this never fails.
+    pub fn apply_transformations_checked(
+        &mut self,
+        transformations: Vec<Transformation>,
+        timer: &mut Timer,
+    ) -> Result<()> {
+        let check = self.config.check_invariants;
+        if check {
+            check_step(self, "the input")?;
+        }
+
         timer.start("simplify StreetNetwork");
         for transformation in transformations {
             transformation.apply(self, timer);
+            if check {
+                if let Err(err) = check_step(self, transformation.name()) {
+                    timer.stop("simplify StreetNetwork");
+                    return Err(err);
+                }
+            }
         }
+        self.update_crossings();
         timer.stop("simplify StreetNetwork");
+        Ok(())
     }
 
     /// Apply a sequence of transformations, but also save a copy of the `StreetNetwork` before
@@ -152,6 +196,22 @@ impl StreetNetwork {
             // first
             self.start_debug_step(transformation.name());
         }
+        self.update_crossings();
         timer.stop("simplify StreetNetwork");
     }
 }
+
+fn check_step(streets: &StreetNetwork, step: &str) -> Result<()> {
+    let errors = streets.check_invariants();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    for err in &errors {
+        warn!("After {step}: {err}");
+    }
+    bail!(
+        "{} invariants broken after {step}, first: {}",
+        errors.len(),
+        errors[0]
+    );
+}
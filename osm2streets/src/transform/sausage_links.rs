@@ -3,7 +3,9 @@ use std::collections::BTreeSet;
 use geom::PolyLine;
 
 use crate::lanes::{Placement, RoadPosition};
-use crate::{BufferType, Direction, DrivingSide, LaneSpec, LaneType, RoadID, StreetNetwork};
+use crate::{
+    AccessRules, BufferType, Direction, DrivingSide, LaneSpec, LaneType, RoadID, StreetNetwork,
+};
 
 /// Find dual carriageways that split very briefly and then re-join, with no intermediate roads.
 /// Collapse them into one road with a barrier in the middle.
@@ -80,6 +82,10 @@ fn fix(streets: &mut StreetNetwork, id1: RoadID, id2: RoadID) {
     let road1 = streets.roads.get_mut(&id1).unwrap();
 
     road1.osm_ids.extend(road2.osm_ids);
+    // The two roads point in opposite directions
+    road1.take_features(road2.features, true);
+    // road1 becomes two-way, so its backwards direction comes from road2
+    road1.speed_limits.back = road2.speed_limits.fwd;
 
     // Geometry
     //
@@ -92,6 +98,7 @@ fn fix(streets: &mut StreetNetwork, id1: RoadID, id2: RoadID) {
         road1.reference_line.last_pt(),
     ]);
     road1.reference_line_placement = Placement::Consistent(RoadPosition::Center);
+    road1.update_features();
 
     // Lanes
     //
@@ -120,6 +127,8 @@ fn fix(streets: &mut StreetNetwork, id1: RoadID, id2: RoadID) {
                 dir: Direction::Fwd,
                 width: LaneSpec::typical_lane_width(LaneType::Buffer(BufferType::Curb)),
                 turn_restrictions: Vec::new(),
+                access: AccessRules::default(),
+                width_profile: None,
             },
         );
 
@@ -141,6 +150,8 @@ fn fix(streets: &mut StreetNetwork, id1: RoadID, id2: RoadID) {
             dir: Direction::Fwd,
             width: LaneSpec::typical_lane_width(LaneType::Buffer(BufferType::Curb)),
             turn_restrictions: Vec::new(),
+            access: AccessRules::default(),
+            width_profile: None,
         });
 
         for mut lane in road2.lane_specs_ltr {
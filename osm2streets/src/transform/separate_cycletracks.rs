@@ -1,6 +1,8 @@
 use geom::{Distance, PolyLine};
 
-use crate::{BufferType, Direction, IntersectionID, LaneSpec, LaneType, RoadID, StreetNetwork};
+use crate::{
+    AccessRules, BufferType, Direction, IntersectionID, LaneSpec, LaneType, RoadID, StreetNetwork,
+};
 
 /// Find cycleway segments that exist as separate objects, parallel to a main road. Merge (or
 /// "snap") them into the main road, inserting a buffer lane to represent the physical division.
@@ -124,6 +126,8 @@ fn snap(streets: &mut StreetNetwork, input: Cycleway) {
         dir: Direction::Fwd,
         width: LaneSpec::typical_lane_width(LaneType::Buffer(BufferType::Planters)),
         turn_restrictions: Vec::new(),
+        access: AccessRules::default(),
+        width_profile: None,
     };
 
     // For every main road segment corresponding to the cycleway, we need to insert these
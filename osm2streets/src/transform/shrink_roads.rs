@@ -66,7 +66,7 @@ pub fn shrink(streets: &mut StreetNetwork, timer: &mut Timer) {
             // untrimmed and trimmed center points instead of calculating them dynamically, that'll
             // have to happen here.
             for spec in &mut streets.roads.get_mut(&id).unwrap().lane_specs_ltr {
-                spec.width *= 0.5;
+                spec.scale_width(0.5);
             }
         }
     }
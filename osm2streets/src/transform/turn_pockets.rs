@@ -0,0 +1,432 @@
+use geom::Distance;
+
+use crate::lanes::WidthProfile;
+use crate::{Direction, IntersectionID, IntersectionKind, LaneSpec, LaneType, Road, StreetNetwork};
+
+/// How far the width of a turn pocket or dropped lane changes over, at most.
+const TAPER_LENGTH: Distance = Distance::const_meters(15.0);
+
+/// OSM ways are split where the number of lanes changes, so a turn pocket starting part of the way
+/// to a junction becomes a separate road with an extra lane. This finds `Connection`s between two
+/// roads where one has extra travel lanes tagged with `turn:lanes`, and merges the roads. The
+/// extra lanes get a `WidthProfile`, so they only exist along the part of the road they were
+/// tagged on, tapering in or out near the old connection.
+pub fn merge_turn_pockets(streets: &mut StreetNetwork) {
+    let candidates: Vec<IntersectionID> = streets
+        .intersections
+        .values()
+        .filter(|i| i.kind == IntersectionKind::Connection && i.roads.len() == 2)
+        .map(|i| i.id)
+        .collect();
+
+    for i in candidates {
+        // Earlier merges may have changed the roads here
+        let roads = match streets.intersections.get(&i) {
+            Some(intersection) if intersection.roads.len() == 2 => intersection.roads.clone(),
+            _ => continue,
+        };
+        let pocket = match find_pocket(&streets.roads[&roads[0]], &streets.roads[&roads[1]], i) {
+            Some(pocket) => pocket,
+            None => continue,
+        };
+
+        // collapse_intersection keeps the first road and its direction
+        streets.collapse_intersection(i);
+        if streets.intersections.contains_key(&i) {
+            continue;
+        }
+        info!("Merged a turn pocket at {i} into {}", roads[0]);
+        let driving_side = streets.config.driving_side;
+        let road = streets.roads.get_mut(&roads[0]).unwrap();
+        road.lane_specs_ltr = pocket;
+        road.update_center_line(driving_side);
+        let endpoints = road.endpoints();
+        for i in endpoints {
+            streets.update_movements(i);
+        }
+    }
+}
+
+/// If two roads meeting at `i` only differ by some extra travel lanes tagged with `turn:lanes`,
+/// returns the lanes of the merged road, oriented like `keep`.
+fn find_pocket(keep: &Road, other: &Road, i: IntersectionID) -> Option<Vec<LaneSpec>> {
+    if keep.id == other.id
+        || keep.highway_type != other.highway_type
+        || keep.name != other.name
+        || keep.layer != other.layer
+        || keep.reference_line_placement != other.reference_line_placement
+        || keep.has_varying_width()
+        || other.has_varying_width()
+    {
+        return None;
+    }
+
+    let reversed = keep.dst_i == other.dst_i || keep.src_i == other.src_i;
+    let other_lanes = if reversed {
+        reverse_lanes(&other.lane_specs_ltr)
+    } else {
+        other.lane_specs_ltr.clone()
+    };
+    // Order the roads as travelled in the direction of keep
+    let (first, second, first_len, second_len) = if keep.dst_i == i {
+        let keep_len = keep.reference_line.length();
+        (
+            &keep.lane_specs_ltr,
+            &other_lanes,
+            keep_len,
+            other.reference_line.length(),
+        )
+    } else {
+        let keep_len = keep.reference_line.length();
+        (
+            &other_lanes,
+            &keep.lane_specs_ltr,
+            other.reference_line.length(),
+            keep_len,
+        )
+    };
+    let long_is_second = second.len() > first.len();
+    let (long, short) = if long_is_second {
+        (second, first)
+    } else {
+        (first, second)
+    };
+    if long.len() == short.len() {
+        return None;
+    }
+    let extra = find_extra_lanes(long, short)?;
+
+    // Where the connection was, as a fraction of the merged road
+    let split = first_len / (first_len + second_len);
+    let long_len = if long_is_second {
+        second_len
+    } else {
+        first_len
+    };
+    let mut taper = TAPER_LENGTH;
+    if long_len / 2.0 < taper {
+        taper = long_len / 2.0;
+    }
+    let taper = taper / (first_len + second_len);
+
+    let mut lanes = Vec::new();
+    let mut matched = 0;
+    for (idx, lane) in long.iter().enumerate() {
+        let mut lane = lane.clone();
+        // turn:lanes describe the end of the road the lane travels towards
+        let towards_second = lane.dir == Direction::Fwd;
+        if extra.contains(&idx) {
+            let profile = if long_is_second {
+                WidthProfile(vec![(split, Distance::ZERO), (split + taper, lane.width)])
+            } else {
+                WidthProfile(vec![(split - taper, lane.width), (split, Distance::ZERO)])
+            };
+            lane.set_width_profile(profile);
+            if towards_second != long_is_second {
+                lane.turn_restrictions.clear();
+            }
+        } else {
+            let short_lane = &short[matched];
+            matched += 1;
+            if towards_second != long_is_second {
+                lane.turn_restrictions = short_lane.turn_restrictions.clone();
+            }
+        }
+        lanes.push(lane);
+    }
+    Some(lanes)
+}
+
+/// Finds which lanes of `long` aren't in `short`. Every other lane must match in order, and the
+/// extra ones must be travel lanes tagged with turns. When the extra lane could be on either end
+/// of a run of identical lanes, prefer the side where the lanes are tagged as turning.
+fn find_extra_lanes(long: &[LaneSpec], short: &[LaneSpec]) -> Option<Vec<usize>> {
+    let from_left = align(long.iter().enumerate(), short.iter());
+    let from_right = align(long.iter().enumerate().rev(), short.iter().rev());
+
+    let valid = |extra: &Vec<usize>| {
+        extra.iter().all(|idx| {
+            matches!(long[*idx].lt, LaneType::Driving | LaneType::Bus)
+                && !long[*idx].turn_restrictions.is_empty()
+        })
+    };
+    let only_turns = |extra: &Vec<usize>| {
+        extra.iter().all(|idx| {
+            !long[*idx]
+                .turn_restrictions
+                .iter()
+                .any(|turn| turn == "through" || turn == "none" || turn.is_empty())
+        })
+    };
+
+    let mut candidates: Vec<Vec<usize>> = from_left.into_iter().chain(from_right).collect();
+    candidates.retain(valid);
+    if let Some(idx) = candidates.iter().position(only_turns) {
+        return Some(candidates.remove(idx));
+    }
+    candidates.into_iter().next()
+}
+
+/// Greedily matches lanes of `short` to `long` in order, returning the unmatched lanes of `long`.
+fn align<'a>(
+    long: impl Iterator<Item = (usize, &'a LaneSpec)>,
+    short: impl Iterator<Item = &'a LaneSpec>,
+) -> Option<Vec<usize>> {
+    let mut short = short.peekable();
+    let mut extra = Vec::new();
+    for (idx, lane) in long {
+        match short.peek() {
+            Some(next) if next.lt == lane.lt && next.dir == lane.dir => {
+                short.next();
+            }
+            _ => {
+                extra.push(idx);
+            }
+        }
+    }
+    if short.next().is_some() {
+        return None;
+    }
+    extra.sort();
+    Some(extra)
+}
+
+/// The lanes of a road, as seen from the other direction
+fn reverse_lanes(lanes_ltr: &[LaneSpec]) -> Vec<LaneSpec> {
+    lanes_ltr
+        .iter()
+        .rev()
+        .map(|lane| {
+            let mut lane = lane.clone();
+            lane.dir = lane.dir.opposite();
+            lane
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lanes described like `LaneSpec::create_for_test`, with turn:lanes given by one character
+    /// per lane: 'l' for left, 't' for through, 'r' for right, anything else for none.
+    fn lanes(lt: &str, dir: &str, turns: &str) -> Vec<LaneSpec> {
+        let mut lanes = LaneSpec::create_for_test(lt, dir);
+        for (lane, turn) in lanes.iter_mut().zip(turns.chars()) {
+            lane.turn_restrictions = match turn {
+                'l' => vec!["left".to_string()],
+                't' => vec!["through".to_string()],
+                'r' => vec!["right".to_string()],
+                _ => Vec::new(),
+            };
+        }
+        lanes
+    }
+
+    #[test]
+    fn test_align() {
+        let long = lanes("sddds", "vv^^^", ".....");
+        let mut ok = true;
+        for (description, short_lt, short_dir, from_right, expected) in vec![
+            (
+                "one extra lane, from the left",
+                "sdds",
+                "vv^^",
+                false,
+                Some(vec![3]),
+            ),
+            (
+                "one extra lane, from the right",
+                "sdds",
+                "vv^^",
+                true,
+                Some(vec![2]),
+            ),
+            ("two extra lanes", "sds", "vv^", false, Some(vec![2, 3])),
+            ("a lane long doesn't have", "sdbds", "vv^^^", false, None),
+        ] {
+            let short = LaneSpec::create_for_test(short_lt, short_dir);
+            let actual = if from_right {
+                align(long.iter().enumerate().rev(), short.iter().rev())
+            } else {
+                align(long.iter().enumerate(), short.iter())
+            };
+            if actual != expected {
+                ok = false;
+                println!("For {description}");
+                println!("Got: {actual:?}");
+                println!("Expected: {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+
+    #[test]
+    fn test_find_extra_lanes() {
+        let mut ok = true;
+        for (description, long_lt, long_dir, long_turns, short_lt, short_dir, expected) in vec![
+            (
+                "a left pocket",
+                "sddds",
+                "vv^^^",
+                "..lt.",
+                "sdds",
+                "vv^^",
+                Some(vec![2]),
+            ),
+            (
+                "a right pocket",
+                "sddds",
+                "vv^^^",
+                "..tr.",
+                "sdds",
+                "vv^^",
+                Some(vec![3]),
+            ),
+            (
+                "an extra lane without turn:lanes",
+                "sddds",
+                "vv^^^",
+                ".....",
+                "sdds",
+                "vv^^",
+                None,
+            ),
+            (
+                "an extra bike lane",
+                "sdbds",
+                "vv^^^",
+                "...l.",
+                "sdds",
+                "vv^^",
+                None,
+            ),
+            (
+                "lanes that don't match",
+                "sddds",
+                "vv^^^",
+                "..lt.",
+                "sdbs",
+                "vv^^",
+                None,
+            ),
+        ] {
+            let long = lanes(long_lt, long_dir, long_turns);
+            let short = LaneSpec::create_for_test(short_lt, short_dir);
+            let actual = find_extra_lanes(&long, &short);
+            if actual != expected {
+                ok = false;
+                println!("For {description}");
+                println!("Got: {actual:?}");
+                println!("Expected: {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+
+    #[test]
+    fn test_find_pocket() {
+        let mut ok = true;
+        // A road from the west reaches the connection, where a road continues east. The result
+        // is oriented like the west road, and the extra lane widens in after the connection.
+        for (description, reversed, other_lt, other_dir, other_turns, expected) in vec![
+            (
+                "a left pocket",
+                false,
+                "sddds",
+                "vv^^^",
+                "..lt.",
+                Some(("sddds", "vv^^^", 2)),
+            ),
+            (
+                "a right pocket",
+                false,
+                "sddds",
+                "vv^^^",
+                "..tr.",
+                Some(("sddds", "vv^^^", 3)),
+            ),
+            (
+                "a left pocket on a road drawn the other way",
+                true,
+                "sddds",
+                "vvv^^",
+                ".tl..",
+                Some(("sddds", "vv^^^", 2)),
+            ),
+            (
+                "an extra lane that isn't a pocket",
+                false,
+                "sddbs",
+                "vv^^^",
+                "..l..",
+                None,
+            ),
+        ] {
+            let mut streets = StreetNetwork::blank();
+            let west_i = streets.add_test_intersection(0.0, 0.0);
+            let i = streets.add_test_intersection(100.0, 0.0);
+            let east_i = streets.add_test_intersection(200.0, 0.0);
+            let keep = streets.add_test_road(west_i, i, "sdds", "vv^^");
+            let other = if reversed {
+                streets.add_test_road(east_i, i, other_lt, other_dir)
+            } else {
+                streets.add_test_road(i, east_i, other_lt, other_dir)
+            };
+            for (lane, with_turns) in streets
+                .roads
+                .get_mut(&other)
+                .unwrap()
+                .lane_specs_ltr
+                .iter_mut()
+                .zip(lanes(other_lt, other_dir, other_turns))
+            {
+                lane.turn_restrictions = with_turns.turn_restrictions;
+            }
+
+            let actual = find_pocket(&streets.roads[&keep], &streets.roads[&other], i);
+            let (lanes, extra) = match (actual, expected) {
+                (None, None) => continue,
+                (Some(lanes), Some((expected_lt, expected_dir, extra))) => {
+                    LaneSpec::check_lanes_ltr(
+                        &lanes,
+                        description.to_string(),
+                        other_lt,
+                        other_dir,
+                        expected_lt,
+                        expected_dir,
+                        &mut ok,
+                    );
+                    (lanes, extra)
+                }
+                (actual, expected) => {
+                    ok = false;
+                    println!("For {description}");
+                    println!("Got: {actual:?}");
+                    println!("Expected: {expected:?}");
+                    println!();
+                    continue;
+                }
+            };
+            // Only the extra lane changes width, starting after the connection
+            for (idx, lane) in lanes.iter().enumerate() {
+                let actual = (lane.width_at(0.25), lane.width_at(1.0));
+                let expected = if idx == extra {
+                    (Distance::ZERO, lane.width)
+                } else {
+                    (lane.width, lane.width)
+                };
+                if actual != expected {
+                    ok = false;
+                    println!("For {description}, lane {idx}");
+                    println!("Got: {actual:?}");
+                    println!("Expected: {expected:?}");
+                    println!();
+                }
+            }
+        }
+        assert!(ok);
+    }
+}
@@ -5,7 +5,8 @@ use serde::{Deserialize, Serialize};
 use abstutil::{deserialize_btreemap, serialize_btreemap, Tags};
 use geom::Distance;
 
-use crate::OriginalRoad;
+use crate::osm::WayID;
+use crate::{OriginalRoad, SpeedUnit};
 
 /// None corresponds to the native name
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -58,6 +59,9 @@ pub struct MapConfig {
     /// Note this is calculated by osm2streets! The value passed in is ignored; don't do any work
     /// to set it.
     pub driving_side: DrivingSide,
+    /// The unit of default speed limits, when nothing is tagged. Like `driving_side`, this is
+    /// calculated by osm2streets from the map's location; the value passed in is ignored.
+    pub default_speed_unit: SpeedUnit,
     pub bikes_can_use_bus_lanes: bool,
     /// If true, roads without explicitly tagged sidewalks may be assigned sidewalks or shoulders.
     /// If false, no inference will occur and separate sidewalks and crossings will be included.
@@ -72,8 +76,24 @@ pub struct MapConfig {
     /// If true, use experimental osm2lanes for figuring out lanes per road. If false, use the
     /// classic algorithm.
     pub osm2lanes: bool,
+    /// Use osm2lanes for roads from these OSM ways, even if `osm2lanes` is false.
+    pub osm2lanes_ways: BTreeSet<WayID>,
+    /// If true, parse lanes with both the classic algorithm and osm2lanes, recording roads where
+    /// they disagree in `Road::lane_provenance`.
+    pub compare_lane_parsers: bool,
     /// OSM railway=rail will be included as light rail if so. Cosmetic only.
     pub include_railroads: bool,
+    /// If true, roads closed to cars by access tags or bollards, like modal filters, are left out
+    /// when calculating the movements through intersections.
+    pub movements_respect_access: bool,
+    /// If true, roads and intersections created from OSM get IDs hashed from their OSM IDs
+    /// instead of sequential ones, so the IDs stay the same when re-importing a slightly changed
+    /// area. IDs are 32-bit hashes, so in large areas, a few colliding ones may still change.
+    pub stable_ids: bool,
+    /// If true, `StreetNetwork::apply_transformations` checks the network's invariants before and
+    /// after every step, failing at the first step that breaks something. This is slow; it's meant
+    /// for tests and debugging.
+    pub check_invariants: bool,
 
     /// Enable experimental dog-leg intersection merging
     pub find_dog_legs_experiment: bool,
@@ -86,12 +106,18 @@ impl MapConfig {
         Self {
             // Just a dummy value that'll be set later
             driving_side: DrivingSide::Right,
+            default_speed_unit: SpeedUnit::KmPerHour,
             bikes_can_use_bus_lanes: true,
             inferred_sidewalks: true,
             street_parking_spot_length: Distance::meters(8.0),
             turn_on_red: true,
             osm2lanes: false,
+            osm2lanes_ways: BTreeSet::new(),
+            compare_lane_parsers: false,
             include_railroads: true,
+            movements_respect_access: false,
+            stable_ids: false,
+            check_invariants: false,
             find_dog_legs_experiment: false,
             merge_osm_ways: BTreeSet::new(),
         }
@@ -104,6 +130,39 @@ pub enum DrivingSide {
     Left,
 }
 
+/// A way of getting around. Turn restrictions and access rules may only apply to some of these.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum TravelMode {
+    Walk,
+    Bike,
+    Car,
+    Bus,
+    Hgv,
+}
+
+impl TravelMode {
+    /// Interprets an OSM transport mode key, as used in `except=*`, `restriction:*` and access
+    /// tags. Some keys, like `motor_vehicle`, cover multiple modes. Unknown keys return nothing.
+    /// See <https://wiki.openstreetmap.org/wiki/Key:access#Transport_mode_restrictions>.
+    pub fn from_osm_key(key: &str) -> Vec<TravelMode> {
+        match key {
+            "foot" => vec![TravelMode::Walk],
+            "bicycle" => vec![TravelMode::Bike],
+            "motorcar" => vec![TravelMode::Car],
+            "bus" | "psv" => vec![TravelMode::Bus],
+            "hgv" => vec![TravelMode::Hgv],
+            "motor_vehicle" => vec![TravelMode::Car, TravelMode::Bus, TravelMode::Hgv],
+            "vehicle" => vec![
+                TravelMode::Bike,
+                TravelMode::Car,
+                TravelMode::Bus,
+                TravelMode::Hgv,
+            ],
+            _ => Vec::new(),
+        }
+    }
+}
+
 /// How a lane of travel is interrupted, as it meets another or ends.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum TrafficInterruption {
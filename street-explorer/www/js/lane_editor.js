@@ -76,8 +76,10 @@ export class LaneEditor {
         debug_each_step: false,
         dual_carriageway_experiment: false,
         cycletrack_snapping_experiment: false,
+        turn_pocket_experiment: false,
         inferred_sidewalks: false,
         osm2lanes: false,
+        check_invariants: false,
       });
       this.rerenderAll();
       const bounds = this.layers[0].getBounds();
@@ -229,7 +231,20 @@ export class LaneEditor {
     }
 
     console.log(`Recalculate with ${JSON.stringify(tags)}`);
-    this.network.overwriteOsmTagsForWay(this.currentWay, JSON.stringify(tags));
+    try {
+      const changed = JSON.parse(
+        this.network.overwriteOsmTagsForWay(
+          this.currentWay,
+          JSON.stringify(tags)
+        )
+      );
+      console.log(
+        `Changed ${changed.roads.length} roads and ${changed.intersections.length} intersections`
+      );
+    } catch (err) {
+      window.alert(`Couldn't apply these tags: ${err}`);
+      return;
+    }
     this.rerenderAll();
 
     this.editedWays.add(this.currentWay);
@@ -239,16 +254,6 @@ export class LaneEditor {
   }
 
   downloadOsc() {
-    var contents = `<osmChange version="0.6" generator="osm2streets">\n`;
-    contents += `<create/>\n`;
-    contents += `<modify>\n`;
-    for (const id of this.editedWays) {
-      contents += this.network.wayToXml(id);
-      contents += "\n";
-    }
-    contents += `</modify>\n`;
-    contents += `</osmChange>`;
-
-    downloadGeneratedFile("lane_edits.osc", contents);
+    downloadGeneratedFile("lane_edits.osc", this.network.toOsmChange());
   }
 }
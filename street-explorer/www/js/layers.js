@@ -151,6 +151,13 @@ export const makeIntersectionMarkingsLayer = (text) => {
   // These could change per locale
   const colors = {
     "sidewalk corner": "#CCCCCC",
+    "crossing stripe": "white",
+    crossing: "white",
+    bollard: "black",
+    gate: "brown",
+    "traffic calming": "orange",
+    "stop sign": "red",
+    "give way": "red",
   };
 
   return new L.geoJSON(JSON.parse(text), {
@@ -197,8 +197,10 @@ function importOSM(groupName, app, osmXML, addOSMLayer) {
       dual_carriageway_experiment: !!importSettings.dualCarriagewayExperiment,
       cycletrack_snapping_experiment:
         !!importSettings.cycletrackSnappingExperiment,
+      turn_pocket_experiment: !!importSettings.turnPocketExperiment,
       inferred_sidewalks: importSettings.sidewalks === "infer",
       osm2lanes: !!importSettings.osm2lanes,
+      check_invariants: !!importSettings.checkInvariants,
     });
     var group = new LayerGroup(groupName, app.map);
     if (addOSMLayer) {
@@ -223,6 +225,9 @@ function importOSM(groupName, app, osmXML, addOSMLayer) {
     group.addLazyLayer("Debug movements", () =>
       makeDebugLayer(network.debugMovementsGeojson())
     );
+    group.addLazyLayer("Debug lane movements", () =>
+      makeDebugLayer(network.debugLaneMovementsGeojson())
+    );
     // TODO Graphviz hits `ReferenceError: can't access lexical declaration 'graph' before initialization`
 
     const numDebugSteps = network.getDebugSteps().length;
@@ -3,7 +3,6 @@ use anyhow::Result;
 
 use osm2streets::{IntersectionControl, IntersectionID, IntersectionKind, StreetNetwork};
 
-// TODO This needs to update turn restrictions too
 pub fn clip_map(streets: &mut StreetNetwork, timer: &mut Timer) -> Result<()> {
     timer.start("clipping map to boundary");
 
@@ -48,6 +47,9 @@ pub fn clip_map(streets: &mut StreetNetwork, timer: &mut Timer) -> Result<()> {
         old_intersection.kind = IntersectionKind::MapEdge;
         old_intersection.control = IntersectionControl::Uncontrolled;
         old_intersection.movements = Vec::new();
+        old_intersection.lane_movements = Vec::new();
+        old_intersection.signalized_approaches.clear();
+        old_intersection.approach_controls.clear();
 
         if old_intersection.roads.len() <= 1 {
             // We don't need to make copies of the intersection; put it back
@@ -57,8 +59,10 @@ pub fn clip_map(streets: &mut StreetNetwork, timer: &mut Timer) -> Result<()> {
         for r in old_intersection.roads.clone() {
             let mut copy = old_intersection.clone();
             copy.roads = vec![r];
-            copy.id = streets.next_intersection_id();
-            // Leave osm_ids alone; all copies of this intersection share provenance
+            // Leave osm_ids alone; all copies of this intersection share provenance. With stable
+            // IDs, the copies are told apart by their road.
+            let road_osm_ids = streets.roads[&r].osm_ids.clone();
+            copy.id = streets.new_intersection_id_for_road(&copy.osm_ids, &road_osm_ids);
 
             let road = streets.roads.get_mut(&r).unwrap();
             if road.src_i == old_id {
@@ -116,10 +120,27 @@ pub fn clip_map(streets: &mut StreetNetwork, timer: &mut Timer) -> Result<()> {
         }
     }
 
+    // Features cut off with the rest of the road are gone; the rest need their distances fixed.
+    for road in streets.roads.values_mut() {
+        if road.features.is_empty() {
+            continue;
+        }
+        road.features.retain(|f| boundary_polygon.contains_pt(f.pt));
+        road.update_features();
+    }
+
     if streets.roads.is_empty() {
         bail!("There are no roads inside the clipping polygon");
     }
 
+    // Roads removed above and intersections copied at the boundary leave turn restrictions
+    // referring to roads that don't exist or aren't connected anymore. Rewrite them to the
+    // surviving roads when possible, and drop the rest.
+    let (repaired, dropped) = streets.fix_dangling_turn_restrictions();
+    if repaired > 0 || dropped > 0 {
+        info!("Clipping cut off turn restrictions: repaired {repaired}, dropped {dropped}");
+    }
+
     timer.stop("clipping map to boundary");
     Ok(())
 }
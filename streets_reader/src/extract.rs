@@ -4,7 +4,10 @@ use osm::{NodeID, OsmID, RelationID, WayID};
 
 use abstutil::Tags;
 use geom::{HashablePt2D, Pt2D};
-use osm2streets::{osm, Direction, RestrictionType};
+use osm2streets::{
+    feature_direction, osm, parse_restrictions, Direction, RestrictionConditions, RestrictionType,
+    RoadFeatureKind,
+};
 
 use crate::osm_reader::{Node, Relation, Way};
 use crate::MapConfig;
@@ -12,13 +15,23 @@ use crate::MapConfig;
 pub struct OsmExtract {
     /// Unsplit roads. These aren't Roads yet, because they may not obey those invariants.
     pub roads: Vec<(WayID, Vec<Pt2D>, Tags)>,
-    /// Traffic signals to the direction they apply
-    pub traffic_signals: HashMap<HashablePt2D, Direction>,
+    /// Traffic signals to the direction they apply, if it's tagged
+    pub traffic_signals: HashMap<HashablePt2D, Option<Direction>>,
     pub osm_node_ids: HashMap<HashablePt2D, NodeID>,
-    /// (ID, restriction type, from way ID, via node ID, to way ID)
-    pub simple_turn_restrictions: Vec<(RestrictionType, WayID, NodeID, WayID)>,
-    /// (relation ID, from way ID, via way ID, to way ID)
-    pub complicated_turn_restrictions: Vec<(RelationID, WayID, WayID, WayID)>,
+    /// Crossings, barriers, and other things along roads, with the direction they apply to
+    pub road_features: Vec<(NodeID, Pt2D, RoadFeatureKind, Option<Direction>)>,
+    /// (restriction type, conditions, from way ID, via node ID, to way ID)
+    pub simple_turn_restrictions:
+        Vec<(RestrictionType, RestrictionConditions, WayID, NodeID, WayID)>,
+    /// (relation ID, restriction type, conditions, from way ID, via way IDs in order, to way ID)
+    pub complicated_turn_restrictions: Vec<(
+        RelationID,
+        RestrictionType,
+        RestrictionConditions,
+        WayID,
+        Vec<WayID>,
+        WayID,
+    )>,
 }
 
 impl OsmExtract {
@@ -27,6 +40,7 @@ impl OsmExtract {
             roads: Vec::new(),
             traffic_signals: HashMap::new(),
             osm_node_ids: HashMap::new(),
+            road_features: Vec::new(),
             simple_turn_restrictions: Vec::new(),
             complicated_turn_restrictions: Vec::new(),
         }
@@ -36,30 +50,45 @@ impl OsmExtract {
         self.osm_node_ids.insert(node.pt.to_hashable(), id);
 
         if node.tags.is(osm::HIGHWAY, "traffic_signals") {
-            let dir = if node.tags.is("traffic_signals:direction", "backward") {
-                Direction::Back
-            } else {
-                Direction::Fwd
+            let dir = match node
+                .tags
+                .get("traffic_signals:direction")
+                .map(|x| x.as_str())
+            {
+                Some("forward") => Some(Direction::Fwd),
+                Some("backward") => Some(Direction::Back),
+                _ => feature_direction(&node.tags),
             };
             self.traffic_signals.insert(node.pt.to_hashable(), dir);
         }
+
+        let direction = feature_direction(&node.tags);
+        for kind in RoadFeatureKind::from_tags(&node.tags) {
+            self.road_features.push((id, node.pt, kind, direction));
+        }
     }
 
     // Returns true if the way was added as a road
     pub fn handle_way(&mut self, id: WayID, way: &Way, cfg: &MapConfig) -> bool {
-        let tags = &way.tags;
+        if !Self::wants_way(&way.tags, cfg) {
+            return false;
+        }
+        self.roads.push((id, way.pts.clone(), way.tags.clone()));
+        true
+    }
 
+    /// Decides if a way should become a road, just based on its tags. This can be used to filter
+    /// input before reading it fully.
+    pub fn wants_way(tags: &Tags, cfg: &MapConfig) -> bool {
         if tags.is("area", "yes") {
             return false;
         }
 
         // First deal with railways.
         if tags.is("railway", "light_rail") {
-            self.roads.push((id, way.pts.clone(), tags.clone()));
             return true;
         }
         if tags.is("railway", "rail") && cfg.include_railroads {
-            self.roads.push((id, way.pts.clone(), tags.clone()));
             return true;
         }
 
@@ -131,19 +160,18 @@ impl OsmExtract {
             return false;
         }
 
-        self.roads.push((id, way.pts.clone(), tags.clone()));
         true
     }
 
     // Returns true if the relation was used (turn restrictions only)
     pub fn handle_relation(&mut self, id: RelationID, rel: &Relation) -> bool {
-        if !rel.tags.is("type", "restriction") {
+        if !Self::wants_relation(&rel.tags) {
             return false;
         }
 
         let mut from_way_id: Option<WayID> = None;
         let mut via_node_id: Option<NodeID> = None;
-        let mut via_way_id: Option<WayID> = None;
+        let mut via_way_ids: Vec<WayID> = Vec::new();
         let mut to_way_id: Option<WayID> = None;
         for (role, member) in &rel.members {
             match member {
@@ -153,7 +181,7 @@ impl OsmExtract {
                     } else if role == "to" {
                         to_way_id = Some(*w);
                     } else if role == "via" {
-                        via_way_id = Some(*w);
+                        via_way_ids.push(*w);
                     }
                 }
                 OsmID::Node(n) => {
@@ -166,26 +194,29 @@ impl OsmExtract {
                 }
             }
         }
-        if let Some(restriction) = rel.tags.get("restriction") {
-            if let Some(rt) = RestrictionType::new(restriction) {
-                if let (Some(from), Some(via), Some(to)) = (from_way_id, via_node_id, to_way_id) {
-                    self.simple_turn_restrictions.push((rt, from, via, to));
-                } else if let (Some(from), Some(via), Some(to)) =
-                    (from_way_id, via_way_id, to_way_id)
-                {
-                    if rt == RestrictionType::BanTurns {
-                        self.complicated_turn_restrictions.push((id, from, via, to));
-                    } else {
-                        warn!(
-                            "Weird complicated turn restriction \"{}\" from {} to {} via {}: \
-                             {}",
-                            restriction, from, to, via, id
-                        );
-                    }
+        for (rt, conditions) in parse_restrictions(&rel.tags) {
+            if let (Some(from), Some(via), Some(to)) = (from_way_id, via_node_id, to_way_id) {
+                self.simple_turn_restrictions
+                    .push((rt, conditions, from, via, to));
+            } else if let (Some(from), Some(to)) = (from_way_id, to_way_id) {
+                if !via_way_ids.is_empty() {
+                    self.complicated_turn_restrictions.push((
+                        id,
+                        rt,
+                        conditions,
+                        from,
+                        via_way_ids.clone(),
+                        to,
+                    ));
                 }
             }
         }
 
         true
     }
+
+    /// Decides if a relation is used at all, just based on its tags.
+    pub fn wants_relation(tags: &Tags) -> bool {
+        tags.is("type", "restriction")
+    }
 }
@@ -3,17 +3,22 @@ extern crate anyhow;
 #[macro_use]
 extern crate log;
 
-use abstutil::Timer;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use abstutil::{Tags, Timer};
 use anyhow::Result;
 use geom::{GPSBounds, LonLat, Ring};
 
-use osm2streets::{DrivingSide, MapConfig, StreetNetwork};
+use osm2streets::{DrivingSide, MapConfig, SpeedUnit, StreetNetwork};
 
 pub use self::extract::OsmExtract;
 
 // TODO Clean up the public API of all of this
 pub mod clip;
 pub mod extract;
+pub mod osm_change;
 pub mod osm_reader;
 pub mod split_ways;
 
@@ -29,6 +34,65 @@ pub fn osm_to_street_network(
     cfg: MapConfig,
     timer: &mut Timer,
 ) -> Result<(StreetNetwork, osm_reader::Document)> {
+    let mut streets = blank_network(&clip_pts, cfg)?;
+    let doc = osm_reader::read(osm_xml_input, &streets.gps_bounds, timer)?;
+    build_network(&mut streets, &doc, clip_pts.is_some(), timer)?;
+    Ok((streets, doc))
+}
+
+/// Create a `StreetNetwork` from either an `.osm.xml` or `.osm.pbf` file, detected from the
+/// contents. If `clip_pts` is specified, use these as a boundary polygon.
+///
+/// Unlike `osm_to_street_network`, this is meant for large PBF inputs. Only the ways that will
+/// become roads, the nodes they reference, and turn restriction relations are kept in the
+/// returned `Document`, so it's incomplete. PBF files are streamed, and ways entirely outside of
+/// the boundary's bounding box are also skipped.
+///
+/// XML files get the same filtering, but the whole file is still read into memory as a string
+/// first, so peak memory use for a large `.osm` file is at least its size. Convert large XML
+/// extracts to PBF (with osmium, for example) first.
+///
+/// You probably want to do `StreetNetwork::apply_transformations` on the result to get a useful
+/// result.
+pub fn osm_file_to_street_network(
+    path: &Path,
+    clip_pts: Option<Vec<LonLat>>,
+    cfg: MapConfig,
+    timer: &mut Timer,
+) -> Result<(StreetNetwork, osm_reader::Document)> {
+    let mut streets = blank_network(&clip_pts, cfg)?;
+    let keep_way = |tags: &Tags| OsmExtract::wants_way(tags, &streets.config);
+
+    let mut header = Vec::new();
+    File::open(path)?.take(15).read_to_end(&mut header)?;
+    let doc = if osm_reader::is_pbf(&header) {
+        let bbox = if clip_pts.is_some() {
+            Some(streets.gps_bounds.clone())
+        } else {
+            None
+        };
+        osm_reader::read_pbf_filtered(
+            path,
+            &streets.gps_bounds,
+            bbox.as_ref(),
+            &keep_way,
+            &OsmExtract::wants_relation,
+            timer,
+        )?
+    } else {
+        osm_reader::read_filtered(
+            &std::fs::read_to_string(path)?,
+            &streets.gps_bounds,
+            &keep_way,
+            &OsmExtract::wants_relation,
+            timer,
+        )?
+    };
+    build_network(&mut streets, &doc, clip_pts.is_some(), timer)?;
+    Ok((streets, doc))
+}
+
+fn blank_network(clip_pts: &Option<Vec<LonLat>>, cfg: MapConfig) -> Result<StreetNetwork> {
     let mut streets = StreetNetwork::blank();
     // Note that DrivingSide is still incorrect. It'll be set in extract_osm, before Road::new
     // happens in split_ways.
@@ -39,37 +103,60 @@ pub fn osm_to_street_network(
         streets.boundary_polygon = Ring::new(gps_bounds.convert(pts))?.into_polygon();
         streets.gps_bounds = gps_bounds;
     }
+    Ok(streets)
+}
+
+fn build_network(
+    streets: &mut StreetNetwork,
+    doc: &osm_reader::Document,
+    clipped: bool,
+    timer: &mut Timer,
+) -> Result<()> {
+    let extract = extract_osm(streets, doc, clipped, timer);
+    split_ways::split_up_roads(streets, extract, timer);
+    // Before clipping, so roads near the boundary still see their neighbours
+    let roads: Vec<_> = streets.roads.keys().cloned().collect();
+    for r in roads {
+        streets.resolve_transition_placement(r);
+    }
+    clip::clip_map(streets, timer)?;
 
-    let (extract, doc) = extract_osm(&mut streets, osm_xml_input, clip_pts, timer)?;
-    split_ways::split_up_roads(&mut streets, extract, timer);
-    clip::clip_map(&mut streets, timer)?;
+    streets.update_crossings();
 
-    // Cul-de-sacs aren't supported yet.
-    streets.retain_roads(|r| r.src_i != r.dst_i);
+    if streets.config.compare_lane_parsers {
+        let disagreements = streets
+            .roads
+            .values()
+            .filter(|r| r.lane_provenance.disagreement.is_some())
+            .count();
+        info!(
+            "classic and osm2lanes disagree about the lanes of {disagreements} of {} roads",
+            streets.roads.len()
+        );
+    }
 
-    Ok((streets, doc))
+    Ok(())
 }
 
 fn extract_osm(
     streets: &mut StreetNetwork,
-    osm_xml_input: &str,
-    clip_pts: Option<Vec<LonLat>>,
+    doc: &osm_reader::Document,
+    clipped: bool,
     timer: &mut Timer,
-) -> Result<(OsmExtract, osm_reader::Document)> {
-    let doc = crate::osm_reader::read(osm_xml_input, &streets.gps_bounds, timer)?;
-
-    if clip_pts.is_none() {
+) -> OsmExtract {
+    if !clipped {
         // Use the boundary from .osm.
         streets.gps_bounds = doc.gps_bounds.clone();
         streets.boundary_polygon = streets.gps_bounds.to_bounds().get_rectangle();
     }
-    // Calculate DrivingSide from some arbitrary point
-    streets.config.driving_side =
-        if driving_side::is_left_handed(streets.gps_bounds.get_rectangle()[0].into()) {
-            DrivingSide::Left
-        } else {
-            DrivingSide::Right
-        };
+    // Calculate DrivingSide and the unit of default speed limits from some arbitrary point
+    let pt = streets.gps_bounds.get_rectangle()[0];
+    streets.config.driving_side = if driving_side::is_left_handed(pt.into()) {
+        DrivingSide::Left
+    } else {
+        DrivingSide::Right
+    };
+    streets.config.default_speed_unit = SpeedUnit::signed_at(pt);
 
     let mut out = OsmExtract::new();
 
@@ -91,5 +178,138 @@ fn extract_osm(
         out.handle_relation(*id, rel);
     }
 
-    Ok((out, doc))
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use geom::{Distance, Pt2D};
+    use osm2streets::osm::WayID;
+    use osm2streets::Transformation;
+
+    use super::*;
+
+    #[test]
+    fn test_filtered_matches_unfiltered() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../tests/src/montlake_roundabout/input.osm"
+        );
+        let mut timer = Timer::throwaway();
+        let (unfiltered, _) = osm_to_street_network(
+            &std::fs::read_to_string(path).unwrap(),
+            None,
+            MapConfig::default(),
+            &mut timer,
+        )
+        .unwrap();
+        let (filtered, _) =
+            osm_file_to_street_network(Path::new(path), None, MapConfig::default(), &mut timer)
+                .unwrap();
+        assert_eq!(filtered.roads, unfiltered.roads);
+        assert_eq!(filtered.intersections, unfiltered.intersections);
+    }
+
+    #[test]
+    fn test_stable_ids_survive_an_extra_way() {
+        let input = std::fs::read_to_string(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../tests/src/montlake_roundabout/input.osm"
+        ))
+        .unwrap();
+        // A disconnected way with lower IDs than everything else, so sequential IDs would all
+        // shift
+        let extra_nodes = r#" <node id="1" lat="47.6395" lon="-122.3040"/>
+ <node id="2" lat="47.6395" lon="-122.3035"/>
+"#;
+        let extra_way = r#" <way id="1">
+  <nd ref="1"/>
+  <nd ref="2"/>
+  <tag k="highway" v="residential"/>
+ </way>
+"#;
+        let first_way = input.find(" <way").unwrap();
+        let first_relation = input.find(" <relation").unwrap();
+        let changed_input = format!(
+            "{}{}{}{}{}",
+            &input[..first_way],
+            extra_nodes,
+            &input[first_way..first_relation],
+            extra_way,
+            &input[first_relation..]
+        );
+
+        let mut cfg = MapConfig::default();
+        cfg.stable_ids = true;
+        let mut timer = Timer::throwaway();
+        let (before, _) = osm_to_street_network(&input, None, cfg.clone(), &mut timer).unwrap();
+        let (after, _) = osm_to_street_network(&changed_input, None, cfg, &mut timer).unwrap();
+
+        assert_eq!(after.roads.len(), before.roads.len() + 1);
+        assert_eq!(after.intersections.len(), before.intersections.len() + 2);
+        for road in before.roads.values() {
+            assert_eq!(after.roads[&road.id].osm_ids, road.osm_ids);
+        }
+        for i in before.intersections.values() {
+            assert_eq!(after.intersections[&i.id].osm_ids, i.osm_ids);
+        }
+    }
+
+    #[test]
+    fn test_incremental_tag_update_matches_full() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../tests/src/montlake_roundabout/input.osm"
+        );
+        let mut timer = Timer::throwaway();
+        let (mut streets, doc) = osm_to_street_network(
+            &std::fs::read_to_string(path).unwrap(),
+            None,
+            MapConfig::default(),
+            &mut timer,
+        )
+        .unwrap();
+        streets.apply_transformations(Transformation::standard_for_clipped_areas(), &mut timer);
+
+        // Widen East Lynn Street
+        let way = WayID(6348168);
+        let mut tags = doc.ways[&way].tags.clone();
+        tags.insert("lanes", "4");
+        let changed = streets.update_osm_tags_for_way(way, &tags).unwrap();
+        assert!(!changed.intersections.is_empty());
+
+        let mut full = streets.clone();
+        full.apply_transformations(
+            vec![Transformation::GenerateIntersectionGeometry],
+            &mut timer,
+        );
+
+        let same_pts = |pts1: &[Pt2D], pts2: &[Pt2D]| {
+            pts1.len() == pts2.len()
+                && pts1
+                    .iter()
+                    .zip(pts2)
+                    .all(|(pt1, pt2)| pt1.approx_eq(*pt2, Distance::meters(0.01)))
+        };
+        for road in streets.roads.values() {
+            assert!(
+                same_pts(
+                    road.center_line.points(),
+                    full.roads[&road.id].center_line.points()
+                ),
+                "{} is trimmed differently",
+                road.id
+            );
+        }
+        for i in streets.intersections.values() {
+            assert!(
+                same_pts(
+                    i.polygon.get_outer_ring().points(),
+                    full.intersections[&i.id].polygon.get_outer_ring().points()
+                ),
+                "{} has a different polygon",
+                i.id
+            );
+        }
+    }
 }
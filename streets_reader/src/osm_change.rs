@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+
+use abstutil::Tags;
+use geom::Distance;
+
+use osm2streets::osm::{NodeID, OsmID, WayID};
+use osm2streets::{lane_specs_to_osm_tags, OriginalRoad, Road, StreetNetwork};
+
+use crate::osm_reader::Document;
+
+/// Describes edits to a `StreetNetwork` as an
+/// [osmChange](https://wiki.openstreetmap.org/wiki/OsmChange) document against the OSM data it was
+/// imported from. `before` is the network as it was imported, and `after` has the edits.
+/// Transformations change lanes and geometry too, so only differences between the two networks
+/// count as edits, not differences from `doc`.
+///
+/// - The lanes of every edited road are written back as tags on its way. `edited_tags` holds
+///   tags edited directly, which are used instead of the original tags of those ways.
+/// - When different roads from the same way now have different tags, the way is split. The first
+///   piece keeps the original ID, and the others are created with negative IDs. Ways belonging
+///   to a relation aren't split, since the relation would have to be fixed too; a warning is
+///   logged and they're left alone.
+/// - Intersections from one OSM node that were moved update that node.
+///
+/// Roads split in the middle of an OSM segment, changes to the shape of roads between
+/// intersections, and new or deleted objects aren't handled yet. Modified objects keep the
+/// `version` from `doc`, so the result can only be uploaded if nobody else has changed them since.
+pub fn write_osm_change(
+    before: &StreetNetwork,
+    after: &StreetNetwork,
+    doc: &Document,
+    edited_tags: &BTreeMap<WayID, Tags>,
+) -> String {
+    let mut create = Vec::new();
+    let mut modify = Vec::new();
+
+    for i in after.intersections.values() {
+        if i.is_map_edge() || i.osm_ids.len() != 1 {
+            continue;
+        }
+        let moved = before
+            .intersections
+            .get(&i.id)
+            .map(|orig| !orig.point.approx_eq(i.point, Distance::meters(0.1)))
+            .unwrap_or(false);
+        if let (true, Some(node)) = (moved, doc.nodes.get(&i.osm_ids[0])) {
+            let gps = i.point.to_gps(&after.gps_bounds);
+            let mut out = format!(r#"  <node id="{}""#, i.osm_ids[0].0);
+            write_version(&mut out, node.version);
+            out.push_str(&format!(r#" lat="{}" lon="{}""#, gps.y(), gps.x()));
+            if node.tags.inner().is_empty() {
+                out.push_str("/>\n");
+            } else {
+                out.push_str(">\n");
+                write_tags(&mut out, &node.tags);
+                out.push_str("  </node>\n");
+            }
+            modify.push(out);
+        }
+    }
+
+    let mut roads_per_segment: BTreeMap<OriginalRoad, &Road> = BTreeMap::new();
+    for road in after.roads.values() {
+        for id in &road.osm_ids {
+            if roads_per_segment.insert(*id, road).is_some() {
+                warn!("Multiple roads come from {id}; only one will be written");
+            }
+        }
+    }
+    let ways_in_relations: BTreeSet<WayID> = doc
+        .relations
+        .values()
+        .flat_map(|r| r.members.iter())
+        .filter_map(|(_, member)| match member {
+            OsmID::Way(w) => Some(*w),
+            _ => None,
+        })
+        .collect();
+
+    let mut next_new_id = -1;
+    for (id, way) in &doc.ways {
+        let base_tags = edited_tags.get(id).unwrap_or(&way.tags);
+        let mut pieces = Vec::new();
+        for (segment, road) in roads_per_segment.range(segments_of(*id)) {
+            let start = match way.nodes.iter().position(|n| *n == segment.i1) {
+                Some(idx) => idx,
+                None => continue,
+            };
+            let end = match way.nodes[start + 1..].iter().position(|n| *n == segment.i2) {
+                Some(idx) => start + 1 + idx,
+                None => continue,
+            };
+            let edited = before
+                .roads
+                .get(&road.id)
+                .map(|orig| orig.lane_specs_ltr != road.lane_specs_ltr)
+                .unwrap_or(true);
+            let tags = if edited {
+                lane_specs_to_osm_tags(&road.lane_specs_ltr, base_tags, &after.config)
+            } else {
+                base_tags.clone()
+            };
+            pieces.push((start, end, tags));
+        }
+        if pieces.is_empty() && !edited_tags.contains_key(id) {
+            continue;
+        }
+        let pieces = cover_way(pieces, way.nodes.len(), base_tags);
+
+        if pieces.len() > 1 && ways_in_relations.contains(id) {
+            warn!("Not splitting {id}, because it belongs to a relation");
+            continue;
+        }
+        for (idx, (start, end, tags)) in pieces.into_iter().enumerate() {
+            let nodes = &way.nodes[start..=end];
+            if idx == 0 {
+                if tags != way.tags || nodes.len() != way.nodes.len() {
+                    modify.push(way_xml(id.0, way.version, nodes, &tags));
+                }
+            } else {
+                create.push(way_xml(next_new_id, None, nodes, &tags));
+                next_new_id -= 1;
+            }
+        }
+    }
+
+    let mut out = String::from(r#"<osmChange version="0.6" generator="osm2streets">"#);
+    out.push('\n');
+    for (name, elements) in [("create", create), ("modify", modify)] {
+        if elements.is_empty() {
+            out.push_str(&format!("<{name}/>\n"));
+        } else {
+            out.push_str(&format!("<{name}>\n"));
+            for element in elements {
+                out.push_str(&element);
+            }
+            out.push_str(&format!("</{name}>\n"));
+        }
+    }
+    out.push_str("</osmChange>\n");
+    out
+}
+
+/// All segments of a way, for looking up a range in a `BTreeMap`
+fn segments_of(way: WayID) -> RangeInclusive<OriginalRoad> {
+    let first = OriginalRoad::new(way.0, (i64::MIN, i64::MIN));
+    let last = OriginalRoad::new(way.0, (i64::MAX, i64::MAX));
+    first..=last
+}
+
+/// Fills the gaps between pieces of a way with the way's own tags, then merges adjacent pieces
+/// with the same tags. Each piece is the index of its first and last node, and its tags.
+fn cover_way(
+    mut pieces: Vec<(usize, usize, Tags)>,
+    num_nodes: usize,
+    base_tags: &Tags,
+) -> Vec<(usize, usize, Tags)> {
+    pieces.sort_by_key(|(start, _, _)| *start);
+    let mut result: Vec<(usize, usize, Tags)> = Vec::new();
+    let mut covered = 0;
+    for (start, end, tags) in pieces {
+        if start < covered {
+            // Overlapping segments; ignore the second one
+            continue;
+        }
+        if start > covered {
+            result.push((covered, start, base_tags.clone()));
+        }
+        result.push((start, end, tags));
+        covered = end;
+    }
+    if covered < num_nodes - 1 || result.is_empty() {
+        result.push((covered, num_nodes - 1, base_tags.clone()));
+    }
+
+    let mut merged: Vec<(usize, usize, Tags)> = Vec::new();
+    for (start, end, tags) in result {
+        if let Some(last) = merged.last_mut() {
+            if last.2 == tags {
+                last.1 = end;
+                continue;
+            }
+        }
+        merged.push((start, end, tags));
+    }
+    merged
+}
+
+fn way_xml(id: i64, version: Option<usize>, nodes: &[NodeID], tags: &Tags) -> String {
+    let mut out = format!(r#"  <way id="{id}""#);
+    write_version(&mut out, version);
+    out.push_str(">\n");
+    for node in nodes {
+        out.push_str(&format!("    <nd ref=\"{}\"/>\n", node.0));
+    }
+    write_tags(&mut out, tags);
+    out.push_str("  </way>\n");
+    out
+}
+
+fn write_version(out: &mut String, version: Option<usize>) {
+    if let Some(version) = version {
+        out.push_str(&format!(r#" version="{version}""#));
+    }
+}
+
+fn write_tags(out: &mut String, tags: &Tags) {
+    for (k, v) in tags.inner() {
+        out.push_str(&format!(
+            "    <tag k=\"{}\" v=\"{}\"/>\n",
+            escape(k),
+            escape(v)
+        ));
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use abstutil::Timer;
+    use geom::Pt2D;
+    use osm2streets::{get_lane_specs_ltr, LaneSpec, MapConfig};
+
+    use super::*;
+
+    // Main Street is split by a side street at node 2
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6" generator="osm2streets test fixture">
+  <node id="1" version="1" lat="47.6000000" lon="-122.3000000"/>
+  <node id="2" version="4" lat="47.6000000" lon="-122.2990000"/>
+  <node id="3" version="1" lat="47.6000000" lon="-122.2980000"/>
+  <node id="4" version="1" lat="47.6010000" lon="-122.2990000"/>
+  <way id="10" version="3">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <tag k="highway" v="residential"/>
+    <tag k="lanes" v="2"/>
+    <tag k="name" v="Main Street"/>
+    <tag k="sidewalk" v="both"/>
+  </way>
+  <way id="11" version="1">
+    <nd ref="2"/>
+    <nd ref="4"/>
+    <tag k="highway" v="residential"/>
+  </way>
+</osm>
+"#;
+
+    #[test]
+    fn test_write_osm_change() {
+        let mut timer = Timer::throwaway();
+        let (before, doc) =
+            crate::osm_to_street_network(XML, None, MapConfig::default(), &mut timer).unwrap();
+
+        // Add bike lanes to the first half of Main Street, and move the junction a bit
+        let mut after = before.clone();
+        let segment = OriginalRoad::new(10, (1, 2));
+        let road = after
+            .roads
+            .values_mut()
+            .find(|r| r.osm_ids.contains(&segment))
+            .unwrap();
+        LaneSpec::maybe_add_bike_lanes(&mut road.lane_specs_ltr, None, after.config.driving_side);
+        let edited_lanes = road.lane_specs_ltr.clone();
+        let junction = after
+            .intersections
+            .values_mut()
+            .find(|i| i.osm_ids == vec![NodeID(2)])
+            .unwrap();
+        junction.point = Pt2D::new(junction.point.x() + 5.0, junction.point.y());
+
+        let out = write_osm_change(&before, &after, &doc, &BTreeMap::new());
+        let (create, modify) = out.split_once("<modify>").unwrap();
+        let way_tags =
+            lane_specs_to_osm_tags(&edited_lanes, &doc.ways[&WayID(10)].tags, &after.config);
+
+        // The first piece keeps the way's ID and version, with the edited lanes
+        let mut expected_way = String::from("  <way id=\"10\" version=\"3\">\n");
+        expected_way.push_str("    <nd ref=\"1\"/>\n    <nd ref=\"2\"/>\n");
+        write_tags(&mut expected_way, &way_tags);
+        expected_way.push_str("  </way>\n");
+        assert!(modify.contains(&expected_way), "{out}");
+
+        // The rest of the way is split off with a new ID and the original tags
+        let mut expected_new_way = String::from("  <way id=\"-1\">\n");
+        expected_new_way.push_str("    <nd ref=\"2\"/>\n    <nd ref=\"3\"/>\n");
+        write_tags(&mut expected_new_way, &doc.ways[&WayID(10)].tags);
+        expected_new_way.push_str("  </way>\n");
+        assert!(create.contains(&expected_new_way), "{out}");
+
+        // Only the moved node changes, keeping its version
+        assert!(modify.contains(r#"<node id="2" version="4""#), "{out}");
+        for untouched in [r#"<node id="1""#, r#"<node id="3""#, r#"<way id="11""#] {
+            assert!(!out.contains(untouched), "{out}");
+        }
+
+        // The written tags describe the edited lanes
+        let parsed = get_lane_specs_ltr(&way_tags, &after.config);
+        let summary = |lanes: &[LaneSpec]| -> Vec<_> {
+            lanes.iter().map(|lane| (lane.lt, lane.dir)).collect()
+        };
+        assert_eq!(summary(&parsed), summary(&edited_lanes));
+        assert_ne!(way_tags, doc.ways[&WayID(10)].tags);
+    }
+}
@@ -1,5 +1,7 @@
 mod geom;
+mod pbf;
 mod reader;
 
 pub use self::geom::*;
+pub use self::pbf::*;
 pub use self::reader::*;
@@ -0,0 +1,442 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use anyhow::Result;
+use osmpbf::{Element, ElementReader, RelMemberType};
+
+use abstutil::{prettyprint_usize, Tags, Timer};
+use geom::{GPSBounds, LonLat};
+use osm2streets::osm::{NodeID, OsmID, RelationID, WayID};
+
+use super::{Document, Node, Relation, Way};
+
+/// Returns true if the input looks like an `.osm.pbf` file. The first blob header of a PBF file
+/// always declares the `OSMHeader` type; anything else is assumed to be XML.
+pub fn is_pbf(input: &[u8]) -> bool {
+    // 4 bytes for the length of the BlobHeader, then the protobuf field tag and string length
+    input.len() >= 15 && &input[6..15] == b"OSMHeader"
+}
+
+/// Reads an `.osm.pbf` file into the same `Document` produced by the XML reader. The file is
+/// streamed, so only the objects kept are held in memory.
+///
+/// If `bbox` is specified, only ways with at least one node inside of it are kept. All nodes of
+/// those ways are kept, even if they're outside the bounding box, so that clipping can still cut
+/// roads at the boundary properly.
+pub fn read_pbf(
+    path: &Path,
+    input_gps_bounds: &GPSBounds,
+    bbox: Option<&GPSBounds>,
+    timer: &mut Timer,
+) -> Result<Document> {
+    read_pbf_objects(path, input_gps_bounds, bbox, None, &|_| true, timer)
+}
+
+/// Like `read_pbf`, but additionally only keeps ways matching `keep_way`, the nodes they
+/// reference, and relations matching `keep_relation`. The first pass over the file only tracks
+/// IDs, so the nodes of irrelevant ways never have to be held in memory.
+pub fn read_pbf_filtered(
+    path: &Path,
+    input_gps_bounds: &GPSBounds,
+    bbox: Option<&GPSBounds>,
+    keep_way: &dyn Fn(&Tags) -> bool,
+    keep_relation: &dyn Fn(&Tags) -> bool,
+    timer: &mut Timer,
+) -> Result<Document> {
+    read_pbf_objects(
+        path,
+        input_gps_bounds,
+        bbox,
+        Some(keep_way),
+        keep_relation,
+        timer,
+    )
+}
+
+fn read_pbf_objects(
+    path: &Path,
+    input_gps_bounds: &GPSBounds,
+    bbox: Option<&GPSBounds>,
+    keep_way: Option<&dyn Fn(&Tags) -> bool>,
+    keep_relation: &dyn Fn(&Tags) -> bool,
+    timer: &mut Timer,
+) -> Result<Document> {
+    let mut doc = Document {
+        gps_bounds: input_gps_bounds.clone(),
+        nodes: BTreeMap::new(),
+        ways: BTreeMap::new(),
+        relations: BTreeMap::new(),
+    };
+    if doc.gps_bounds == GPSBounds::new() {
+        if let Some(bbox) = bbox {
+            doc.gps_bounds = bbox.clone();
+        }
+    }
+
+    // PBF files have no <bounds> element and we can't convert nodes to Pt2D without knowing the
+    // bounds first, so we may need an extra pass over the file. When we're filtering, the same
+    // pass figures out which ways and nodes to keep.
+    let filtering = bbox.is_some() || keep_way.is_some();
+    let mut keep_nodes: Option<HashSet<i64>> = None;
+    let mut keep_ways: Option<HashSet<i64>> = None;
+    if filtering || doc.gps_bounds == GPSBounds::new() {
+        timer.start("scrape bounds and relevant objects");
+        let mut scraped_bounds = GPSBounds::new();
+        let mut nodes_inside = HashSet::new();
+        let mut referenced_nodes = HashSet::new();
+        let mut relevant_ways = HashSet::new();
+        ElementReader::from_path(path)?.for_each(|element| match element {
+            Element::Node(node) => scrape_node(
+                node.id(),
+                LonLat::new(node.lon(), node.lat()),
+                bbox,
+                &mut scraped_bounds,
+                &mut nodes_inside,
+            ),
+            Element::DenseNode(node) => scrape_node(
+                node.id(),
+                LonLat::new(node.lon(), node.lat()),
+                bbox,
+                &mut scraped_bounds,
+                &mut nodes_inside,
+            ),
+            Element::Way(way) => {
+                if !filtering {
+                    return;
+                }
+                if let Some(keep_way) = keep_way {
+                    if !keep_way(&read_tags(way.tags())) {
+                        return;
+                    }
+                }
+                let refs: Vec<i64> = way.refs().collect();
+                if bbox.is_none() || refs.iter().any(|n| nodes_inside.contains(n)) {
+                    relevant_ways.insert(way.id());
+                    referenced_nodes.extend(refs);
+                }
+            }
+            Element::Relation(_) => {}
+        })?;
+        timer.stop("scrape bounds and relevant objects");
+
+        if doc.gps_bounds == GPSBounds::new() {
+            doc.gps_bounds = scraped_bounds;
+        }
+        if filtering {
+            info!(
+                "Keeping {} ways, referencing {} nodes",
+                prettyprint_usize(relevant_ways.len()),
+                prettyprint_usize(referenced_nodes.len())
+            );
+            keep_nodes = Some(referenced_nodes);
+            keep_ways = Some(relevant_ways);
+        }
+    }
+
+    timer.start("scrape objects");
+    let mut error = None;
+    // Relations may refer to ways and other relations later in the file, so members are only
+    // checked after everything has been read
+    let mut relations = Vec::new();
+    let mut relation_ids = HashSet::new();
+    ElementReader::from_path(path)?.for_each(|element| {
+        if error.is_some() {
+            return;
+        }
+        match element {
+            Element::Node(node) => {
+                if let Err(err) = insert_node(
+                    &mut doc,
+                    keep_nodes.as_ref(),
+                    node.id(),
+                    LonLat::new(node.lon(), node.lat()),
+                    node.info().version().map(|x| x as usize),
+                    node.tags(),
+                ) {
+                    error = Some(err);
+                }
+            }
+            Element::DenseNode(node) => {
+                if let Err(err) = insert_node(
+                    &mut doc,
+                    keep_nodes.as_ref(),
+                    node.id(),
+                    LonLat::new(node.lon(), node.lat()),
+                    node.info().map(|info| info.version() as usize),
+                    node.tags(),
+                ) {
+                    error = Some(err);
+                }
+            }
+            Element::Way(way) => {
+                if keep_ways
+                    .as_ref()
+                    .map(|keep| !keep.contains(&way.id()))
+                    .unwrap_or(false)
+                {
+                    return;
+                }
+                let id = WayID(way.id());
+                if doc.ways.contains_key(&id) {
+                    error = Some(anyhow!("Duplicate {}, your .osm.pbf is corrupt", id));
+                    return;
+                }
+
+                let mut nodes = Vec::new();
+                let mut pts = Vec::new();
+                for n in way.refs() {
+                    let n = NodeID(n);
+                    // Just skip missing nodes
+                    if let Some(node) = doc.nodes.get(&n) {
+                        nodes.push(n);
+                        pts.push(node.pt);
+                    }
+                }
+                if nodes.is_empty() {
+                    return;
+                }
+
+                let version = way.info().version().map(|x| x as usize);
+                doc.ways.insert(
+                    id,
+                    Way {
+                        nodes,
+                        pts,
+                        tags: read_tags(way.tags()),
+                        version,
+                    },
+                );
+            }
+            Element::Relation(rel) => {
+                let id = RelationID(rel.id());
+                if !relation_ids.insert(id) {
+                    error = Some(anyhow!("Duplicate {}, your .osm.pbf is corrupt", id));
+                    return;
+                }
+
+                let tags = read_tags(rel.tags());
+                if !keep_relation(&tags) {
+                    return;
+                }
+
+                let members = rel
+                    .members()
+                    .map(|member| {
+                        let member_id = match member.member_type {
+                            RelMemberType::Node => OsmID::Node(NodeID(member.member_id)),
+                            RelMemberType::Way => OsmID::Way(WayID(member.member_id)),
+                            RelMemberType::Relation => {
+                                OsmID::Relation(RelationID(member.member_id))
+                            }
+                        };
+                        (member.role().unwrap_or("").to_string(), member_id)
+                    })
+                    .collect();
+                relations.push((id, Relation { tags, members }));
+            }
+        }
+    })?;
+    timer.stop("scrape objects");
+    if let Some(err) = error {
+        return Err(err);
+    }
+    insert_relations(&mut doc, relations);
+
+    info!(
+        "Found {} nodes, {} ways, {} relations",
+        prettyprint_usize(doc.nodes.len()),
+        prettyprint_usize(doc.ways.len()),
+        prettyprint_usize(doc.relations.len())
+    );
+
+    Ok(doc)
+}
+
+/// Inserts relations, keeping only members that were read. Like references to missing objects
+/// elsewhere, other members are just filtered out. Unlike the XML reader, this doesn't depend on
+/// the order of objects in the file.
+fn insert_relations(doc: &mut Document, relations: Vec<(RelationID, Relation)>) {
+    let ids: HashSet<RelationID> = relations.iter().map(|(id, _)| *id).collect();
+    for (id, mut relation) in relations {
+        relation.members.retain(|(_, member)| match member {
+            OsmID::Node(n) => doc.nodes.contains_key(n),
+            OsmID::Way(w) => doc.ways.contains_key(w),
+            OsmID::Relation(r) => ids.contains(r),
+        });
+        doc.relations.insert(id, relation);
+    }
+}
+
+fn scrape_node(
+    id: i64,
+    pt: LonLat,
+    bbox: Option<&GPSBounds>,
+    scraped_bounds: &mut GPSBounds,
+    nodes_inside: &mut HashSet<i64>,
+) {
+    scraped_bounds.update(pt);
+    if let Some(bbox) = bbox {
+        if bbox.contains(pt) {
+            nodes_inside.insert(id);
+        }
+    }
+}
+
+fn insert_node<'a>(
+    doc: &mut Document,
+    keep_nodes: Option<&HashSet<i64>>,
+    id: i64,
+    pt: LonLat,
+    version: Option<usize>,
+    tags: impl Iterator<Item = (&'a str, &'a str)>,
+) -> Result<()> {
+    if let Some(keep) = keep_nodes {
+        if !keep.contains(&id) {
+            return Ok(());
+        }
+    }
+
+    let id = NodeID(id);
+    if doc.nodes.contains_key(&id) {
+        bail!("Duplicate {}, your .osm.pbf is corrupt", id);
+    }
+    doc.nodes.insert(
+        id,
+        Node {
+            pt: pt.to_pt(&doc.gps_bounds),
+            tags: read_tags(tags),
+            version,
+        },
+    );
+    Ok(())
+}
+
+fn read_tags<'a>(input: impl Iterator<Item = (&'a str, &'a str)>) -> Tags {
+    let mut tags = Tags::empty();
+    for (key, value) in input {
+        tags.insert(key, value);
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The same objects, encoded both ways. A way and a relation member are outside of the bbox used
+    // below.
+    const XML: &str = include_str!("fixtures/tiny.osm");
+    const PBF: &[u8] = include_bytes!("fixtures/tiny.osm.pbf");
+    const PBF_PATH: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/osm_reader/fixtures/tiny.osm.pbf"
+    );
+
+    fn gps_bounds() -> GPSBounds {
+        let mut bounds = GPSBounds::new();
+        bounds.update(LonLat::new(-122.31, 47.59));
+        bounds.update(LonLat::new(-122.24, 47.66));
+        bounds
+    }
+
+    #[test]
+    fn test_is_pbf() {
+        assert!(is_pbf(PBF));
+        assert!(!is_pbf(XML.as_bytes()));
+        assert!(!is_pbf(&PBF[..10]));
+    }
+
+    #[test]
+    fn test_pbf_matches_xml() {
+        let mut timer = Timer::throwaway();
+        let expected = super::super::read(XML, &gps_bounds(), &mut timer).unwrap();
+        let actual = read_pbf(Path::new(PBF_PATH), &gps_bounds(), None, &mut timer).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_pbf_with_bbox() {
+        let mut timer = Timer::throwaway();
+        let mut bbox = GPSBounds::new();
+        bbox.update(LonLat::new(-122.31, 47.59));
+        bbox.update(LonLat::new(-122.29, 47.61));
+
+        // Only the way touching the bbox and the nodes it references are kept
+        let mut expected = super::super::read(XML, &gps_bounds(), &mut timer).unwrap();
+        expected.ways.remove(&WayID(11));
+        expected.nodes.retain(|id, _| [1, 2, 3].contains(&id.0));
+        for relation in expected.relations.values_mut() {
+            relation
+                .members
+                .retain(|(_, member)| *member != OsmID::Way(WayID(11)));
+        }
+
+        let actual = read_pbf(Path::new(PBF_PATH), &gps_bounds(), Some(&bbox), &mut timer).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_relation_members_read_later() {
+        let mut doc = Document {
+            gps_bounds: gps_bounds(),
+            nodes: BTreeMap::new(),
+            ways: BTreeMap::new(),
+            relations: BTreeMap::new(),
+        };
+        let pt = geom::Pt2D::new(0.0, 0.0);
+        doc.nodes.insert(
+            NodeID(1),
+            Node {
+                pt,
+                tags: Tags::empty(),
+                version: None,
+            },
+        );
+        doc.ways.insert(
+            WayID(10),
+            Way {
+                nodes: vec![NodeID(1)],
+                pts: vec![pt],
+                tags: Tags::empty(),
+                version: None,
+            },
+        );
+
+        // The first relation refers to the second one, which is read after it, and to some objects
+        // that were never read
+        let member = |role: &str, id: OsmID| (role.to_string(), id);
+        let relations = vec![
+            (
+                RelationID(20),
+                Relation {
+                    tags: Tags::empty(),
+                    members: vec![
+                        member("from", OsmID::Way(WayID(10))),
+                        member("via", OsmID::Node(NodeID(1))),
+                        member("to", OsmID::Way(WayID(99))),
+                        member("", OsmID::Relation(RelationID(21))),
+                        member("", OsmID::Relation(RelationID(98))),
+                    ],
+                },
+            ),
+            (
+                RelationID(21),
+                Relation {
+                    tags: Tags::empty(),
+                    members: vec![member("", OsmID::Node(NodeID(2)))],
+                },
+            ),
+        ];
+        insert_relations(&mut doc, relations);
+
+        assert_eq!(
+            doc.relations[&RelationID(20)].members,
+            vec![
+                member("from", OsmID::Way(WayID(10))),
+                member("via", OsmID::Node(NodeID(1))),
+                member("", OsmID::Relation(RelationID(21))),
+            ]
+        );
+        assert!(doc.relations[&RelationID(21)].members.is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::iter::Peekable;
 
 use anyhow::Result;
@@ -18,6 +18,7 @@ use osm2streets::osm::{NodeID, OsmID, RelationID, WayID};
 // TODO Replicate IDs in each object, and change members to just hold a reference to the object
 // (which is guaranteed to exist).
 
+#[derive(Debug, PartialEq)]
 pub struct Document {
     pub gps_bounds: GPSBounds,
     pub nodes: BTreeMap<NodeID, Node>,
@@ -25,11 +26,14 @@ pub struct Document {
     pub relations: BTreeMap<RelationID, Relation>,
 }
 
+#[derive(Debug, PartialEq)]
 pub struct Node {
     pub pt: Pt2D,
     pub tags: Tags,
+    pub version: Option<usize>,
 }
 
+#[derive(Debug, PartialEq)]
 pub struct Way {
     // Duplicates geometry, because it's convenient
     pub nodes: Vec<NodeID>,
@@ -38,6 +42,7 @@ pub struct Way {
     pub version: Option<usize>,
 }
 
+#[derive(Debug, PartialEq)]
 pub struct Relation {
     pub tags: Tags,
     /// Role, member
@@ -45,6 +50,79 @@ pub struct Relation {
 }
 
 pub fn read(raw_string: &str, input_gps_bounds: &GPSBounds, timer: &mut Timer) -> Result<Document> {
+    read_objects(raw_string, input_gps_bounds, None, &|_| true, timer)
+}
+
+/// Like `read`, but only keeps ways matching `keep_way`, the nodes they reference, and relations
+/// matching `keep_relation`. This makes two passes over the input. The first one just finds the
+/// relevant ways, so that the nodes of everything else never have to be held in memory.
+pub fn read_filtered(
+    raw_string: &str,
+    input_gps_bounds: &GPSBounds,
+    keep_way: &dyn Fn(&Tags) -> bool,
+    keep_relation: &dyn Fn(&Tags) -> bool,
+    timer: &mut Timer,
+) -> Result<Document> {
+    timer.start("find relevant ways");
+    let filter = find_relevant_objects(raw_string, keep_way);
+    timer.stop("find relevant ways");
+    info!(
+        "Keeping {} ways, referencing {} nodes",
+        prettyprint_usize(filter.ways.len()),
+        prettyprint_usize(filter.nodes.len())
+    );
+
+    read_objects(
+        raw_string,
+        input_gps_bounds,
+        Some(&filter),
+        keep_relation,
+        timer,
+    )
+}
+
+/// The ways to keep while reading, and the nodes they reference
+struct ObjectFilter {
+    nodes: HashSet<NodeID>,
+    ways: HashSet<WayID>,
+}
+
+fn find_relevant_objects(raw_string: &str, keep_way: &dyn Fn(&Tags) -> bool) -> ObjectFilter {
+    let mut filter = ObjectFilter {
+        nodes: HashSet::new(),
+        ways: HashSet::new(),
+    };
+
+    let mut reader = ElementReader {
+        tokenizer: xmlparser::Tokenizer::from(raw_string),
+    }
+    .peekable();
+    while let Some(obj) = reader.next() {
+        if obj.name != "way" {
+            continue;
+        }
+        let id = WayID(obj.attribute("id").parse::<i64>().unwrap());
+        let mut nodes = Vec::new();
+        while reader.peek().map(|x| x.name == "nd").unwrap_or(false) {
+            let node_ref = reader.next().unwrap();
+            nodes.push(NodeID(node_ref.attribute("ref").parse::<i64>().unwrap()));
+        }
+        let tags = read_tags(&mut reader);
+        if keep_way(&tags) {
+            filter.ways.insert(id);
+            filter.nodes.extend(nodes);
+        }
+    }
+    filter
+}
+
+fn read_objects(
+    raw_string: &str,
+    input_gps_bounds: &GPSBounds,
+    filter: Option<&ObjectFilter>,
+    keep_relation: &dyn Fn(&Tags) -> bool,
+    timer: &mut Timer,
+) -> Result<Document> {
     let mut doc = Document {
         gps_bounds: input_gps_bounds.clone(),
         nodes: BTreeMap::new(),
@@ -86,6 +164,10 @@ pub fn read(raw_string: &str, input_gps_bounds: &GPSBounds, timer: &mut Timer) -
                 }
 
                 let id = NodeID(obj.attribute("id").parse::<i64>().unwrap());
+                // Any <tag>'s of skipped objects are just ignored by this loop
+                if filter.map(|f| !f.nodes.contains(&id)).unwrap_or(false) {
+                    continue;
+                }
                 if doc.nodes.contains_key(&id) {
                     bail!("Duplicate {}, your .osm is corrupt", id);
                 }
@@ -94,11 +176,18 @@ pub fn read(raw_string: &str, input_gps_bounds: &GPSBounds, timer: &mut Timer) -
                     obj.attribute("lat").parse::<f64>().unwrap(),
                 )
                 .to_pt(&doc.gps_bounds);
+                let version = obj
+                    .attributes
+                    .get("version")
+                    .and_then(|x| x.parse::<usize>().ok());
                 let tags = read_tags(&mut reader);
-                doc.nodes.insert(id, Node { pt, tags });
+                doc.nodes.insert(id, Node { pt, tags, version });
             }
             "way" => {
                 let id = WayID(obj.attribute("id").parse::<i64>().unwrap());
+                if filter.map(|f| !f.ways.contains(&id)).unwrap_or(false) {
+                    continue;
+                }
                 if doc.ways.contains_key(&id) {
                     bail!("Duplicate {}, your .osm is corrupt", id);
                 }
@@ -172,7 +261,9 @@ pub fn read(raw_string: &str, input_gps_bounds: &GPSBounds, timer: &mut Timer) -
                 // We assume <nd>'s come before <tag>'s
                 let tags = read_tags(&mut reader);
 
-                doc.relations.insert(id, Relation { tags, members });
+                if keep_relation(&tags) {
+                    doc.relations.insert(id, Relation { tags, members });
+                }
             }
             _ => {}
         }
@@ -3,8 +3,8 @@ use std::collections::{btree_map::Entry, BTreeMap, HashMap};
 use abstutil::{Counter, Tags, Timer};
 use geom::{Distance, HashablePt2D, PolyLine, Pt2D};
 use osm2streets::{
-    osm, Direction, IntersectionControl, IntersectionID, IntersectionKind, OriginalRoad, Road,
-    RoadID, StreetNetwork,
+    osm, ComplicatedTurnRestriction, Direction, IntersectionControl, IntersectionID,
+    IntersectionKind, OriginalRoad, Road, RoadID, StreetNetwork, TurnRestriction,
 };
 
 use super::OsmExtract;
@@ -64,6 +64,30 @@ pub fn split_up_roads(
         }
     }
 
+    // A way that leaves an intersection and returns to it without passing through any others (a
+    // cul-de-sac loop, or a closed way touching nothing else) would become a road with src_i ==
+    // dst_i. Lots of things assume that can't happen, so split these in the middle, using the OSM
+    // node there as an extra intersection.
+    for (id, pts, _) in &input.roads {
+        let mut last_idx = 0;
+        for (idx, raw_pt) in pts.iter().enumerate().skip(1) {
+            let i2 = match pt_to_intersection.get(&raw_pt.to_hashable()) {
+                Some(i2) => *i2,
+                None => continue,
+            };
+            let i1 = pt_to_intersection[&pts[last_idx].to_hashable()];
+            if i1 == i2 && idx - last_idx > 1 {
+                let middle = pts[(last_idx + idx) / 2].to_hashable();
+                info!(
+                    "Splitting loop on {} at {}",
+                    id, input.osm_node_ids[&middle]
+                );
+                pt_to_intersection.insert(middle, input.osm_node_ids[&middle]);
+            }
+            last_idx = idx;
+        }
+    }
+
     let mut osm_id_to_id: HashMap<osm::NodeID, IntersectionID> = HashMap::new();
     for (pt, osm_id) in &pt_to_intersection {
         let id = streets.insert_intersection(
@@ -74,8 +98,8 @@ pub fn split_up_roads(
             if input.traffic_signals.remove(pt).is_some() {
                 IntersectionControl::Signalled
             } else {
-                // TODO default to uncontrolled, guess StopSign as a transform
-                IntersectionControl::Signed
+                // Transformation::InferIntersectionControl looks for signs later
+                IntersectionControl::Uncontrolled
             },
         );
         osm_id_to_id.insert(*osm_id, id);
@@ -104,14 +128,30 @@ pub fn split_up_roads(
         let mut pts = Vec::new();
         let endpt1 = pt_to_intersection[&orig_pts[0].to_hashable()];
         let mut i1 = endpt1;
+        // Track how far along the way each piece is, so tags like placement:start and
+        // width:lanes:end describing the whole way can be applied to each piece
+        let way_length: Distance = orig_pts
+            .windows(2)
+            .map(|pair| pair[0].dist_to(pair[1]))
+            .sum();
+        let mut dist_along = Distance::ZERO;
+        let mut piece_start = Distance::ZERO;
 
-        for pt in orig_pts {
+        for (pt_idx, pt) in orig_pts.iter().enumerate() {
+            if let Some(prev) = pts.last() {
+                dist_along += prev.dist_to(*pt);
+            }
             pts.push(*pt);
             if pts.len() == 1 {
                 continue;
             }
             if let Some(i2) = pt_to_intersection.get(&pt.to_hashable()) {
-                let id = streets.next_road_id();
+                let osm_id = OriginalRoad {
+                    osm_way_id: *osm_way_id,
+                    i1,
+                    i2: *i2,
+                };
+                let id = streets.new_road_id(&[osm_id]);
 
                 // Note we populate this before simplify_linestring, so even if some points are
                 // removed, we can still associate them to the road.
@@ -124,34 +164,59 @@ pub fn split_up_roads(
                 let untrimmed_center_line = simplify_linestring(std::mem::take(&mut pts));
                 match PolyLine::new(untrimmed_center_line) {
                     Ok(pl) => {
-                        streets.roads.insert(
-                            id,
-                            Road::new(
+                        let end = if pt_idx == orig_pts.len() - 1 {
+                            1.0
+                        } else {
+                            dist_along / way_length
+                        };
+                        let piece = (
+                            osm_id_to_id[&i1],
+                            osm_id_to_id[i2],
+                            pl,
+                            (piece_start / way_length, end),
+                        );
+                        // Consecutive nodes can lead to the same intersection, like a short
+                        // connector between two nodes of a collapsed roundabout. There's no OSM
+                        // node to split at, but roads can't start and end at the same
+                        // intersection, so split in the middle.
+                        let pieces = if piece.0 == piece.1 {
+                            info!("Splitting loop on {osm_id} at a new intersection");
+                            split_loop(streets, piece)
+                        } else {
+                            vec![piece]
+                        };
+
+                        for (idx, (src_i, dst_i, pl, way_fractions)) in
+                            pieces.into_iter().enumerate()
+                        {
+                            let id = if idx == 0 {
+                                id
+                            } else {
+                                let id = streets.new_road_id(&[osm_id]);
+                                for pt in &pl.points()[1..pl.points().len() - 1] {
+                                    pt_to_road.insert(pt.to_hashable(), id);
+                                }
+                                id
+                            };
+                            let mut road = Road::new(
                                 id,
-                                vec![OriginalRoad {
-                                    osm_way_id: *osm_way_id,
-                                    i1,
-                                    i2: *i2,
-                                }],
-                                osm_id_to_id[&i1],
-                                osm_id_to_id[i2],
+                                vec![osm_id],
+                                src_i,
+                                dst_i,
                                 pl,
-                                tags,
+                                tags.clone(),
                                 &streets.config,
-                            ),
-                        );
-                        streets
-                            .intersections
-                            .get_mut(&osm_id_to_id[&i1])
-                            .unwrap()
-                            .roads
-                            .push(id);
-                        streets
-                            .intersections
-                            .get_mut(&osm_id_to_id[i2])
-                            .unwrap()
-                            .roads
-                            .push(id);
+                            );
+                            road.way_fractions = way_fractions;
+                            if road.way_fractions != (0.0, 1.0) {
+                                road.slice_width_profiles_to_way();
+                                road.update_center_line(streets.config.driving_side);
+                            }
+                            streets.roads.insert(id, road);
+                            for i in [src_i, dst_i] {
+                                streets.intersections.get_mut(&i).unwrap().roads.push(id);
+                            }
+                        }
                     }
                     Err(err) => {
                         error!("Skipping {id}: {err}");
@@ -163,6 +228,7 @@ pub fn split_up_roads(
                 // Start a new road
                 tags = orig_tags.clone();
                 i1 = *i2;
+                piece_start = dist_along;
                 pts.push(*pt);
             }
         }
@@ -171,7 +237,7 @@ pub fn split_up_roads(
 
     // Resolve simple turn restrictions (via a node)
     let mut restrictions = Vec::new();
-    for (restriction, from_osm, via_osm, to_osm) in input.simple_turn_restrictions {
+    for (restriction, conditions, from_osm, via_osm, to_osm) in input.simple_turn_restrictions {
         // A via node might not be an intersection
         let via_id = if let Some(x) = osm_id_to_id.get(&via_osm) {
             *x
@@ -188,66 +254,57 @@ pub fn split_up_roads(
             roads.iter().find(|r| r.osm_ids[0].osm_way_id == from_osm),
             roads.iter().find(|r| r.osm_ids[0].osm_way_id == to_osm),
         ) {
-            restrictions.push((from.id, restriction, to.id));
+            restrictions.push((
+                from.id,
+                TurnRestriction {
+                    restriction,
+                    to: to.id,
+                    conditions,
+                },
+            ));
         }
     }
-    for (from, rt, to) in restrictions {
+    for (from, tr) in restrictions {
         streets
             .roads
             .get_mut(&from)
             .unwrap()
             .turn_restrictions
-            .push((rt, to));
+            .push(tr);
     }
 
-    // Resolve complicated turn restrictions (via a way). TODO Only handle via ways immediately
-    // connected to both roads, for now
+    // Resolve complicated turn restrictions (via one or more ways). Each way may have been split
+    // into multiple roads, so search for a connected sequence of roads matching the ways in order.
     let mut complicated_restrictions = Vec::new();
-    for (rel_osm, from_osm, via_osm, to_osm) in input.complicated_turn_restrictions {
-        let via_candidates: Vec<&Road> = streets
-            .roads
-            .values()
-            .filter(|r| r.osm_ids[0].osm_way_id == via_osm)
-            .collect();
-        if via_candidates.len() != 1 {
+    for (rel_osm, restriction, conditions, from_osm, via_osm, to_osm) in
+        input.complicated_turn_restrictions
+    {
+        if let Some((from, via, to)) =
+            resolve_complicated_restriction(streets, from_osm, &via_osm, to_osm)
+        {
+            complicated_restrictions.push((
+                from,
+                ComplicatedTurnRestriction {
+                    restriction,
+                    via,
+                    to,
+                    conditions,
+                },
+            ));
+        } else {
             warn!(
-                "Couldn't resolve turn restriction from way {} to way {} via way {}. Candidate \
-                 roads for via: {:?}. See {}",
-                from_osm, to_osm, via_osm, via_candidates, rel_osm
+                "Couldn't resolve turn restriction from way {} to way {} via ways {:?}. See {}",
+                from_osm, to_osm, via_osm, rel_osm
             );
-            continue;
-        }
-        let via = via_candidates[0];
-
-        let maybe_from = streets
-            .roads_per_intersection(via.src_i)
-            .into_iter()
-            .chain(streets.roads_per_intersection(via.dst_i).into_iter())
-            .find(|r| r.osm_ids[0].osm_way_id == from_osm);
-        let maybe_to = streets
-            .roads_per_intersection(via.src_i)
-            .into_iter()
-            .chain(streets.roads_per_intersection(via.dst_i).into_iter())
-            .find(|r| r.osm_ids[0].osm_way_id == to_osm);
-        match (maybe_from, maybe_to) {
-            (Some(from), Some(to)) => {
-                complicated_restrictions.push((from.id, via.id, to.id));
-            }
-            _ => {
-                warn!(
-                    "Couldn't resolve turn restriction from {} to {} via {:?}",
-                    from_osm, to_osm, via
-                );
-            }
         }
     }
-    for (from, via, to) in complicated_restrictions {
+    for (from, ctr) in complicated_restrictions {
         streets
             .roads
             .get_mut(&from)
             .unwrap()
             .complicated_turn_restrictions
-            .push((via, to));
+            .push(ctr);
     }
 
     timer.start("match traffic signals to intersections");
@@ -259,19 +316,41 @@ pub fn split_up_roads(
             if let Some(road) = streets.roads.get(r) {
                 // Example: https://www.openstreetmap.org/node/26734224
                 if road.highway_type != "construction" {
-                    let i = if dir == Direction::Fwd {
-                        road.dst_i
-                    } else {
-                        road.src_i
-                    };
-                    streets.intersections.get_mut(&i).unwrap().control =
-                        IntersectionControl::Signalled;
+                    match_traffic_signal(streets, *r, pt.to_pt2d(), dir);
                 }
             }
         }
     }
     timer.stop("match traffic signals to intersections");
 
+    timer.start("match features to roads");
+    for (osm_node, pt, kind, direction) in input.road_features {
+        if let Some(r) = pt_to_road.get(&pt.to_hashable()) {
+            if let Some(road) = streets.roads.get_mut(r) {
+                road.add_feature(kind, osm_node, pt, direction);
+            }
+        } else if kind.is_traffic_sign() {
+            // Signs mapped on an intersection node don't belong to any one road. Put them on the
+            // end of every road there, and let Transformation::InferIntersectionControl decide
+            // which ones they apply to. The direction is relative to one unknown way, so ignore it.
+            if let Some(i) = pt_to_intersection
+                .get(&pt.to_hashable())
+                .and_then(|osm_id| osm_id_to_id.get(osm_id))
+            {
+                for r in streets.intersections[i].roads.clone() {
+                    streets.roads.get_mut(&r).unwrap().add_feature(
+                        kind.clone(),
+                        osm_node,
+                        pt,
+                        None,
+                    );
+                }
+            }
+        }
+        // Other features exactly at intersections are skipped
+    }
+    timer.stop("match features to roads");
+
     timer.start("calculate intersection movements");
     let intersection_ids = osm_id_to_id.values();
     for &i in intersection_ids {
@@ -284,6 +363,99 @@ pub fn split_up_roads(
     pt_to_road
 }
 
+fn dist_along(road: &Road, pt: Pt2D) -> Distance {
+    road.reference_line
+        .dist_along_of_point(road.reference_line.project_pt(pt))
+        .map(|(dist, _)| dist)
+        .unwrap_or(Distance::ZERO)
+}
+
+/// Marks the intersection controlled by a traffic signal mapped along a road. The signal's
+/// direction picks the end of the road it faces.
+fn match_traffic_signal(streets: &mut StreetNetwork, r: RoadID, pt: Pt2D, dir: Option<Direction>) {
+    let road = &streets.roads[&r];
+    let (dist_to_end, i) = match dir {
+        Some(Direction::Fwd) => (
+            road.reference_line.length() - dist_along(road, pt),
+            road.dst_i,
+        ),
+        Some(Direction::Back) => (dist_along(road, pt), road.src_i),
+        // Without a direction, assume the signal controls the closer end
+        Some(Direction::Both) | None => {
+            let dist = dist_along(road, pt);
+            let remaining = road.reference_line.length() - dist;
+            if dist < remaining {
+                (dist, road.src_i)
+            } else {
+                (remaining, road.dst_i)
+            }
+        }
+    };
+    let (approach, i) = find_signalized_approach(streets, r, i, dist_to_end);
+    let intersection = streets.intersections.get_mut(&i).unwrap();
+    intersection.control = IntersectionControl::Signalled;
+    intersection.signalized_approaches.insert(approach);
+}
+
+/// Traffic signals are often mapped on the incoming ways a few meters before the junction they
+/// control. When the way is split between the signal and the junction (because some attribute
+/// changes), follow the road through those simple connections to find the real junction. Returns
+/// the road approaching the signalized intersection and that intersection. If no junction is
+/// found nearby, the signal is assumed to control the intersection it's closest to.
+fn find_signalized_approach(
+    streets: &StreetNetwork,
+    start_road: RoadID,
+    start_i: IntersectionID,
+    dist_to_end: Distance,
+) -> (RoadID, IntersectionID) {
+    let max_dist = Distance::meters(50.0);
+
+    let mut r = start_road;
+    let mut i = start_i;
+    let mut dist = dist_to_end;
+    loop {
+        let roads = &streets.intersections[&i].roads;
+        if roads.len() != 2 {
+            return (r, i);
+        }
+        let next = if roads[0] == r { roads[1] } else { roads[0] };
+        let next_road = &streets.roads[&next];
+        dist += next_road.reference_line.length();
+        // Also stops when we loop around
+        if dist > max_dist || next == start_road {
+            return (start_road, start_i);
+        }
+        i = next_road.other_side(i);
+        r = next;
+    }
+}
+
+/// Splits a piece of a way that starts and ends at the same intersection in half, with a new
+/// intersection in the middle. The pieces are (src_i, dst_i, center line, way fractions).
+fn split_loop(
+    streets: &mut StreetNetwork,
+    piece: (IntersectionID, IntersectionID, PolyLine, (f64, f64)),
+) -> Vec<(IntersectionID, IntersectionID, PolyLine, (f64, f64))> {
+    let (i, _, pl, (from, to)) = piece;
+    let half = pl.length() / 2.0;
+    let new_i = streets.insert_intersection(
+        Vec::new(),
+        pl.must_dist_along(half).0,
+        IntersectionKind::Connection,
+        IntersectionControl::Uncontrolled,
+    );
+    let middle = (from + to) / 2.0;
+    vec![
+        (
+            i,
+            new_i,
+            pl.exact_slice(Distance::ZERO, half),
+            (from, middle),
+        ),
+        (new_i, i, pl.exact_slice(half, pl.length()), (middle, to)),
+    ]
+}
+
 // TODO Consider doing this in PolyLine::new always. Also in extend() -- it attempts to dedupe
 // angles.
 fn simplify_linestring(pts: Vec<Pt2D>) -> Vec<Pt2D> {
@@ -310,3 +482,235 @@ fn should_collapse_roundabout(pts: &[Pt2D], tags: &Tags) -> bool {
         && pts[0] == *pts.last().unwrap()
         && PolyLine::unchecked_new(pts.to_vec()).length() < Distance::meters(50.0)
 }
+
+/// Finds a sequence of connected roads that starts on the `from` way, passes through each of the
+/// `via` ways in order, and ends on the `to` way. Returns (from road, via roads, to road).
+fn resolve_complicated_restriction(
+    streets: &StreetNetwork,
+    from_osm: osm::WayID,
+    via_osm: &[osm::WayID],
+    to_osm: osm::WayID,
+) -> Option<(RoadID, Vec<RoadID>, RoadID)> {
+    for from in streets.roads.values() {
+        if from.osm_ids[0].osm_way_id != from_osm {
+            continue;
+        }
+        for exit in from.endpoints() {
+            let mut path = vec![from.id];
+            if search_via_roads(streets, via_osm, to_osm, exit, 0, &mut path) {
+                let to = path.pop().unwrap();
+                return Some((path[0], path[1..].to_vec(), to));
+            }
+        }
+    }
+    None
+}
+
+/// Depth-first search for the rest of a complicated restriction. The last road in `path` leaves
+/// through intersection `i`. `stage` is the index of the first via way not entered yet. On success,
+/// `path` is filled out.
+fn search_via_roads(
+    streets: &StreetNetwork,
+    via_osm: &[osm::WayID],
+    to_osm: osm::WayID,
+    i: IntersectionID,
+    stage: usize,
+    path: &mut Vec<RoadID>,
+) -> bool {
+    // Via ways are usually short, so this is just a safety limit.
+    if path.len() > 20 {
+        return false;
+    }
+    for next in streets.roads_per_intersection(i) {
+        if path.contains(&next.id) {
+            continue;
+        }
+        let way = next.osm_ids[0].osm_way_id;
+        if stage == via_osm.len() && way == to_osm {
+            path.push(next.id);
+            return true;
+        }
+        let next_stage = if stage > 0 && way == via_osm[stage - 1] {
+            // Continue along the same via way
+            stage
+        } else if stage < via_osm.len() && way == via_osm[stage] {
+            stage + 1
+        } else {
+            continue;
+        };
+
+        path.push(next.id);
+        let exit = if next.src_i == i {
+            next.dst_i
+        } else {
+            next.src_i
+        };
+        if search_via_roads(streets, via_osm, to_osm, exit, next_stage, path) {
+            return true;
+        }
+        path.pop();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_complicated_restriction() {
+        // Way 2 is split into two roads. Way 4 touches the network twice: once right after the
+        // first half of way 2, and once at the end of way 3.
+        let mut streets = StreetNetwork::blank();
+        let i: Vec<IntersectionID> = [
+            (0.0, 0.0),
+            (100.0, 0.0),
+            (200.0, 0.0),
+            (300.0, 0.0),
+            (300.0, -100.0),
+            (400.0, -100.0),
+            (200.0, 100.0),
+            (300.0, 100.0),
+        ]
+        .into_iter()
+        .map(|(x, y)| streets.add_test_intersection(x, y))
+        .collect();
+        for (way, i1, i2) in [
+            (1, 0, 1),
+            (2, 1, 2),
+            (2, 2, 3),
+            (3, 3, 4),
+            (4, 4, 5),
+            (4, 2, 6),
+            (5, 3, 7),
+        ] {
+            streets.add_test_road_for_way(way, i[i1], i[i2], "dd", "v^");
+        }
+        let r = |idx: usize| RoadID(idx);
+        let w = osm::WayID;
+
+        let mut ok = true;
+        for (description, from, via, to, expected) in vec![
+            (
+                "one via way split into two roads",
+                1,
+                vec![2],
+                5,
+                Some(((r(0), vec![r(1), r(2)]), r(6))),
+            ),
+            (
+                "two via ways, skipping the to way before all vias are entered",
+                1,
+                vec![2, 3],
+                4,
+                Some(((r(0), vec![r(1), r(2), r(3)]), r(4))),
+            ),
+            (
+                "the to way right after part of the via way",
+                1,
+                vec![2],
+                4,
+                Some(((r(0), vec![r(1)]), r(5))),
+            ),
+            (
+                "reversed",
+                4,
+                vec![3, 2],
+                1,
+                Some(((r(4), vec![r(3), r(2), r(1)]), r(0))),
+            ),
+            ("via way not connected to the from way", 1, vec![3], 4, None),
+            ("via ways out of order", 1, vec![3, 2], 4, None),
+        ] {
+            let via: Vec<osm::WayID> = via.into_iter().map(w).collect();
+            let actual = resolve_complicated_restriction(&streets, w(from), &via, w(to))
+                .map(|(from, via, to)| ((from, via), to));
+            if actual != expected {
+                ok = false;
+                println!("For {description}");
+                println!("Got:      {actual:?}");
+                println!("Expected: {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+    }
+
+    #[test]
+    fn test_match_traffic_signal() {
+        // A road leading through a simple connection to a four-way junction
+        let mut streets = StreetNetwork::blank();
+        let dead_end = streets.add_test_intersection(-100.0, 0.0);
+        let connection = streets.add_test_intersection(0.0, 0.0);
+        let junction = streets.add_test_intersection(20.0, 0.0);
+        streets.add_test_road(dead_end, connection, "dd", "v^");
+        streets.add_test_road(connection, junction, "dd", "v^");
+        for (x, y) in [(20.0, -100.0), (120.0, 0.0), (20.0, 100.0)] {
+            let i = streets.add_test_intersection(x, y);
+            streets.add_test_road(junction, i, "dd", "v^");
+        }
+        let r = |idx: usize| RoadID(idx);
+        let i = |idx: usize| IntersectionID(idx);
+
+        let mut ok = true;
+        for (description, pt, dir, expected_approach, expected_i) in vec![
+            (
+                "through the simple connection to the junction",
+                (-10.0, 0.0),
+                Some(Direction::Fwd),
+                r(1),
+                i(2),
+            ),
+            (
+                "no direction, so the closer end",
+                (-10.0, 0.0),
+                None,
+                r(1),
+                i(2),
+            ),
+            (
+                "facing backwards to a dead-end",
+                (-10.0, 0.0),
+                Some(Direction::Back),
+                r(0),
+                i(0),
+            ),
+            (
+                "too far from the junction, so the simple connection",
+                (-80.0, 0.0),
+                Some(Direction::Fwd),
+                r(0),
+                i(1),
+            ),
+        ] {
+            let mut copy = streets.clone();
+            match_traffic_signal(&mut copy, r(0), Pt2D::new(pt.0, pt.1), dir);
+            let signalled: Vec<(IntersectionID, Vec<RoadID>)> = copy
+                .intersections
+                .values()
+                .filter(|x| x.control == IntersectionControl::Signalled)
+                .map(|x| (x.id, x.signalized_approaches.iter().cloned().collect()))
+                .collect();
+            let expected = vec![(expected_i, vec![expected_approach])];
+            if signalled != expected {
+                ok = false;
+                println!("For {description}");
+                println!("Got:      {signalled:?}");
+                println!("Expected: {expected:?}");
+                println!();
+            }
+        }
+        assert!(ok);
+
+        // Walking through simple connections stops after 50m in total
+        for (dist_to_end, expected) in [
+            (Distance::meters(30.0), (r(1), i(2))),
+            (Distance::meters(31.0), (r(0), i(1))),
+        ] {
+            assert_eq!(
+                find_signalized_approach(&streets, r(0), i(1), dist_to_end),
+                expected
+            );
+        }
+    }
+}
@@ -3,7 +3,8 @@ mod tests {
     use abstutil::Timer;
     use anyhow::{bail, Result};
     use experimental::RoadNetwork;
-    use osm2streets::{MapConfig, Transformation};
+    use geom::Distance;
+    use osm2streets::{MapConfig, NetworkDiff, StreetNetwork, Transformation};
 
     include!(concat!(env!("OUT_DIR"), "/tests.rs"));
 
@@ -19,29 +20,47 @@ mod tests {
             .unwrap_or_else(|_| String::new());
         let prior_dot = std::fs::read_to_string(format!("{path}/road_network.dot"))
             .unwrap_or_else(|_| String::new());
+        // The full network from the previous run isn't committed, but it's kept locally to
+        // describe what changed
+        let prior_network: Option<StreetNetwork> =
+            std::fs::read_to_string(format!("{path}/street_network.json"))
+                .ok()
+                .and_then(|x| serde_json::from_str(&x).ok());
 
         let clip_pts = None;
+        let mut cfg = MapConfig::default();
+        cfg.check_invariants = true;
         let (mut street_network, _) = streets_reader::osm_to_street_network(
             &std::fs::read_to_string(format!("{path}/input.osm"))?,
             clip_pts,
-            MapConfig::default(),
+            cfg,
+            &mut timer,
+        )?;
+        street_network.apply_transformations_checked(
+            Transformation::standard_for_clipped_areas(),
             &mut timer,
         )?;
-        street_network
-            .apply_transformations(Transformation::standard_for_clipped_areas(), &mut timer);
         street_network.save_to_geojson(format!("{path}/geometry.json"))?;
+        std::fs::write(
+            format!("{path}/street_network.json"),
+            serde_json::to_string(&street_network)?,
+        )?;
 
-        let road_network: RoadNetwork = street_network.into();
+        let road_network: RoadNetwork = street_network.clone().into();
         std::fs::write(format!("{path}/road_network.dot"), road_network.to_dot())?;
 
         let current_dot = std::fs::read_to_string(format!("{path}/road_network.dot"))?;
+        let current_json = std::fs::read_to_string(format!("{path}/geometry.json"))?;
+        if prior_dot != current_dot || prior_json != current_json {
+            describe_changes(path, prior_network.as_ref(), &street_network)?;
+        }
+
         if prior_dot != current_dot {
             std::fs::write(format!("{path}/road_network.orig.dot"), prior_dot)?;
             bail!("./{}/road_network.dot is different! If it is OK, commit it.
 ./{0}/road_network.orig.dot is previous result. Compare it on https://doctorbud.com/graphviz-viewer/", path);
         }
 
-        let current_json = std::fs::read_to_string(format!("{path}/geometry.json"))?;
         if prior_json != current_json {
             std::fs::write(format!("{path}/geometry.orig.json"), prior_json)?;
             bail!(
@@ -52,4 +71,32 @@ mod tests {
         }
         Ok(())
     }
+
+    /// Prints the semantic differences from the previous run's network, and saves them as
+    /// `diff.geojson` to view in `street-explorer`.
+    fn describe_changes(
+        path: &str,
+        prior: Option<&StreetNetwork>,
+        current: &StreetNetwork,
+    ) -> Result<()> {
+        let prior = match prior {
+            Some(prior) => prior,
+            None => {
+                println!("./{path}/street_network.json is missing, so changes can't be described");
+                return Ok(());
+            }
+        };
+        let diff = NetworkDiff::new(prior, current, Distance::meters(0.1));
+        if diff.is_empty() {
+            println!("./{path} only has small geometry changes since the previous run");
+            return Ok(());
+        }
+        println!("./{path} changed since the previous run:\n{diff}");
+        std::fs::write(
+            format!("{path}/diff.geojson"),
+            diff.to_geojson(prior, current)?,
+        )?;
+        println!("./{path}/diff.geojson shows the changes");
+        Ok(())
+    }
 }